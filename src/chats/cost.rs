@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::path::Path;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::helpers;
+use crate::openaiapi::{Chat, ChatKey, Usage};
+use crate::openaiapi::store::read_chat_file;
+use super::chat_file;
+
+/// US dollars per 1000 tokens
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Price {
+	pub prompt: f64,
+	pub completion: f64,
+}
+
+/// Prices of some OpenAI models, `prices` in config.toml adds to and replaces these
+pub const DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+	("gpt-3.5-turbo", 0.0005, 0.0015),
+	("gpt-4", 0.03, 0.06),
+	("gpt-4-turbo", 0.01, 0.03),
+	("gpt-4o", 0.0025, 0.01),
+	("gpt-4o-mini", 0.00015, 0.0006),
+	("gpt-4.1", 0.002, 0.008),
+	("gpt-4.1-mini", 0.0004, 0.0016),
+	("o1", 0.015, 0.06),
+	("o3-mini", 0.0011, 0.0044),
+];
+
+pub struct Pricing {
+	prices: HashMap<String, Price>,
+}
+
+impl Pricing {
+	/// The default prices with `configured` added
+	pub fn new(configured: &HashMap<String, Price>) -> Self {
+		let mut prices: HashMap<String, Price> = DEFAULT_PRICES.iter()
+			.map(|(model, prompt, completion)| (model.to_string(), Price{ prompt: *prompt, completion: *completion }))
+			.collect();
+		prices.extend(configured.iter().map(|(model, price)| (model.clone(), *price)));
+		Pricing{ prices: prices }
+	}
+
+	/// The price of `model`, or of the longest model name it starts with so
+	/// that dated versions (`gpt-4o-2024-08-06`) use the price of the model
+	pub fn price(&self, model: &str) -> Option<Price> {
+		self.prices.iter()
+			.filter(|(name, _)| model.starts_with(name.as_str()))
+			.max_by_key(|(name, _)| name.len())
+			.map(|(_, price)| *price)
+	}
+
+	pub fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+		self.price(model).map(|price| (usage.prompt_tokens as f64 * price.prompt + usage.completion_tokens as f64 * price.completion) / 1000.0)
+	}
+}
+
+/// The tokens used by the responses in a chat and what they cost
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CostSummary {
+	pub prompt_tokens: u64,
+	pub completion_tokens: u64,
+	pub cost: f64,
+	/// responses from models without a price, not included in `cost`
+	pub unpriced: usize,
+}
+
+/// The cost of the responses in `chat`, only those created in `period`
+/// (`from..until` in seconds since the epoch) when it is given
+pub fn chat_cost(chat: &Chat, pricing: &Pricing, period: Option<(u64, u64)>) -> CostSummary {
+	let mut summary = CostSummary::default();
+	for message in chat.messages.iter() {
+		let usage = match message.usage.as_ref() {
+			Some(usage) => usage,
+			None => continue,
+		};
+		if let Some((from, until)) = period {
+			match message.created_at {
+				Some(created_at) if created_at >= from && created_at < until => {},
+				_ => continue,
+			}
+		}
+		summary.prompt_tokens += usage.prompt_tokens;
+		summary.completion_tokens += usage.completion_tokens;
+		match pricing.cost(message.model.as_deref().unwrap_or(&chat.model), usage) {
+			Some(cost) => summary.cost += cost,
+			None => summary.unpriced += 1,
+		}
+	}
+	summary
+}
+
+/// The start of a `YYYY-MM` month and of the month after it
+pub fn month_range(month: &str) -> Option<(u64, u64)> {
+	let (year, number) = month.trim().split_once('-')?;
+	let (year, number) = (year.parse::<u32>().ok()?, number.parse::<u32>().ok()?);
+	let from = helpers::parse_date(&format!("{}-{:02}-01", year, number))?;
+	let (next_year, next) = if number == 12 { (year + 1, 1) } else { (year, number + 1) };
+	let until = helpers::parse_date(&format!("{}-{:02}-01", next_year, next))?;
+	Some((from, until))
+}
+
+/// The cost of every chat in `chats_dir` that had responses in `period`
+pub fn costs(chats_dir: &Path, pricing: &Pricing, period: (u64, u64), key: Option<&ChatKey>) -> Result<Vec<(String, CostSummary)>, helpers::HelperError> {
+	let mut costs = Vec::new();
+	for summary in super::list(chats_dir, key)? {
+		// chats modified before the period cannot have responses in it
+		if summary.modified < period.0 {
+			continue;
+		}
+		let chat = match read_chat_file(&chat_file(chats_dir, &summary.chat_id), key) {
+			Ok(chat) => chat,
+			Err(_) => continue,
+		};
+		let cost = chat_cost(&chat, pricing, Some(period));
+		if cost.prompt_tokens + cost.completion_tokens > 0 {
+			costs.push((summary.chat_id, cost));
+		}
+	}
+	Ok(costs)
+}
+
+/// Limits on what may be spent, in config.toml:
+///
+/// ```toml
+/// [budget]
+/// chat_cost = 2.0
+/// daily_cost = 10.0
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Budget {
+	/// dollars for all the responses in a chat
+	pub chat_cost: Option<f64>,
+	/// prompt and completion tokens for all the responses in a chat
+	pub chat_tokens: Option<u64>,
+	/// dollars for the responses in all the chats today (UTC)
+	pub daily_cost: Option<f64>,
+	pub daily_tokens: Option<u64>,
+}
+
+/// The start of today and of tomorrow (UTC)
+pub fn today(now: u64) -> (u64, u64) {
+	let from = now - now % 86400;
+	(from, from + 86400)
+}
+
+/// Checks a chat against a budget before it is sent
+pub struct BudgetCheck {
+	pub budget: Budget,
+	pub pricing: Pricing,
+	pub today: (u64, u64),
+	/// what the other chats have used today
+	pub others_today: CostSummary,
+}
+
+fn exceeded(what: &str, spent: String, limit: String) -> String {
+	format!("The {} budget of {} is used up ({} spent), use --force to send anyway", what, limit, spent)
+}
+
+impl BudgetCheck {
+	pub fn check(&self, chat: &Chat) -> Result<(), String> {
+		let total = chat_cost(chat, &self.pricing, None);
+		let today = chat_cost(chat, &self.pricing, Some(self.today));
+		let daily_cost = today.cost + self.others_today.cost;
+		let daily_tokens = today.prompt_tokens + today.completion_tokens + self.others_today.prompt_tokens + self.others_today.completion_tokens;
+		if let Some(limit) = self.budget.chat_cost.filter(|limit| total.cost >= *limit) {
+			return Err(exceeded("chat", format!("${:.4}", total.cost), format!("${:.4}", limit)));
+		}
+		if let Some(limit) = self.budget.chat_tokens.filter(|limit| total.prompt_tokens + total.completion_tokens >= *limit) {
+			return Err(exceeded("chat", format!("{} tokens", total.prompt_tokens + total.completion_tokens), format!("{} tokens", limit)));
+		}
+		if let Some(limit) = self.budget.daily_cost.filter(|limit| daily_cost >= *limit) {
+			return Err(exceeded("daily", format!("${:.4}", daily_cost), format!("${:.4}", limit)));
+		}
+		if let Some(limit) = self.budget.daily_tokens.filter(|limit| daily_tokens >= *limit) {
+			return Err(exceeded("daily", format!("{} tokens", daily_tokens), format!("{} tokens", limit)));
+		}
+		Ok(())
+	}
+}
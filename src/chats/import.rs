@@ -0,0 +1,106 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use serde_json::Value;
+
+use crate::helpers;
+use crate::openaiapi::{Chat, ChatKey, Message};
+use crate::openaiapi::store::write_chat_file;
+use super::unused;
+
+/// The conversation files that can be imported
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ImportFormat {
+	/// conversations.json from a ChatGPT data export
+	Chatgpt,
+	/// one {"role": ..., "content": ...} object per line
+	Jsonl,
+}
+
+fn invalid(message: String) -> helpers::HelperError {
+	helpers::HelperError::Io(Error::new(ErrorKind::InvalidData, message))
+}
+
+/// The messages of a ChatGPT conversation, following the parents from the
+/// current node (the branch shown in ChatGPT) back to the start. Parts that
+/// are not text (images...) are left out.
+pub fn chatgpt_messages(conversation: &Value) -> Result<Vec<Message>, helpers::HelperError> {
+	let mapping = conversation["mapping"].as_object().ok_or(invalid("conversation without a mapping".to_string()))?;
+	let mut node_id = conversation["current_node"].as_str()
+		// without a current node the last node without children is used
+		.or(mapping.iter().filter(|(_, node)| node["children"].as_array().map(|c| c.is_empty()).unwrap_or(true)).map(|(id, _)| id.as_str()).last())
+		.map(|id| id.to_string());
+	let mut messages = Vec::new();
+	let mut visited = 0;
+	while let Some(id) = node_id {
+		visited += 1;
+		if visited > mapping.len() {
+			return Err(invalid("the conversation nodes form a loop".to_string()));
+		}
+		let node = mapping.get(&id).ok_or(invalid(format!("node {} not found", id)))?;
+		let message = &node["message"];
+		if let Some(role) = message["author"]["role"].as_str() {
+			let text: Vec<&str> = message["content"]["parts"].as_array().into_iter().flatten().filter_map(|part| part.as_str()).collect();
+			let text = text.join("\n");
+			if !text.trim().is_empty() {
+				messages.push(Message::normal(role.to_string(), text));
+			}
+		}
+		node_id = node["parent"].as_str().map(|parent| parent.to_string());
+	}
+	messages.reverse();
+	Ok(messages)
+}
+
+pub fn jsonl_messages(content: &str) -> Result<Vec<Message>, helpers::HelperError> {
+	let mut messages = Vec::new();
+	for (index, line) in content.lines().enumerate() {
+		if line.trim().is_empty() {
+			continue;
+		}
+		let value: Value = serde_json::from_str(line)?;
+		let role = value["role"].as_str().ok_or(invalid(format!("line {}: no role", index + 1)))?;
+		let content = value["content"].as_str().ok_or(invalid(format!("line {}: no content", index + 1)))?;
+		messages.push(Message::normal(role.to_string(), content.to_string()));
+	}
+	Ok(messages)
+}
+
+/// Write the conversations in `file` to `chats_dir` as copies of `template`
+/// with its messages replaced. A single conversation is stored as `chat_id`,
+/// several as `chat_id-1`, `chat_id-2`... (without a chat id the ChatGPT
+/// conversation ids or the file name are used). Returns the new chat ids.
+pub fn import(chats_dir: &Path, template: &Chat, file: &Path, format: ImportFormat, chat_id: Option<&str>, key: Option<&ChatKey>) -> Result<Vec<String>, helpers::HelperError> {
+	let content = std::fs::read_to_string(file)?;
+	let stem = file.file_stem().unwrap_or_default().to_string_lossy().to_string();
+	let conversations: Vec<(String, Vec<Message>)> = match format {
+		ImportFormat::Jsonl => vec![(stem, jsonl_messages(&content)?)],
+		ImportFormat::Chatgpt => {
+			let value: Value = serde_json::from_str(&content)?;
+			// the export is a list of conversations, a single one is accepted too
+			let list = match value {
+				Value::Array(list) => list,
+				conversation => vec![conversation],
+			};
+			let mut conversations = Vec::new();
+			for (index, conversation) in list.iter().enumerate() {
+				let id = conversation["id"].as_str().or(conversation["conversation_id"].as_str()).map(|id| id.to_string()).unwrap_or(format!("{}-{}", stem, index + 1));
+				conversations.push((id, chatgpt_messages(conversation)?));
+			}
+			conversations
+		},
+	};
+	let count = conversations.len();
+	let mut chat_ids = Vec::new();
+	for (index, (id, messages)) in conversations.into_iter().enumerate() {
+		let id = match chat_id {
+			Some(chat_id) if count == 1 => chat_id.to_string(),
+			Some(chat_id) => format!("{}-{}", chat_id, index + 1),
+			None => id,
+		};
+		let mut chat = template.clone();
+		chat.messages = messages;
+		write_chat_file(&unused(chats_dir, &id)?, &chat, key)?;
+		chat_ids.push(id);
+	}
+	Ok(chat_ids)
+}
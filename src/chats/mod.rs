@@ -0,0 +1,299 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::helpers;
+use crate::openaiapi::{Chat, ChatKey, ImageUrl, Message};
+use crate::openaiapi::store::{read_chat_file, write_chat_file};
+
+pub mod cost;
+pub mod import;
+
+/// A stored chat as shown by `chats list`
+#[derive(Debug)]
+pub struct ChatSummary {
+	pub chat_id: String,
+	/// None when the file cannot be read as a chat
+	pub messages: Option<usize>,
+	pub model: Option<String>,
+	/// seconds since the epoch
+	pub modified: u64,
+}
+
+fn chat_file(chats_dir: &Path, chat_id: &str) -> PathBuf {
+	chats_dir.join(chat_id.to_string() + ".json")
+}
+
+/// Every chat in `chats_dir`, the most recently modified first (`key`
+/// decrypts the encrypted chats)
+pub fn list(chats_dir: &Path, key: Option<&ChatKey>) -> Result<Vec<ChatSummary>, helpers::HelperError> {
+	let files = helpers::list_files(chats_dir, |path| if helpers::has_specific_extension(&path, "json") { Some(path) } else { None }, 0)?;
+	let mut chats: Vec<ChatSummary> = files.iter().map(|file| {
+		let chat = read_chat_file(file, key).ok();
+		let modified = fs::metadata(file).and_then(|m| m.modified()).ok()
+			.and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		ChatSummary{
+			chat_id: file.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+			messages: chat.as_ref().map(|chat| chat.messages.len()),
+			model: chat.map(|chat| chat.model),
+			modified: modified,
+		}
+	}).collect();
+	chats.sort_by(|a, b| b.modified.cmp(&a.modified));
+	Ok(chats)
+}
+
+fn existing(chats_dir: &Path, chat_id: &str) -> Result<PathBuf, helpers::HelperError> {
+	let file = chat_file(chats_dir, chat_id);
+	if !file.is_file() {
+		return Err(helpers::HelperError::Io(Error::new(ErrorKind::NotFound, format!("Chat {} not found", chat_id))));
+	}
+	Ok(file)
+}
+
+fn unused(chats_dir: &Path, chat_id: &str) -> Result<PathBuf, helpers::HelperError> {
+	let file = chat_file(chats_dir, chat_id);
+	if file.exists() {
+		return Err(helpers::HelperError::Io(Error::new(ErrorKind::AlreadyExists, format!("Chat {} already exists", chat_id))));
+	}
+	Ok(file)
+}
+
+pub fn delete(chats_dir: &Path, chat_id: &str) -> Result<(), helpers::HelperError> {
+	fs::remove_file(existing(chats_dir, chat_id)?)?;
+	Ok(())
+}
+
+pub fn rename(chats_dir: &Path, chat_id: &str, new_chat_id: &str) -> Result<(), helpers::HelperError> {
+	let from = existing(chats_dir, chat_id)?;
+	fs::rename(from, unused(chats_dir, new_chat_id)?)?;
+	Ok(())
+}
+
+pub fn copy(chats_dir: &Path, chat_id: &str, new_chat_id: &str) -> Result<(), helpers::HelperError> {
+	let from = existing(chats_dir, chat_id)?;
+	let content = fs::read(from)?;
+	helpers::write_atomic(unused(chats_dir, new_chat_id)?, content)?;
+	Ok(())
+}
+
+/// Start `new_chat_id` as a copy of another chat, `spec` is `CHAT_ID` or
+/// `CHAT_ID:N` to keep only the first N messages.
+pub fn fork(chats_dir: &Path, spec: &str, new_chat_id: &str, key: Option<&ChatKey>) -> Result<(), helpers::HelperError> {
+	let (chat_id, keep) = match spec.rsplit_once(':') {
+		Some((chat_id, count)) => {
+			let count = count.parse::<usize>().map_err(|_| Error::new(ErrorKind::InvalidInput, format!("Invalid message count in {}", spec)))?;
+			(chat_id, Some(count))
+		},
+		None => (spec, None),
+	};
+	let mut chat = read_chat_file(&existing(chats_dir, chat_id)?, key)?;
+	if let Some(keep) = keep {
+		if keep > chat.messages.len() {
+			return Err(helpers::HelperError::Io(Error::new(ErrorKind::InvalidInput, format!("Chat {} has only {} messages", chat_id, chat.messages.len()))));
+		}
+		chat.messages.truncate(keep);
+	}
+	write_chat_file(&unused(chats_dir, new_chat_id)?, &chat, key)
+}
+
+/// Characters shown before and after a match found by `search`
+pub const SNIPPET_CONTEXT: usize = 40;
+
+/// A message containing the text searched for
+#[derive(Debug)]
+pub struct SearchMatch {
+	pub chat_id: String,
+	/// the index of the message in `messages`
+	pub index: usize,
+	pub role: String,
+	pub before: String,
+	pub matched: String,
+	pub after: String,
+}
+
+/// What `search` looks for, the dates are seconds since the epoch
+#[derive(Debug, Default)]
+pub struct SearchFilter {
+	pub role: Option<String>,
+	/// only chats modified at or after this time
+	pub since: Option<u64>,
+	/// only chats modified before this time
+	pub until: Option<u64>,
+}
+
+/// Up to `count` characters at the end of `text` (or at the start when
+/// `from_end` is false) on one line
+fn snippet(text: &str, count: usize, from_end: bool) -> String {
+	let chars: Vec<char> = text.chars().collect();
+	let chars = if from_end { &chars[chars.len().saturating_sub(count)..] } else { &chars[..count.min(chars.len())] };
+	chars.iter().map(|c| if c.is_whitespace() { ' ' } else { *c }).collect()
+}
+
+/// The first match of `query` (ignoring case) in every message of the
+/// chats in `chats_dir`, the most recently modified chats first.
+pub fn search(chats_dir: &Path, query: &str, filter: &SearchFilter, key: Option<&ChatKey>) -> Result<Vec<SearchMatch>, helpers::HelperError> {
+	let regex = regex::RegexBuilder::new(&regex::escape(query)).case_insensitive(true).build()
+		.map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+	let mut matches = Vec::new();
+	for summary in list(chats_dir, key)? {
+		if filter.since.map(|since| summary.modified < since).unwrap_or(false) || filter.until.map(|until| summary.modified >= until).unwrap_or(false) {
+			continue;
+		}
+		let chat = match read_chat_file(&chat_file(chats_dir, &summary.chat_id), key) {
+			Ok(chat) => chat,
+			Err(_) => continue,
+		};
+		for (index, message) in chat.messages.iter().enumerate() {
+			if filter.role.as_ref().map(|role| role != &message.role).unwrap_or(false) {
+				continue;
+			}
+			let content = match message.text() {
+				Some(content) => content,
+				None => continue,
+			};
+			if let Some(found) = regex.find(&content) {
+				matches.push(SearchMatch{
+					chat_id: summary.chat_id.clone(),
+					index: index,
+					role: message.role.clone(),
+					before: snippet(&content[..found.start()], SNIPPET_CONTEXT, true),
+					matched: found.as_str().to_string(),
+					after: snippet(&content[found.end()..], SNIPPET_CONTEXT, false),
+				});
+			}
+		}
+	}
+	Ok(matches)
+}
+
+/// How a chat is written by `--dump`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum DumpFormat {
+	/// the content of the messages and the tool calls
+	Text,
+	/// roles as headers and tool calls as fenced blocks
+	Markdown,
+	/// a standalone page
+	Html,
+	/// the chat as stored
+	Json,
+}
+
+fn escape_html(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// The role with the tool name for tool responses, e.g. `tool (execute)`
+fn heading(message: &Message) -> String {
+	match message.name.as_ref() {
+		Some(name) => format!("{} ({})", message.role, name),
+		None => message.role.clone(),
+	}
+}
+
+/// When the message was added, the model and the tokens used, e.g.
+/// `2024-05-01T10:00:00Z, gpt-4o, 120 + 30 = 150 tokens`
+fn metadata(message: &Message) -> Option<String> {
+	let mut parts = Vec::new();
+	if let Some(created_at) = message.created_at {
+		parts.push(helpers::format_utc(created_at));
+	}
+	if let Some(model) = message.model.as_ref() {
+		parts.push(model.clone());
+	}
+	if let Some(usage) = message.usage.as_ref() {
+		parts.push(format!("{} + {} = {} tokens", usage.prompt_tokens, usage.completion_tokens, usage.total_tokens));
+	}
+	if let Some(fingerprint) = message.system_fingerprint.as_ref() {
+		parts.push(fingerprint.clone());
+	}
+	if message.truncated.unwrap_or(false) {
+		parts.push("truncated".to_string());
+	}
+	if parts.is_empty() { None } else { Some(parts.join(", ")) }
+}
+
+fn images(message: &Message) -> Vec<&ImageUrl> {
+	message.content.as_ref().map(|content| content.images()).unwrap_or_default()
+}
+
+fn audio_formats(message: &Message) -> Vec<&str> {
+	message.content.as_ref().map(|content| content.audio().iter().map(|audio| audio.format.as_str()).collect()).unwrap_or_default()
+}
+
+/// The URL of an image, the data of an embedded image is left out
+fn image_reference(url: &str) -> &str {
+	if url.starts_with("data:") { "(embedded)" } else { url }
+}
+
+pub fn dump(chat: &Chat, format: DumpFormat) -> Result<String, serde_json::Error> {
+	let mut out = String::new();
+	match format {
+		DumpFormat::Json => out = serde_json::to_string_pretty(chat)?,
+		DumpFormat::Text => {
+			for message in chat.messages.iter() {
+				if let Some(content) = message.text() {
+					out.push_str(&format!("{}\n", content));
+				}
+				for image in images(message) {
+					out.push_str(&format!("[image {}]\n", image_reference(&image.url)));
+				}
+				for format in audio_formats(message) {
+					out.push_str(&format!("[audio {}]\n", format));
+				}
+				for tool_call in message.tool_calls.iter().flatten() {
+					out.push_str(&format!("```{}\n{}\n```\n", tool_call.function.name, tool_call.function.arguments));
+				}
+			}
+		},
+		DumpFormat::Markdown => {
+			for message in chat.messages.iter() {
+				out.push_str(&format!("## {}\n\n", heading(message)));
+				if let Some(metadata) = metadata(message) {
+					out.push_str(&format!("_{}_\n\n", metadata));
+				}
+				if let Some(content) = message.text().filter(|c| !c.is_empty()) {
+					out.push_str(&format!("{}\n\n", content));
+				}
+				for image in images(message) {
+					out.push_str(&format!("_[image {}]_\n\n", image_reference(&image.url)));
+				}
+				for format in audio_formats(message) {
+					out.push_str(&format!("_[audio {}]_\n\n", format));
+				}
+				for tool_call in message.tool_calls.iter().flatten() {
+					out.push_str(&format!("Tool call `{}` ({}):\n\n```json\n{}\n```\n\n", tool_call.function.name, tool_call.id, tool_call.function.arguments));
+				}
+			}
+		},
+		DumpFormat::Html => {
+			out.push_str(&format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n", escape_html(&chat.model)));
+			for message in chat.messages.iter() {
+				out.push_str(&format!("<h2>{}</h2>\n", escape_html(&heading(message))));
+				if let Some(metadata) = metadata(message) {
+					out.push_str(&format!("<p><small>{}</small></p>\n", escape_html(&metadata)));
+				}
+				if let Some(content) = message.text().filter(|c| !c.is_empty()) {
+					out.push_str(&format!("<pre>{}</pre>\n", escape_html(&content)));
+				}
+				for image in images(message) {
+					out.push_str(&format!("<p><img src=\"{}\" alt=\"image\"></p>\n", escape_html(&image.url)));
+				}
+				for audio in message.content.as_ref().map(|content| content.audio()).unwrap_or_default() {
+					let mime = if audio.format == "mp3" { "audio/mpeg" } else { "audio/wav" };
+					out.push_str(&format!("<p><audio controls src=\"data:{};base64,{}\"></audio></p>\n", mime, escape_html(&audio.data)));
+				}
+				for tool_call in message.tool_calls.iter().flatten() {
+					out.push_str(&format!("<p>Tool call <code>{}</code> ({}):</p>\n<pre><code>{}</code></pre>\n",
+						escape_html(&tool_call.function.name), escape_html(&tool_call.id), escape_html(&tool_call.function.arguments)));
+				}
+			}
+			out.push_str("</body>\n</html>\n");
+		},
+	}
+	Ok(out)
+}
@@ -0,0 +1,390 @@
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::chats::cost::{Budget, Price};
+use crate::helpers::{self, HelperError};
+use crate::openaiapi::{Backend, ChatKey, TlsFiles};
+use crate::tools::ToolPolicy;
+use crate::tools::sql::SqlDatabaseConfig;
+use crate::tools::web::{HttpRequestConfig, WebSearchConfig};
+
+pub const APP_NAME: &str = "openaiclient";
+/// The settings of a project, found in the current directory or above it
+pub const PROJECT_CONFIG: &str = ".openaiclient.toml";
+/// The instructions of a project added to the system message of new chats
+pub const INSTRUCTIONS_FILE: &str = "AGENTS.md";
+
+/// Contents of `~/.config/openaiclient/config.toml`, for example:
+///
+/// ```toml
+/// api_base = "https://example.openai.azure.com/openai/deployments/gpt4/"
+/// api_key = "..."
+/// api_version = "2024-02-01"
+/// model = "gpt-4"
+/// chats_dir = "/home/user/chats"
+/// ```
+///
+/// When `api_version` is present the endpoint is treated as Azure, `backend`
+/// may be set to `openai`, `azure` or `ollama` to choose explicitly.
+///
+/// Named profiles may be added and selected with `--profile`:
+///
+/// ```toml
+/// default_profile = "azure-prod"
+///
+/// [profiles.local-llama]
+/// backend = "ollama"
+/// api_base = "http://localhost:11434/"
+/// model = "llama3.1"
+/// temperature = 0.2
+/// ```
+///
+/// Prices (per 1000 tokens) are used by `--cost` and `costs`:
+///
+/// ```toml
+/// [prices."mistralai/mistral-large"]
+/// prompt = 0.002
+/// completion = 0.006
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Config {
+	#[serde(flatten)]
+	pub profile: Profile,
+	pub chats_dir: Option<PathBuf>,
+	pub config_dir: Option<PathBuf>,
+	pub default_profile: Option<String>,
+	#[serde(default)]
+	pub profiles: HashMap<String, Profile>,
+	#[serde(default)]
+	pub mcp_servers: HashMap<String, McpServerConfig>,
+	pub tool_policy: Option<ToolPolicy>,
+	/// file tools may only use files inside this directory
+	pub workspace_root: Option<PathBuf>,
+	/// shell used by the execute tool (sh, bash, cmd, powershell or pwsh)
+	pub shell: Option<String>,
+	/// 64 hexadecimal digits, when present the chats are saved encrypted
+	pub chat_key: Option<String>,
+	/// compact a chat before sending it when it has more tokens
+	pub compact_at: Option<usize>,
+	/// US dollars per 1000 prompt and completion tokens by model, added to
+	/// `chats::cost::DEFAULT_PRICES`
+	#[serde(default)]
+	pub prices: HashMap<String, Price>,
+	/// requests are refused once a chat or the day has used this much
+	pub budget: Option<Budget>,
+	/// the search engine used by the web_search tool
+	pub web_search: Option<WebSearchConfig>,
+	/// the addresses the http_request tool may send requests to
+	pub http_request: Option<HttpRequestConfig>,
+	/// the template of the system message of new chats, instead of
+	/// `SYSTEM_PROMPT.md` in the config directory
+	pub system_prompt: Option<PathBuf>,
+	/// the file of a project whose content is added to the system message of
+	/// new chats (`AGENTS.md` by default, `""` for none)
+	pub instructions_file: Option<String>,
+	/// the tool policy of the `.openaiclient.toml` of a project, applied as
+	/// well as `tool_policy` so a project can only deny more
+	#[serde(skip)]
+	pub project_tool_policy: Option<ToolPolicy>,
+	/// the directory of the project that gave `system_prompt`, the template
+	/// is then processed untrusted
+	#[serde(skip)]
+	pub system_prompt_project: Option<PathBuf>,
+	/// the SQLite databases the sql_query tool may read, by name
+	#[serde(default)]
+	pub sql_databases: HashMap<String, SqlDatabaseConfig>,
+}
+
+/// Contents of `.openaiclient.toml` in a project, merged over `config.toml`
+/// (relative paths are relative to the project):
+///
+/// ```toml
+/// profile = "local-llama"
+/// model = "qwen2.5-coder"
+/// chats_dir = ".openaiclient/chats"
+/// system_prompt = "docs/SYSTEM_PROMPT.md"
+///
+/// [tool_policy]
+/// denied = ["execute"]
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ProjectConfig {
+	/// the profile used unless `--profile` is given
+	pub profile: Option<String>,
+	/// replaces the model of every profile
+	pub model: Option<String>,
+	pub chats_dir: Option<PathBuf>,
+	pub tool_policy: Option<ToolPolicy>,
+	pub system_prompt: Option<PathBuf>,
+	pub instructions_file: Option<String>,
+}
+
+impl ProjectConfig {
+	/// The nearest `.openaiclient.toml` in `start` or a directory above it
+	pub fn find(start: &Path) -> Option<PathBuf> {
+		start.ancestors().map(|dir| dir.join(PROJECT_CONFIG)).find(|path| path.is_file())
+	}
+}
+
+/// The instructions file (e.g. `AGENTS.md`) of the project `start` is in,
+/// looked for in `start` and the directories above it up to the root of
+/// its git repository
+pub fn find_instructions(start: &Path, name: &str) -> Option<PathBuf> {
+	if name.is_empty() {
+		return None;
+	}
+	for dir in start.ancestors() {
+		let path = dir.join(name);
+		if path.is_file() {
+			return Some(path);
+		}
+		if dir.join(".git").exists() {
+			break;
+		}
+	}
+	None
+}
+
+/// A Model Context Protocol server started as a child process (stdio transport):
+///
+/// ```toml
+/// [mcp_servers.filesystem]
+/// command = "npx"
+/// args = ["-y", "@modelcontextprotocol/server-filesystem", "/home/user/src"]
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct McpServerConfig {
+	pub command: String,
+	#[serde(default)]
+	pub args: Vec<String>,
+	#[serde(default)]
+	pub env: HashMap<String, String>,
+}
+
+/// An endpoint with its own model and default sampling parameters
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Profile {
+	pub backend: Option<String>,
+	pub api_base: Option<String>,
+	pub api_key: Option<String>,
+	pub api_version: Option<String>,
+	pub model: Option<String>,
+	pub temperature: Option<f64>,
+	pub top_p: Option<f64>,
+	pub max_tokens: Option<u32>,
+	/// token id to bias (-100 to 100), added to the biases of the chat
+	pub logit_bias: Option<HashMap<String, i32>>,
+	/// merged into every request, e.g. `{ top_k = 40, min_p = 0.05 }`
+	pub extra_body: Option<serde_json::Value>,
+	/// sent with every request, e.g. `{ "X-Title" = "openaiclient" }`
+	#[serde(default)]
+	pub headers: HashMap<String, String>,
+	/// PEM root certificates to trust as well as the system ones
+	pub ca_cert: Option<PathBuf>,
+	/// PEM certificate and PKCS#8 key to authenticate with (mutual TLS)
+	pub client_cert: Option<PathBuf>,
+	pub client_key: Option<PathBuf>,
+	/// send the requests to this socket (e.g. a llama.cpp server), the host
+	/// of `api_base` is only used as the `Host` header
+	pub unix_socket: Option<PathBuf>,
+}
+
+/// Where requests are sent and how they are authenticated
+pub struct Endpoint {
+	pub url: String,
+	/// where the models served by the endpoint are listed
+	pub models_url: String,
+	/// the base the other APIs (audio, embeddings...) are found under
+	pub base: String,
+	pub api_version: Option<String>,
+	pub api_key: String,
+	pub backend: Backend,
+	pub headers: HashMap<String, String>,
+	pub tls: TlsFiles,
+	pub unix_socket: Option<PathBuf>,
+}
+
+impl Endpoint {
+	/// The url of another API of the endpoint, e.g. `audio/transcriptions`,
+	/// Ollama serves them under `v1/`
+	pub fn api_url(&self, path: &str) -> String {
+		match (&self.backend, self.api_version.as_ref()) {
+			(Backend::Ollama, _) => format!("{}v1/{}", self.base, path),
+			(_, Some(ver)) => format!("{}{}?api-version={}", self.base, path, ver),
+			(_, None) => format!("{}{}", self.base, path),
+		}
+	}
+}
+
+/// The models list of an endpoint: `<base>models` for OpenAI compatible
+/// endpoints, `/openai/models` of the resource for Azure (the base is a
+/// deployment) and `/api/tags` for Ollama.
+fn models_url(backend: &Backend, base: &str, api_version: Option<&str>) -> String {
+	match backend {
+		Backend::Ollama => format!("{}api/tags", base),
+		Backend::Azure => {
+			let resource = match base.find("/deployments/") {
+				Some(index) => &base[..index + 1],
+				None => base,
+			};
+			format!("{}models?api-version={}", resource, api_version.unwrap_or("2024-02-01"))
+		},
+		Backend::OpenAi => format!("{}models", base),
+	}
+}
+
+impl Config {
+	/// Load the configuration file, `None` is returned when it does not exist.
+	pub fn load() -> Result<Option<Config>, HelperError> {
+		match helpers::config_load_toml::<Config>(APP_NAME, "config") {
+			Ok(config) => Ok(Some(config)),
+			Err(HelperError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+			Err(err) => Err(err),
+		}
+	}
+
+	/// Replace the settings given by a project, `dir` is the directory of its
+	/// `.openaiclient.toml`. Any repository can have one, so its tool policy
+	/// is kept apart (to be applied after the user's) and its system prompt
+	/// is untrusted.
+	pub fn merge_project(&mut self, project: ProjectConfig, dir: &Path) {
+		if let Some(profile) = project.profile {
+			self.default_profile = Some(profile);
+		}
+		if let Some(model) = project.model {
+			for profile in self.profiles.values_mut().chain(std::iter::once(&mut self.profile)) {
+				profile.model = Some(model.clone());
+			}
+		}
+		if let Some(chats_dir) = project.chats_dir {
+			self.chats_dir = Some(dir.join(chats_dir));
+		}
+		if let Some(tool_policy) = project.tool_policy {
+			self.project_tool_policy = Some(tool_policy);
+		}
+		if let Some(system_prompt) = project.system_prompt {
+			self.system_prompt = Some(dir.join(system_prompt));
+			self.system_prompt_project = Some(dir.to_path_buf());
+		}
+		if let Some(instructions_file) = project.instructions_file {
+			self.instructions_file = Some(instructions_file);
+		}
+	}
+
+	/// The named profile, or the `default_profile`, or the top level settings
+	pub fn profile(&self, name: Option<&str>) -> Result<Profile, Box<dyn std::error::Error>> {
+		match name.or(self.default_profile.as_deref()) {
+			Some(name) => match self.profiles.get(name) {
+				Some(profile) => Ok(profile.clone()),
+				None => Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, format!("Profile not found: {}", name)))),
+			},
+			None => Ok(self.profile.clone()),
+		}
+	}
+}
+
+impl Profile {
+	pub fn endpoint(&self) -> Result<Endpoint, Box<dyn std::error::Error>> {
+		let base = self.api_base.as_ref().ok_or(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "api_base missing from configuration")))?;
+		let backend = match self.backend.as_deref() {
+			Some("azure") => Backend::Azure,
+			Some("openai") => Backend::OpenAi,
+			Some("ollama") => Backend::Ollama,
+			Some(other) => return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("Unknown backend: {}", other)))),
+			None if self.api_version.is_some() => Backend::Azure,
+			None => Backend::OpenAi,
+		};
+		let url = match (&backend, self.api_version.as_ref()) {
+			(Backend::Ollama, _) => format!("{}api/chat", base),
+			(_, Some(ver)) => format!("{}chat/completions?api-version={}", base, ver),
+			(_, None) => format!("{}chat/completions", base),
+		};
+		let models_url = models_url(&backend, base, self.api_version.as_deref());
+		let tls = TlsFiles{ ca_cert: self.ca_cert.clone(), client_cert: self.client_cert.clone(), client_key: self.client_key.clone() };
+		Ok(Endpoint{ url: url, models_url: models_url, base: base.clone(), api_version: self.api_version.clone(), api_key: self.api_key.clone().unwrap_or_default(), backend: backend, headers: self.headers.clone(), tls: tls, unix_socket: self.unix_socket.clone() })
+	}
+}
+
+/// What `init` writes in `config.toml`
+#[derive(Debug, Default, Clone)]
+pub struct InitSettings {
+	pub api_base: String,
+	pub api_key: String,
+	pub model: String,
+}
+
+/// The model of the chats made by `init` when none is given
+pub const INIT_MODEL: &str = "gpt-4o-mini";
+
+/// Create the configuration: `config_file` (config.toml) for the endpoint
+/// and the directories, `empty_chat.json` with the built-in tools in
+/// `config_dir`, and the chats directory `chats_dir`. Files that exist are
+/// only replaced with `force`, the files written are returned.
+pub fn init(config_file: &Path, config_dir: &Path, chats_dir: &Path, settings: &InitSettings, force: bool) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+	let empty_chat_file = config_dir.join("empty_chat.json");
+	if !force {
+		if let Some(existing) = [config_file, empty_chat_file.as_path()].into_iter().find(|path| path.exists()) {
+			return Err(Box::new(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} already exists, --force replaces it", existing.display()))));
+		}
+	}
+	if let Some(parent) = config_file.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	fs::create_dir_all(config_dir)?;
+	fs::create_dir_all(chats_dir)?;
+	let quote = |value: &str| toml::Value::String(value.to_string()).to_string();
+	let model = if settings.model.is_empty() { INIT_MODEL } else { settings.model.as_str() };
+	let mut config = format!("api_base = {}\napi_key = {}\nmodel = {}\n", quote(&settings.api_base), quote(&settings.api_key), quote(model));
+	config.push_str(&format!("config_dir = {}\nchats_dir = {}\n", quote(&config_dir.display().to_string()), quote(&chats_dir.display().to_string())));
+	// it holds the API key
+	helpers::write_atomic_private(config_file, config)?;
+	let tools = crate::tools::ToolRegistry::with_builtin(None, None, None).definitions()?;
+	let empty_chat = serde_json::json!({
+		"model": model,
+		"messages": [{"role": "system", "content": "You are a helpful assistant."}],
+		"tools": tools,
+	});
+	helpers::write_atomic(&empty_chat_file, serde_json::to_string_pretty(&empty_chat)?)?;
+	Ok(vec![config_file.to_path_buf(), empty_chat_file, chats_dir.to_path_buf()])
+}
+
+/// The key used to encrypt the chat files: `OPENAICLIENT_CHAT_KEY`, then
+/// `chat_key` in config.toml, then (with the `keyring` feature) the
+/// `openaiclient` / `chat_key` entry of the OS keyring.
+pub fn chat_key(config: Option<&Config>) -> Result<Option<ChatKey>, Box<dyn std::error::Error>> {
+	if let Ok(key) = env::var("OPENAICLIENT_CHAT_KEY") {
+		return Ok(Some(ChatKey::from_hex(&key)?));
+	}
+	if let Some(key) = config.and_then(|config| config.chat_key.as_ref()) {
+		return Ok(Some(ChatKey::from_hex(key)?));
+	}
+	#[cfg(feature = "keyring")]
+	match keyring::Entry::new(APP_NAME, "chat_key")?.get_password() {
+		Ok(key) => return Ok(Some(ChatKey::from_hex(&key)?)),
+		Err(keyring::Error::NoEntry) => {},
+		Err(err) => return Err(Box::new(err)),
+	}
+	Ok(None)
+}
+
+/// The endpoint described by the environment, used when there is no configuration file.
+pub fn endpoint_from_env() -> Result<Endpoint, Box<dyn std::error::Error>> {
+	let azure_api_key = env::var("AZURE_API_KEY");
+	let azure_api_base = env::var("AZURE_API_BASE");
+	let azure_api_version = env::var("AZURE_API_VERSION");
+	let ollama_api_base = env::var("OLLAMA_API_BASE");
+
+	if let (Ok(key), Ok(base), Ok(ver)) = (azure_api_key, azure_api_base, azure_api_version) {
+		let url_base = format!("{}chat/completions?api-version={}", base, ver);
+		let models_url = models_url(&Backend::Azure, &base, Some(&ver));
+		Ok(Endpoint{ url: url_base, models_url: models_url, base: base, api_version: Some(ver), api_key: key, backend: Backend::Azure, headers: HashMap::new(), tls: TlsFiles::default(), unix_socket: None })
+	} else if let Ok(base) = ollama_api_base {
+		let url_base = format!("{}api/chat", base);
+		Ok(Endpoint{ url: url_base, models_url: models_url(&Backend::Ollama, &base, None), base: base, api_version: None, api_key: String::new(), backend: Backend::Ollama, headers: HashMap::new(), tls: TlsFiles::default(), unix_socket: None })
+	} else {
+		Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Ooops! no configuration file or environment variables")))
+	}
+}
@@ -9,6 +9,9 @@ use std::process;
 use std::env;
 use thiserror::Error;
 
+mod template;
+pub use template::TemplateProcessor;
+
 #[derive(Debug, Error)]
 pub enum HelperError {
 	#[error("IO error: {0}")]
@@ -17,6 +20,10 @@ pub enum HelperError {
 	Serde(#[from] serde_json::Error),
 	#[error("FromUtf8 error: {0}")]
 	FromUtf8Error(#[from] string::FromUtf8Error),
+	#[error("TOML error: {0}")]
+	Toml(#[from] toml::de::Error),
+	#[error("Template error: {0}")]
+	Template(String),
 }
 
 pub fn has_specific_extension<P: AsRef<Path>>(path: P, ext: &str) -> bool {
@@ -43,7 +50,25 @@ pub fn config_get_dir(name: Option<&str>) -> Result<PathBuf, std::io::Error> {
 	if let Some(app_name) = name {
 		pb.push(app_name);
 	}
-	println!("{}", pb.display());
+	eprintln!("{}", pb.display());
+	Ok(pb)
+}
+
+/// The XDG data directory (`$XDG_DATA_HOME` or `~/.local/share`) for `name`
+pub fn data_get_dir(name: Option<&str>) -> Result<PathBuf, std::io::Error> {
+	let mut pb = if let Ok(data_home) = env::var("XDG_DATA_HOME") {
+		PathBuf::from(data_home)
+	} else if let Ok(home) = env::var("HOME") {
+		let mut data_home = PathBuf::from(home);
+		data_home.push(".local");
+		data_home.push("share");
+		data_home
+	} else {
+		return Err(std::io::Error::new(std::io::ErrorKind::Other, "XDG_DATA_HOME or HOME not found"))
+	};
+	if let Some(app_name) = name {
+		pb.push(app_name);
+	}
 	Ok(pb)
 }
 
@@ -53,6 +78,12 @@ pub fn config_load<T: DeserializeOwned>(app_name: &str, config_name: &str) -> Re
 	read_from_json(&config_file)
 }
 
+pub fn config_load_toml<T: DeserializeOwned>(app_name: &str, config_name: &str) -> Result<T, HelperError> {
+	let mut config_file = config_get_dir(Some(app_name))?;
+	config_file.push(config_name.to_string() + ".toml");
+	read_from_toml(&config_file)
+}
+
 pub fn config_save<T: Serialize>(app_name: &str, config_name: &str, object: &T) -> Result<(), HelperError> {
 	let mut config_file = config_get_dir(Some(app_name))?;
 	fs::create_dir_all(&config_file)?;
@@ -68,18 +99,65 @@ pub fn read_from_json<T: DeserializeOwned>(file_path: impl AsRef<Path>) -> Resul
 	Ok(parsed_json)
 }
 
+pub fn read_from_toml<T: DeserializeOwned>(file_path: impl AsRef<Path>) -> Result<T, HelperError> {
+	let mut file = File::open(file_path.as_ref())?;
+	let mut content = String::new();
+	file.read_to_string(&mut content)?;
+	let parsed_toml: T = toml::from_str(&content)?;
+	Ok(parsed_toml)
+}
+
 pub fn save_to_json<T: Serialize>(file_path: impl AsRef<Path>, object: &T) -> Result<(), HelperError> {
 	let serialised = serde_json::to_string_pretty(&object)?;
-	let mut file = OpenOptions::new()
-		.read(true)
-		.write(true)
-		.create(true)
-		.truncate(true)
-		.open(file_path.as_ref())?;
-	writeln!(file, "{}", &serialised)?;
+	write_atomic(file_path, format!("{}\n", &serialised))?;
 	Ok(())
 }
 
+/// Write `contents` to a temporary file in the same directory and rename it
+/// over `file_path`, so the file is either the old or the new content even
+/// if writing fails part way. The permissions of an existing file are kept.
+pub fn write_atomic(file_path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<(), io::Error> {
+	write_atomic_mode(file_path.as_ref(), contents.as_ref(), None)
+}
+
+/// As `write_atomic`, but only the owner may read the file (on unix), for
+/// files holding secrets such as an API key
+pub fn write_atomic_private(file_path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<(), io::Error> {
+	write_atomic_mode(file_path.as_ref(), contents.as_ref(), Some(0o600))
+}
+
+fn write_atomic_mode(file_path: &Path, contents: &[u8], mode: Option<u32>) -> Result<(), io::Error> {
+	let file_name = file_path.file_name().ok_or(Error::new(ErrorKind::InvalidInput, format!("{}: not a file name", file_path.display())))?;
+	let mut temp_name = std::ffi::OsString::from(".");
+	temp_name.push(file_name);
+	temp_name.push(format!(".{}.tmp", process::id()));
+	let temp_path = file_path.with_file_name(temp_name);
+	let result = (|| {
+		let mut options = OpenOptions::new();
+		options.write(true).create(true).truncate(true);
+		#[cfg(unix)]
+		{
+			// set when created, the content is never readable by others
+			use std::os::unix::fs::OpenOptionsExt;
+			if let Some(mode) = mode {
+				options.mode(mode);
+			}
+		}
+		let mut file = options.open(&temp_path)?;
+		file.write_all(contents)?;
+		file.sync_all()?;
+		match fs::metadata(file_path) {
+			Ok(metadata) if mode.is_none() => fs::set_permissions(&temp_path, metadata.permissions())?,
+			_ => {},
+		}
+		fs::rename(&temp_path, file_path)
+	})();
+	if result.is_err() {
+		let _ = fs::remove_file(&temp_path);
+	}
+	result
+}
+
 pub fn list_files<F>(dir: &Path, accept: F, depth: usize) -> Result<Vec<PathBuf>, io::Error> where F: Fn(PathBuf) -> Option<PathBuf> {
 	let mut files_list = Vec::new();
 	let mut stack = Vec::new();
@@ -108,6 +186,135 @@ pub fn list_files<F>(dir: &Path, accept: F, depth: usize) -> Result<Vec<PathBuf>
 	Ok(files_list)
 }
 
+/// Make an `accept` function for `list_files` and `walk_files` that rejects
+/// the paths ignored by the `.gitignore` in `root` (and `.git` itself).
+pub fn make_gitaccept_matcher(root: &Path) -> impl Fn(PathBuf) -> Option<PathBuf> {
+	let mut builder = ignore::gitignore::GitignoreBuilder::new(root);
+	// a missing .gitignore is reported here and is not a problem
+	let _ = builder.add(root.join(".gitignore"));
+	let gitignore = builder.build().unwrap_or(ignore::gitignore::Gitignore::empty());
+	move |path: PathBuf| {
+		if path.file_name().map(|name| name == ".git").unwrap_or(false) {
+			return None;
+		}
+		if !path.starts_with(gitignore.path()) {
+			return Some(path);
+		}
+		if gitignore.matched_path_or_any_parents(&path, path.is_dir()).is_ignore() {
+			None
+		} else {
+			Some(path)
+		}
+	}
+}
+
+/// Like `list_files` but only files are returned and directories rejected
+/// by `accept` are not entered, `max_depth` 0 lists only `dir` itself.
+pub fn walk_files<F>(dir: &Path, accept: F, max_depth: usize) -> Result<Vec<PathBuf>, io::Error> where F: Fn(PathBuf) -> Option<PathBuf> {
+	let mut files_list = Vec::new();
+	let mut stack = vec![(dir.to_path_buf(), 0)];
+
+	while let Some((current_dir, depth)) = stack.pop() {
+		for entry in fs::read_dir(current_dir)? {
+			let path = entry?.path();
+			let is_dir = path.is_dir();
+			let accepted = match accept(path) {
+				Some(accepted) => accepted,
+				None => continue,
+			};
+			if is_dir {
+				if depth < max_depth {
+					stack.push((accepted, depth + 1));
+				}
+			} else {
+				files_list.push(accepted);
+			}
+		}
+	}
+	Ok(files_list)
+}
+
+/// Seconds since the epoch as an ISO 8601 UTC date and time
+pub fn format_utc(secs: u64) -> String {
+	let days = (secs / 86400) as i64;
+	let time = secs % 86400;
+	// civil_from_days by Howard Hinnant
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+/// Seconds since the epoch at midnight UTC of a `YYYY-MM-DD` date
+pub fn parse_date(date: &str) -> Option<u64> {
+	let mut parts = date.trim().splitn(3, '-').map(|part| part.parse::<i64>().ok());
+	let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+	if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+		return None;
+	}
+	// days_from_civil by Howard Hinnant
+	let y = if month <= 2 { year - 1 } else { year };
+	let era = y.div_euclid(400);
+	let yoe = y.rem_euclid(400);
+	let mp = if month > 2 { month - 3 } else { month + 9 };
+	let doy = (153 * mp + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	let days = era * 146097 + doe - 719468;
+	u64::try_from(days * 86400).ok()
+}
+
+/// Put `text` on the system clipboard with the first of pbcopy (macOS),
+/// clip (Windows), wl-copy (Wayland), xclip or xsel (X11) that runs
+pub fn copy_to_clipboard(text: &str) -> Result<(), io::Error> {
+	let commands: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+		&[("pbcopy", &[])]
+	} else if cfg!(windows) {
+		&[("clip", &[])]
+	} else {
+		&[("wl-copy", &[]), ("xclip", &["-selection", "clipboard"]), ("xsel", &["--clipboard", "--input"])]
+	};
+	for (command, args) in commands {
+		let mut child = match process::Command::new(command).args(*args).stdin(process::Stdio::piped()).spawn() {
+			Ok(child) => child,
+			Err(err) if err.kind() == ErrorKind::NotFound => continue,
+			Err(err) => return Err(err),
+		};
+		if let Some(mut stdin) = child.stdin.take() {
+			stdin.write_all(text.as_bytes())?;
+		}
+		let status = child.wait()?;
+		if status.success() {
+			return Ok(());
+		}
+	}
+	Err(Error::new(ErrorKind::NotFound, "No clipboard command found (pbcopy, clip, wl-copy, xclip or xsel)"))
+}
+
+/// Play an audio file with the first of afplay (macOS), ffplay, mpv, paplay
+/// or aplay that runs
+pub fn play_audio(path: &Path) -> Result<(), io::Error> {
+	let commands: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+		&[("afplay", &[])]
+	} else {
+		&[("ffplay", &["-nodisp", "-autoexit", "-loglevel", "quiet"]), ("mpv", &["--no-video", "--really-quiet"]), ("paplay", &[]), ("aplay", &["-q"])]
+	};
+	for (command, args) in commands {
+		match process::Command::new(command).args(*args).arg(path).status() {
+			Ok(status) if status.success() => return Ok(()),
+			Ok(_) => continue,
+			Err(err) if err.kind() == ErrorKind::NotFound => continue,
+			Err(err) => return Err(err),
+		}
+	}
+	Err(Error::new(ErrorKind::NotFound, "No audio player found (afplay, ffplay, mpv, paplay or aplay)"))
+}
+
 pub fn extract_zip_file_with_password(extractor: &str, dest_path: &Path, file_path: &Path, password: &str) -> Result<(), HelperError> {
 	let password_arg = "-p".to_owned() + &password;
 	let file_arg = file_path.to_str().unwrap();
@@ -0,0 +1,224 @@
+// Text templates for the system prompt: `{{NAME}}` is replaced by a variable,
+// `{{env:NAME}}` by an environment variable, `{{file:path}}` by the content
+// of a file, `{{cmd:command}}` by the output of a command and `{{date}}`,
+// `{{time}}` or `{{datetime}}` by the current UTC date and time.
+// `{{#if NAME}}...{{else}}...{{/if}}` keeps the first part when the value
+// of NAME (any of the above, `!` before it negates) is not empty.
+// `set_project_context` adds variables describing the directory worked in.
+// A template that is not the user's own is processed `untrusted`: it cannot
+// run commands, read the environment or read files outside its project.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use super::{format_utc, make_gitaccept_matcher, walk_files, HelperError};
+
+/// The most files listed in `{{file_tree}}`
+pub const FILE_TREE_LIMIT: usize = 200;
+/// The directories below the base directory listed in `{{file_tree}}`
+const FILE_TREE_DEPTH: usize = 2;
+
+enum Node {
+	Text(String),
+	Value(String),
+	If{ condition: String, then: Vec<Node>, otherwise: Vec<Node> },
+}
+
+/// An `{{#if}}` being read: its condition, the nodes before `{{else}}` and those after
+struct Block {
+	condition: String,
+	then: Vec<Node>,
+	otherwise: Option<Vec<Node>>,
+}
+
+fn template_error(message: String) -> HelperError {
+	HelperError::Template(message)
+}
+
+/// Where the nodes being read go: the innermost block or the template
+fn current<'a>(nodes: &'a mut Vec<Node>, blocks: &'a mut [Block]) -> &'a mut Vec<Node> {
+	match blocks.last_mut() {
+		Some(Block{ otherwise: Some(otherwise), .. }) => otherwise,
+		Some(block) => &mut block.then,
+		None => nodes,
+	}
+}
+
+fn parse(template: &str) -> Result<Vec<Node>, HelperError> {
+	let mut nodes = Vec::new();
+	let mut blocks: Vec<Block> = Vec::new();
+	let mut rest = template;
+	while let Some(start) = rest.find("{{") {
+		let end = rest[start..].find("}}").ok_or(template_error("{{ without }}".to_string()))? + start;
+		if start > 0 {
+			current(&mut nodes, &mut blocks).push(Node::Text(rest[..start].to_string()));
+		}
+		let tag = rest[start + 2..end].trim();
+		rest = &rest[end + 2..];
+		if let Some(condition) = tag.strip_prefix("#if ") {
+			blocks.push(Block{ condition: condition.trim().to_string(), then: Vec::new(), otherwise: None });
+		} else if tag == "else" {
+			match blocks.last_mut() {
+				Some(block) if block.otherwise.is_none() => block.otherwise = Some(Vec::new()),
+				_ => return Err(template_error("{{else}} outside of {{#if}}".to_string())),
+			}
+		} else if tag == "/if" {
+			let block = blocks.pop().ok_or(template_error("{{/if}} without {{#if}}".to_string()))?;
+			current(&mut nodes, &mut blocks).push(Node::If{ condition: block.condition, then: block.then, otherwise: block.otherwise.unwrap_or_default() });
+		} else {
+			current(&mut nodes, &mut blocks).push(Node::Value(tag.to_string()));
+		}
+	}
+	if !rest.is_empty() {
+		current(&mut nodes, &mut blocks).push(Node::Text(rest.to_string()));
+	}
+	match blocks.last() {
+		Some(block) => Err(template_error(format!("{{{{#if {}}}}} without {{{{/if}}}}", block.condition))),
+		None => Ok(nodes),
+	}
+}
+
+pub struct TemplateProcessor {
+	vars: HashMap<String, String>,
+	/// where the paths of `{{file:...}}` are relative to and the commands run
+	base_dir: PathBuf,
+	/// when present the template is untrusted, its files must be in this directory
+	project_dir: Option<PathBuf>,
+}
+
+impl TemplateProcessor {
+	pub fn new(base_dir: impl Into<PathBuf>) -> Self {
+		TemplateProcessor{ vars: HashMap::new(), base_dir: base_dir.into(), project_dir: None }
+	}
+
+	/// For a template that is not the user's own (e.g. given by a project):
+	/// `{{cmd:...}}` and `{{env:...}}` are refused and `{{file:...}}` may
+	/// only read the files in `project_dir`
+	pub fn untrusted(mut self, project_dir: impl Into<PathBuf>) -> Self {
+		self.project_dir = Some(project_dir.into());
+		self
+	}
+
+	pub fn set(&mut self, name: &str, value: impl Into<String>) {
+		self.vars.insert(name.to_string(), value.into());
+	}
+
+	/// Set `cwd`, `os`, `git_branch` (empty outside a repository), `file_tree`
+	/// (the files not ignored by git, two directories deep) and
+	/// `project_context`, all of them as a few lines for the system message
+	pub fn set_project_context(&mut self) {
+		let cwd = self.base_dir.display().to_string();
+		let os = std::env::consts::OS.to_string();
+		let git_branch = process::Command::new("git").arg("-C").arg(&self.base_dir).args(["branch", "--show-current"]).output().ok()
+			.filter(|output| output.status.success())
+			.map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+			.unwrap_or_default();
+		let mut files: Vec<String> = walk_files(&self.base_dir, make_gitaccept_matcher(&self.base_dir), FILE_TREE_DEPTH).unwrap_or_default().iter()
+			.map(|path| path.strip_prefix(&self.base_dir).unwrap_or(path).display().to_string())
+			.collect();
+		files.sort();
+		let more = files.len().saturating_sub(FILE_TREE_LIMIT);
+		files.truncate(FILE_TREE_LIMIT);
+		let mut file_tree = files.join("\n");
+		if more > 0 {
+			file_tree.push_str(&format!("\n... and {} more", more));
+		}
+		let mut context = format!("Working directory: {}\nOperating system: {}\n", cwd, os);
+		if !git_branch.is_empty() {
+			context.push_str(&format!("Git branch: {}\n", git_branch));
+		}
+		if !file_tree.is_empty() {
+			context.push_str(&format!("Files:\n{}\n", file_tree));
+		}
+		self.set("cwd", cwd);
+		self.set("os", os);
+		self.set("git_branch", git_branch);
+		self.set("file_tree", file_tree);
+		self.set("project_context", context);
+	}
+
+	pub fn process(&self, template: &str) -> Result<String, HelperError> {
+		let mut output = String::new();
+		self.render(&parse(template)?, &mut output)?;
+		Ok(output)
+	}
+
+	fn render(&self, nodes: &[Node], output: &mut String) -> Result<(), HelperError> {
+		for node in nodes {
+			match node {
+				Node::Text(text) => output.push_str(text),
+				Node::Value(expression) => output.push_str(&self.value(expression)?),
+				Node::If{ condition, then, otherwise } => {
+					let (negated, condition) = match condition.strip_prefix('!') {
+						Some(condition) => (true, condition.trim()),
+						None => (false, condition.as_str()),
+					};
+					// a missing file or a failing command is false
+					let value = self.value(condition).unwrap_or_default();
+					self.render(if value.trim().is_empty() == negated { then } else { otherwise }, output)?;
+				},
+			}
+		}
+		Ok(())
+	}
+
+	/// The value of an expression, an unknown variable is empty
+	fn value(&self, expression: &str) -> Result<String, HelperError> {
+		if let Some(value) = self.vars.get(expression) {
+			return Ok(value.clone());
+		}
+		if let Some(name) = expression.strip_prefix("env:") {
+			if self.project_dir.is_some() {
+				return Err(template_error(format!("{{{{env:{}}}}} is not allowed in this template", name.trim())));
+			}
+			return Ok(std::env::var(name.trim()).unwrap_or_default());
+		}
+		if let Some(path) = expression.strip_prefix("file:") {
+			let path = self.base_dir.join(path.trim());
+			if let Some(project_dir) = self.project_dir.as_ref() {
+				// resolved first, neither .. nor a link may lead out of the project
+				let inside = match (path.canonicalize(), project_dir.canonicalize()) {
+					(Ok(path), Ok(project_dir)) => path.starts_with(project_dir),
+					_ => false,
+				};
+				if !inside {
+					return Err(template_error(format!("{}: only the files of the project may be read by this template", path.display())));
+				}
+			}
+			return fs::read_to_string(&path).map_err(|e| template_error(format!("{}: {}", path.display(), e)));
+		}
+		if let Some(command) = expression.strip_prefix("cmd:") {
+			return self.command(command.trim());
+		}
+		let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+		match expression {
+			"date" => Ok(format_utc(now)[..10].to_string()),
+			"time" => Ok(format_utc(now)[11..].to_string()),
+			"datetime" => Ok(format_utc(now)),
+			_ => Ok(String::new()),
+		}
+	}
+
+	/// The output of a command run by the shell, without the newlines at its end
+	fn command(&self, command: &str) -> Result<String, HelperError> {
+		if self.project_dir.is_some() {
+			return Err(template_error(format!("{{{{cmd:{}}}}} is not allowed in this template", command)));
+		}
+		let mut shell = if cfg!(windows) {
+			let mut shell = process::Command::new("cmd");
+			shell.arg("/C");
+			shell
+		} else {
+			let mut shell = process::Command::new("sh");
+			shell.arg("-c");
+			shell
+		};
+		let output = shell.arg(command).current_dir(&self.base_dir).output()?;
+		if !output.status.success() {
+			return Err(template_error(format!("{} failed: {}", command, String::from_utf8_lossy(&output.stderr).trim())));
+		}
+		Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches(['\n', '\r']).to_string())
+	}
+}
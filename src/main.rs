@@ -1,31 +1,46 @@
 #![allow(unused)]
 
-use clap::{CommandFactory,Parser};
+use clap::{CommandFactory,Parser,Subcommand};
 use url::Url;
-use std::path::PathBuf;
-use std::fs::{File,OpenOptions};
-use std::io::{Read,Write};
+use std::path::{Path, PathBuf};
+use std::fs::OpenOptions;
+use std::io::{IsTerminal,Read,Write};
 use std::env;
 use serde::ser::StdError;
 
+mod chats;
+mod config;
 mod helpers;
+mod mcp;
 mod openaiapi;
+mod render;
+mod tools;
+mod vectorstore;
 
 #[cfg(test)]
 mod test;
 
 #[derive(Parser)]
+#[clap(subcommand_negates_reqs = true)]
 struct Cli {
-	chat_id: String,
+	#[clap(subcommand)]
+	command: Option<Command>,
+	#[clap(required_unless_present_any = ["serve_mcp", "show_audit"])]
+	chat_id: Option<String>,
 	/// The message to send to the assistant (prefix a filename with @ to send that file as your
-	/// message)
-	message: String,
+	/// message, - reads it from stdin)
+	#[clap(required_unless_present_any = ["serve_mcp", "undo", "show_audit", "rewind", "edit_message", "delete_message", "dump", "compact", "count_tokens", "cost", "set", "system"])]
+	message: Option<String>,
+	/// Files sent after the message (as with @ but without it), - reads stdin
+	attachments: Vec<String>,
 	#[clap(long, default_value = "user")]
 	role: String,
-	#[clap(long, default_value = "data")]
-	config_dir: PathBuf,
-	#[clap(long, default_value = "chats")]
-	chats_dir: PathBuf,
+	#[clap(long)]
+	/// directory containing empty_chat.json (default: data)
+	config_dir: Option<PathBuf>,
+	#[clap(long)]
+	/// directory the chats are stored in (default: chats)
+	chats_dir: Option<PathBuf>,
 	#[clap(long, default_value = "false")]
 	write_req_resp: bool,
 	#[clap(long)]
@@ -36,12 +51,532 @@ struct Cli {
 	tool_call_id: Option<String>,
 	#[clap(long)]
 	pretend: bool,
+	#[clap(long)]
+	/// print the response as it is generated (server sent events)
+	stream: bool,
+	#[clap(long, value_name = "FILE")]
+	/// send an image (PNG, JPEG, GIF, WebP or BMP) with the message, may be repeated
+	image: Vec<PathBuf>,
+	#[clap(long, value_name = "URL")]
+	/// send an image by its URL (fetched by the provider), may be repeated
+	image_url: Vec<String>,
+	#[clap(long, value_enum)]
+	/// how closely the model looks at the images (low costs far fewer tokens)
+	image_detail: Option<ImageDetail>,
+	#[clap(long)]
+	/// model to use for this request (overrides the model stored in the chat)
+	model: Option<String>,
+	#[clap(long)]
+	/// sampling temperature for this request (overrides the chat)
+	temperature: Option<f64>,
+	#[clap(long)]
+	/// maximum tokens in the response for this request (overrides the chat)
+	max_tokens: Option<u32>,
+	#[clap(long)]
+	/// nucleus sampling probability for this request (overrides the chat)
+	top_p: Option<f64>,
+	#[clap(long, value_name = "SEQUENCE")]
+	/// stop sequence for this request, may be repeated (overrides the chat)
+	stop: Vec<String>,
+	#[clap(long)]
+	/// ask for a JSON object as the response ({"type":"json_object"}), the response is
+	/// checked and pretty printed (the prompt must mention JSON)
+	json_mode: bool,
+	#[clap(long, value_name = "BOOL")]
+	/// whether the model may make several tool calls in one response (true or false)
+	parallel_tool_calls: Option<bool>,
+	#[clap(long, value_name = "N")]
+	/// seed for repeatable sampling (overrides the chat)
+	seed: Option<i64>,
+	#[clap(long, value_name = "TOKEN:BIAS", value_parser = parse_logit_bias)]
+	/// bias (-100 to 100) for a token id, may be repeated (added to the chat)
+	logit_bias: Vec<(String, i32)>,
+	#[clap(long)]
+	/// save --temperature, --max-tokens, --top-p, --stop, --json-mode,
+	/// --parallel-tool-calls, --seed and --logit-bias in the chat (without a message the
+	/// chat is only saved)
+	set: bool,
+	#[clap(long)]
+	/// name of the endpoint profile in config.toml to use
+	profile: Option<String>,
+	#[clap(long)]
+	/// send the built-in tools with the request (replaces the tools in the chat)
+	tools: bool,
+	#[clap(long)]
+	/// perform the tool calls requested by the assistant and send the results back
+	/// until the assistant gives an answer without tool calls
+	auto_tools: bool,
+	#[clap(long, default_value = "10")]
+	/// maximum number of tool call round trips for --auto-tools
+	max_iterations: u32,
+	#[clap(long)]
+	/// serve the built-in tools over the Model Context Protocol (stdio) instead of chatting
+	serve_mcp: bool,
+	#[clap(long, value_enum, default_value = "destructive")]
+	/// which tool calls must be approved on the terminal before they are performed
+	approve: tools::Approve,
+	#[clap(long)]
+	/// file tools may only use files inside this directory
+	workspace_root: Option<PathBuf>,
+	#[clap(long)]
+	/// shell used by the execute tool (sh, bash, cmd, powershell or pwsh)
+	shell: Option<String>,
+	#[clap(long)]
+	/// do not perform tool calls that may change something, print what they would do
+	/// (a diff or the command) and send that back as the result instead
+	pretend_tools: bool,
+	#[clap(long, value_name = "CHAT_ID[:N]")]
+	/// start the chat as a copy of another chat (only its first N messages when given)
+	fork_from: Option<String>,
+	#[clap(long, value_name = "N")]
+	/// remove the last N messages from the chat (and any tool calls left without a response)
+	rewind: Option<usize>,
+	#[clap(long)]
+	/// print the chat instead of sending a message (the same as the message "dump")
+	dump: bool,
+	#[clap(long, value_enum, default_value = "text")]
+	/// how the chat is printed by --dump
+	format: chats::DumpFormat,
+	#[clap(long)]
+	/// print responses and dumps as they are instead of rendering the markdown
+	/// (the markdown is only rendered when writing to a terminal)
+	plain: bool,
+	#[clap(long, value_enum, default_value = "text")]
+	/// json prints only the final response as one JSON object (with its tool calls,
+	/// finish_reason and usage), for scripts
+	output: OutputFormat,
+	#[clap(long, value_name = "FILE")]
+	/// also write the response to this file
+	out: Option<PathBuf>,
+	#[clap(long)]
+	/// also copy the response to the clipboard
+	copy: bool,
+	#[clap(long)]
+	/// write only the fenced code blocks of the response with --out and --copy
+	code_only: bool,
+	#[clap(long, value_name = "N", conflicts_with = "delete_message")]
+	/// replace the content of message N (from 0) with the message argument, or stdin without one
+	edit_message: Option<usize>,
+	#[clap(long, value_name = "N")]
+	/// remove message N (from 0) from the chat
+	delete_message: Option<usize>,
+	#[clap(long)]
+	/// replace the older messages of the chat with a summary written by the model
+	/// (the chat as it was is kept as <chat_id>-archive-<time>)
+	compact: bool,
+	#[clap(long, value_name = "TOKENS")]
+	/// compact the chat before sending it when it is estimated to be longer than this
+	compact_at: Option<usize>,
+	#[clap(long)]
+	/// print the tokens of each message (with the message argument when given) and the
+	/// total instead of sending, exact when <encoding>.tiktoken is in the config directory
+	count_tokens: bool,
+	#[clap(long)]
+	/// print the tokens used by the responses in the chat and what they cost
+	cost: bool,
+	#[clap(long, value_name = "NAME")]
+	/// start a new chat from templates/NAME.json in the config directory instead of
+	/// empty_chat.json (its model and sampling parameters replace those of the profile)
+	template: Option<String>,
+	#[clap(long, value_name = "TEXT|@FILE")]
+	/// the system message of the chat (instead of the system prompt template), an
+	/// existing one is only replaced with --force
+	system: Option<String>,
+	#[clap(long)]
+	/// send even when the budget in config.toml is used up, replace the system
+	/// message with --system
+	force: bool,
+	#[clap(long)]
+	/// restore the files changed by the most recent file tool call in this chat
+	undo: bool,
+	#[clap(long)]
+	/// print the tool calls recorded in the audit log (of the chat when a chat id is given)
+	show_audit: bool,
+}
+
+/// The `detail` of the images sent with the message
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum ImageDetail {
+	Low,
+	High,
+	Auto,
+}
+
+/// How the response is printed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+	/// the content as it is written (and the tool calls performed)
+	Text,
+	/// the response message as stored in the chat
+	Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// manage the chats stored in the chats directory
+	Chats {
+		#[clap(subcommand)]
+		action: ChatsAction,
+	},
+	/// import conversations exported from ChatGPT (or role/content JSONL) as new chats
+	Import {
+		file: PathBuf,
+		#[clap(long, value_enum, default_value = "chatgpt")]
+		format: chats::import::ImportFormat,
+		#[clap(long)]
+		/// id of the new chat (numbered when the file has several conversations)
+		chat_id: Option<String>,
+	},
+	/// find the messages containing some text (ignoring case) in all the chats
+	Search {
+		query: String,
+		#[clap(long)]
+		/// only messages with this role (user, assistant, tool...)
+		role: Option<String>,
+		#[clap(long, value_name = "YYYY-MM-DD")]
+		/// only chats modified on or after this date
+		since: Option<String>,
+		#[clap(long, value_name = "YYYY-MM-DD")]
+		/// only chats modified before this date
+		until: Option<String>,
+	},
+	/// list the ids of the models served by the endpoint
+	Models,
+	/// the tokens used and their cost by chat for a month
+	Costs {
+		#[clap(long, value_name = "YYYY-MM")]
+		/// the month to add up (default: this month)
+		month: Option<String>,
+	},
+	/// print the text spoken in a recording (mp3, wav, m4a...)
+	Transcribe {
+		file: PathBuf,
+		#[clap(long, default_value = openaiapi::audio::TRANSCRIPTION_MODEL)]
+		model: String,
+		#[clap(long)]
+		/// the language spoken (ISO-639-1, e.g. en), detected when not given
+		language: Option<String>,
+		#[clap(long)]
+		/// add the text to this chat as a user message instead of printing it
+		chat_id: Option<String>,
+	},
+	/// read the last reply of the assistant in a chat aloud (audio/speech)
+	Speak {
+		chat_id: String,
+		#[clap(long, default_value = openaiapi::audio::SPEECH_VOICE)]
+		/// alloy, echo, fable, onyx, nova or shimmer
+		voice: String,
+		#[clap(long, value_enum, default_value = "mp3")]
+		format: SpeechFormat,
+		#[clap(long, default_value = openaiapi::audio::SPEECH_MODEL)]
+		model: String,
+		#[clap(long, value_name = "FILE")]
+		/// where the audio is written (default: CHAT_ID.FORMAT)
+		out: Option<PathBuf>,
+		#[clap(long)]
+		/// play the audio once it is written
+		play: bool,
+	},
+	/// the embeddings of the text of files (- reads stdin), one per file or line
+	Embed {
+		#[clap(required = true)]
+		inputs: Vec<String>,
+		#[clap(long)]
+		/// embed each line that is not blank rather than each file
+		lines: bool,
+		#[clap(long, default_value = openaiapi::embeddings::EMBEDDING_MODEL)]
+		model: String,
+		#[clap(long, value_enum, default_value = "json")]
+		format: EmbedFormat,
+		#[clap(long, value_name = "FILE")]
+		/// where the embeddings are written (default: stdout)
+		out: Option<PathBuf>,
+	},
+	/// add the text files of directories (or files) to the local vector store
+	Index {
+		#[clap(required = true)]
+		paths: Vec<PathBuf>,
+		#[clap(long, value_name = "FILE")]
+		/// the store (default: .openaiclient/vectors.sqlite3 in the workspace root)
+		store: Option<PathBuf>,
+		#[clap(long, default_value = openaiapi::embeddings::EMBEDDING_MODEL)]
+		model: String,
+	},
+	/// create the configuration directory with config.toml and empty_chat.json, and
+	/// the chats directory (asks for the settings not given on a terminal)
+	Init {
+		#[clap(long)]
+		api_base: Option<String>,
+		#[clap(long)]
+		api_key: Option<String>,
+		#[clap(long)]
+		model: Option<String>,
+		#[clap(long)]
+		/// replace config.toml and empty_chat.json when they exist
+		force: bool,
+	},
+	/// write a commit message for the staged changes (git diff --staged)
+	Commitmsg {
+		#[clap(long)]
+		/// commit the staged changes with the message
+		commit: bool,
+		#[clap(long)]
+		/// keep the request and the message in this new chat (not kept otherwise)
+		chat_id: Option<String>,
+		#[clap(long)]
+		model: Option<String>,
+	},
+	/// find the chunks of the files in the local vector store most like a query
+	Query {
+		query: String,
+		#[clap(long, default_value_t = vectorstore::DEFAULT_TOP)]
+		/// the number of chunks found
+		top: usize,
+		#[clap(long, value_name = "FILE")]
+		/// the store (default: .openaiclient/vectors.sqlite3 in the workspace root)
+		store: Option<PathBuf>,
+		#[clap(long, default_value = openaiapi::embeddings::EMBEDDING_MODEL)]
+		model: String,
+	},
+}
+
+/// How `embed` writes the vectors
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum EmbedFormat {
+	/// a list of the sources (FILE or FILE:LINE) and their embeddings
+	Json,
+	/// the vectors one after the other as little-endian f32
+	F32,
+}
+
+/// The format of the audio written by `speak`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum SpeechFormat {
+	Mp3,
+	Opus,
+	Aac,
+	Flac,
+	Wav,
+	/// raw 24kHz 16-bit samples
+	Pcm,
+}
+
+impl SpeechFormat {
+	fn name(&self) -> &'static str {
+		match self {
+			SpeechFormat::Mp3 => "mp3",
+			SpeechFormat::Opus => "opus",
+			SpeechFormat::Aac => "aac",
+			SpeechFormat::Flac => "flac",
+			SpeechFormat::Wav => "wav",
+			SpeechFormat::Pcm => "pcm",
+		}
+	}
+}
+
+/// `TOKEN:BIAS` where the token is an id and the bias is from -100 to 100
+fn parse_logit_bias(arg: &str) -> Result<(String, i32), String> {
+	let (token, bias) = arg.rsplit_once(':').ok_or(format!("{} is not TOKEN:BIAS", arg))?;
+	token.parse::<u32>().map_err(|_| format!("{} is not a token id", token))?;
+	let bias = bias.parse::<i32>().ok().filter(|bias| (-100..=100).contains(bias)).ok_or(format!("{} is not a bias from -100 to 100", bias))?;
+	Ok((token.to_string(), bias))
+}
+
+fn parse_date_arg(date: Option<&String>) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+	match date {
+		Some(date) => Ok(Some(helpers::parse_date(date).ok_or(format!("Invalid date {}, use YYYY-MM-DD", date))?)),
+		None => Ok(None),
+	}
+}
+
+#[derive(Subcommand)]
+enum ChatsAction {
+	/// list the chats with their message count, model and last modified time
+	List,
+	/// delete a chat
+	Delete { chat_id: String },
+	/// change the id of a chat
+	Rename { chat_id: String, new_chat_id: String },
+	/// copy a chat to a new chat id
+	Copy { chat_id: String, new_chat_id: String },
+}
+
+fn run_chats_action(chats_dir: &std::path::Path, action: &ChatsAction, key: Option<&openaiapi::ChatKey>) -> Result<(), Box<dyn std::error::Error>> {
+	match action {
+		ChatsAction::List => {
+			for chat in chats::list(chats_dir, key)? {
+				let messages = chat.messages.map(|count| count.to_string()).unwrap_or("?".to_string());
+				println!("{}\t{}\t{}\t{}", chat.chat_id, messages, chat.model.unwrap_or("?".to_string()), helpers::format_utc(chat.modified));
+			}
+		},
+		ChatsAction::Delete { chat_id } => chats::delete(chats_dir, chat_id)?,
+		ChatsAction::Rename { chat_id, new_chat_id } => chats::rename(chats_dir, chat_id, new_chat_id)?,
+		ChatsAction::Copy { chat_id, new_chat_id } => chats::copy(chats_dir, chat_id, new_chat_id)?,
+	}
+	Ok(())
+}
+
+fn now() -> u64 {
+	std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// The cost in dollars, noting the responses from models without a price
+fn format_cost(cost: &chats::cost::CostSummary) -> String {
+	if cost.unpriced > 0 {
+		format!("${:.4} ({} response(s) without a price)", cost.cost, cost.unpriced)
+	} else {
+		format!("${:.4}", cost.cost)
+	}
+}
+
+/// Print markdown, rendered for the terminal unless `plain` or the output
+/// is not a terminal
+fn print_markdown(markdown: &str, plain: bool) {
+	if plain || !std::io::stdout().is_terminal() {
+		print!("{}", markdown);
+		if !markdown.ends_with('\n') {
+			println!();
+		}
+	} else {
+		print!("{}", render::Renderer::new(render::terminal_width()).render(markdown));
+	}
+}
+
+/// The part for an attachment: `-` for stdin, an http(s) image URL,
+/// `FILE:START-END`, a directory or a file (an image or text)
+fn attachment(spec: &str) -> Result<openaiapi::ContentPart, Box<dyn std::error::Error>> {
+	let path = Path::new(spec);
+	if spec == "-" {
+		let mut data = Vec::new();
+		std::io::stdin().read_to_end(&mut data)?;
+		Ok(openaiapi::content::stdin_part(data)?)
+	} else if spec.starts_with("http://") || spec.starts_with("https://") {
+		Ok(openaiapi::content::remote_image(spec)?)
+	} else if let Some((file, start, end)) = openaiapi::content::split_line_range(spec).filter(|_| !path.exists()) {
+		Ok(openaiapi::ContentPart::Text { text: openaiapi::content::line_range(Path::new(file), start, end)? })
+	} else if path.is_dir() {
+		let (text, skipped) = openaiapi::content::directory_text(path)?;
+		if !skipped.is_empty() {
+			eprintln!("Left out of {}:\n  {}", spec, skipped.join("\n  "));
+		}
+		Ok(openaiapi::ContentPart::Text { text: text })
+	} else {
+		Ok(openaiapi::content::make_content_part(path)?)
+	}
+}
+
+/// Add the text files below `paths` to the vector store, the files not
+/// modified since they were indexed are skipped
+async fn index_files(store: &mut vectorstore::VectorStore, paths: &[PathBuf], transport: &openaiapi::Transport, endpoint: &config::Endpoint, model: &str) -> Result<(), Box<dyn std::error::Error>> {
+	let mut files = Vec::new();
+	for path in paths {
+		if path.is_dir() {
+			files.extend(helpers::walk_files(path, helpers::make_gitaccept_matcher(path), openaiapi::content::MAX_DIRECTORY_DEPTH)?);
+		} else {
+			files.push(path.clone());
+		}
+	}
+	files.sort();
+	let (mut indexed, mut chunk_count, mut unchanged) = (0, 0, 0);
+	for file in files {
+		let metadata = std::fs::metadata(&file)?;
+		if metadata.len() > openaiapi::content::MAX_DIRECTORY_FILE_SIZE {
+			continue;
+		}
+		let modified = metadata.modified()?.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+		// the same wherever index is run, remove_missing checks it from anywhere
+		let path = file.canonicalize()?.display().to_string();
+		if store.is_current(&path, modified, model)? {
+			unchanged += 1;
+			continue;
+		}
+		let text = match String::from_utf8(std::fs::read(&file)?) {
+			Ok(text) => text,
+			Err(_) => continue,
+		};
+		let chunks = vectorstore::chunk_text(&path, &text);
+		let texts: Vec<String> = chunks.iter().map(|chunk| chunk.text.clone()).collect();
+		let embeddings = if texts.is_empty() {
+			Vec::new()
+		} else {
+			openaiapi::embeddings::embed(transport, &endpoint.api_url("embeddings"), &endpoint.api_key, &endpoint.backend, &endpoint.headers, model, &texts).await?
+		};
+		store.replace_file(&path, modified, model, &chunks, &embeddings)?;
+		indexed += 1;
+		chunk_count += chunks.len();
+	}
+	let removed = store.remove_missing()?;
+	eprintln!("Indexed {} file(s) in {} chunk(s), {} unchanged, {} removed", indexed, chunk_count, unchanged, removed);
+	Ok(())
+}
+
+/// A setting for `init`: the one given, or the answer on a terminal, or `default`
+fn ask_setting(given: &Option<String>, question: &str, default: &str) -> Result<String, std::io::Error> {
+	if let Some(given) = given {
+		return Ok(given.clone());
+	}
+	if !std::io::stdin().is_terminal() {
+		return Ok(default.to_string());
+	}
+	if default.is_empty() {
+		eprint!("{}: ", question);
+	} else {
+		eprint!("{} [{}]: ", question, default);
+	}
+	std::io::stderr().flush()?;
+	let mut answer = String::new();
+	std::io::stdin().read_line(&mut answer)?;
+	let answer = answer.trim();
+	Ok(if answer.is_empty() { default.to_string() } else { answer.to_string() })
+}
+
+/// The instructions sent with the diff by `commitmsg`, replaced by
+/// `commitmsg_prompt.md` in the config directory when there is one
+const COMMIT_PROMPT: &str = "Write a commit message for the diff below in the conventional commits style: \
+a subject line of at most 72 characters starting with a type (feat, fix, docs, refactor, test, chore...) \
+and an optional scope, e.g. `fix(parser): handle empty input`, then, when the change needs explaining, \
+a blank line and a body wrapped at 72 characters saying what changed and why. \
+Reply with the message only, without a code block or any other text.";
+
+/// Diffs longer than this are cut before they are sent by `commitmsg`
+const MAX_COMMIT_DIFF: usize = 100 * 1024;
+
+/// The reply without the code block the model may have put it in
+fn strip_code_fence(reply: &str) -> &str {
+	let trimmed = reply.trim();
+	match trimmed.strip_prefix("```") {
+		Some(rest) => {
+			let rest = rest.split_once('\n').map(|(_, rest)| rest).unwrap_or("");
+			rest.trim_end().strip_suffix("```").unwrap_or(rest).trim()
+		},
+		None => trimmed,
+	}
+}
+
+/// Where the original content of the files changed by the tools in a chat is kept
+fn backups_dir(workspace_root: Option<&PathBuf>, chat_id: &str) -> PathBuf {
+	workspace_root.cloned().unwrap_or_else(|| PathBuf::from(".")).join(".openaiclient").join("backups").join(chat_id)
+}
+
+/// Call the API and save the chat when the call fails (or is interrupted
+/// with Ctrl-C), so the message just added is not lost
+async fn call_api_or_save(ctx: &mut openaiapi::ChatContext) -> Result<String, Box<dyn std::error::Error>> {
+	match ctx.call_api().await {
+		Ok(response) => Ok(response),
+		Err(err) => {
+			ctx.save_chat()?;
+			Err(err)
+		},
+	}
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let args = Cli::parse();
 
+	let chat_id = args.chat_id.clone().unwrap_or_default();
+	let message_arg = args.message.clone().unwrap_or_default();
+
 	if args.role == "function" && args.name.is_none() {
 		let mut cmd = Cli::command();
 		cmd.error(
@@ -50,62 +585,567 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 			).exit();
 	}
 
-	let azure_api_key = env::var("AZURE_API_KEY");
-	let azure_api_base = env::var("AZURE_API_BASE");
-	let azure_api_version = env::var("AZURE_API_VERSION");
-
-	let (api_url, api_key) = if let (Ok(key), Ok(base), Ok(ver)) = (azure_api_key, azure_api_base, azure_api_version) {
-		let url_base = format!("{}chat/completions?api-version={}", base, ver);
-		(url_base, key)
-	} else {
-		return Err(Into::<Box<dyn std::error::Error>>::into(std::io::Error::new(std::io::ErrorKind::Other, "Ooops! no environment variables")));
+	if let Some(Command::Init { api_base, api_key, model, force }) = args.command.as_ref() {
+		// config.toml is always read from the XDG config directory
+		let config_home = helpers::config_get_dir(Some(config::APP_NAME))?;
+		let config_dir = args.config_dir.clone().unwrap_or(config_home.clone());
+		let chats_dir = args.chats_dir.clone().map(Ok).unwrap_or_else(|| helpers::data_get_dir(Some(config::APP_NAME)).map(|dir| dir.join("chats")))?;
+		let settings = config::InitSettings{
+			api_base: ask_setting(api_base, "API base (e.g. https://api.openai.com/v1/)", "https://api.openai.com/v1/")?,
+			api_key: ask_setting(api_key, "API key", "")?,
+			model: ask_setting(model, "Model", config::INIT_MODEL)?,
+		};
+		for path in config::init(&config_home.join("config.toml"), &config_dir, &chats_dir, &settings, *force)? {
+			println!("Created {}", path.display());
+		}
+		return Ok(());
+	}
+	// the configuration file takes precedence, the environment is only used without one
+	let mut config = config::Config::load()?;
+	let has_config_file = config.is_some();
+	if let Some(path) = config::ProjectConfig::find(&env::current_dir()?) {
+		let project: config::ProjectConfig = helpers::read_from_toml(&path)?;
+		config.get_or_insert_with(Default::default).merge_project(project, path.parent().unwrap_or(Path::new(".")));
+	}
+	let config_dir = args.config_dir.clone().or(config.as_ref().and_then(|c| c.config_dir.clone())).unwrap_or(PathBuf::from("data"));
+	let chats_dir = args.chats_dir.clone().or(config.as_ref().and_then(|c| c.chats_dir.clone())).unwrap_or(PathBuf::from("chats"));
+	// nothing else may be written to stdout when serving, and stdin carries
+	// the requests so no call can be approved there
+	if args.serve_mcp {
+		let workspace_root = match args.workspace_root.as_ref() {
+			Some(root) => Some(root.canonicalize()?),
+			None => None,
+		};
+		let backups = backups_dir(workspace_root.as_ref(), "mcp");
+		let mut dispatcher = tools::Dispatcher::new(tools::ToolRegistry::with_builtin(workspace_root, args.shell.clone(), Some(backups)));
+		dispatcher.pretend = args.pretend_tools;
+		dispatcher.audit = Some(tools::audit::AuditLog::new(config_dir.join("audit.jsonl"), "mcp"));
+		if let Some(config) = config {
+			dispatcher.policies.extend(config.tool_policy);
+			dispatcher.policies.extend(config.project_tool_policy);
+		}
+		return Ok(mcp::serve(&dispatcher)?);
+	}
+	let chat_key = config::chat_key(config.as_ref())?;
+	// the chats and the audit log can be used without an endpoint
+	match args.command.as_ref() {
+		Some(Command::Chats { action }) => return run_chats_action(&chats_dir, action, chat_key.as_ref()),
+		Some(Command::Import { file, format, chat_id }) => {
+			let template: openaiapi::Chat = helpers::read_from_json(config_dir.join("empty_chat.json"))?;
+			for chat_id in chats::import::import(&chats_dir, &template, file, *format, chat_id.as_deref(), chat_key.as_ref())? {
+				println!("Imported {}", chat_id);
+			}
+			return Ok(());
+		},
+		Some(Command::Costs { month }) => {
+			let pricing = chats::cost::Pricing::new(&config.as_ref().map(|c| c.prices.clone()).unwrap_or_default());
+			let month = month.clone().unwrap_or_else(|| helpers::format_utc(now())[..7].to_string());
+			let period = chats::cost::month_range(&month).ok_or(format!("Invalid month {}, use YYYY-MM", month))?;
+			let mut total = chats::cost::CostSummary::default();
+			for (chat_id, cost) in chats::cost::costs(&chats_dir, &pricing, period, chat_key.as_ref())? {
+				println!("{}\t{}\t{}\t{}", chat_id, cost.prompt_tokens, cost.completion_tokens, format_cost(&cost));
+				total.prompt_tokens += cost.prompt_tokens;
+				total.completion_tokens += cost.completion_tokens;
+				total.cost += cost.cost;
+				total.unpriced += cost.unpriced;
+			}
+			println!("total\t{}\t{}\t{}", total.prompt_tokens, total.completion_tokens, format_cost(&total));
+			return Ok(());
+		},
+		Some(Command::Search { query, role, since, until }) => {
+			let filter = chats::SearchFilter{ role: role.clone(), since: parse_date_arg(since.as_ref())?, until: parse_date_arg(until.as_ref())? };
+			// the match is highlighted in bold on a terminal
+			let (start, end) = if std::io::stdout().is_terminal() { ("\x1b[1m", "\x1b[0m") } else { ("", "") };
+			for found in chats::search(&chats_dir, query, &filter, chat_key.as_ref())? {
+				println!("{}\t{}\t{}\t{}{}{}{}{}", found.chat_id, found.index, found.role, found.before, start, found.matched, end, found.after);
+			}
+			return Ok(());
+		},
+		Some(Command::Models) | Some(Command::Transcribe { .. }) | Some(Command::Speak { .. }) | Some(Command::Embed { .. })
+			| Some(Command::Index { .. }) | Some(Command::Query { .. }) | Some(Command::Commitmsg { .. }) | Some(Command::Init { .. }) | None => {},
+	}
+	if args.show_audit {
+		for entry in tools::audit::AuditLog::read(&config_dir.join("audit.jsonl"), args.chat_id.as_deref())? {
+			println!("{} {} {} {}{} {}", entry.timestamp, entry.chat_id, entry.tool,
+				if entry.success { "ok" } else { "failed" }, if entry.pretend { " (pretend)" } else { "" },
+				entry.summary.lines().next().unwrap_or(""));
+		}
+		return Ok(());
+	}
+	let (endpoint, profile) = match config.as_ref() {
+		Some(config) if has_config_file || args.profile.is_some() || config.default_profile.is_some() => {
+			let profile = config.profile(args.profile.as_deref())?;
+			(profile.endpoint()?, profile)
+		},
+		// only the settings of a project, the endpoint is in the environment
+		Some(config) => (config::endpoint_from_env()?, config.profile(None)?),
+		None if args.profile.is_some() => {
+			return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "--profile requires a configuration file")));
+		},
+		None => (config::endpoint_from_env()?, config::Profile::default()),
 	};
+	let config = config.unwrap_or_default();
+	let transport = openaiapi::Transport{ client: openaiapi::http_client(&endpoint.tls)?, unix_socket: endpoint.unix_socket.clone() };
+	if let Some(Command::Models) = args.command {
+		for model in openaiapi::list_models(&transport, &endpoint.models_url, &endpoint.api_key, &endpoint.backend, &endpoint.headers).await? {
+			println!("{}", model);
+		}
+		return Ok(());
+	}
 
-    println!("Got chat_id: {} and message: {}", &args.chat_id, &args.message);
-
-	let mut ctx = openaiapi::ChatContext::new(args.config_dir, args.chats_dir, api_url, api_key)?;
-	ctx.write_req_resp = args.write_req_resp;
-	ctx.load_or_new_chat(&args.chat_id)?;
-
-	if args.message == "dump" {
-		for message in ctx.chat.as_ref().unwrap().messages.iter() {
-			if let Some(mesg) = message.content.as_ref() {
-				println!("{}", mesg);
+	if let Some(Command::Transcribe { file, model, language, chat_id }) = args.command.as_ref() {
+		let text = openaiapi::audio::transcribe(&transport, &endpoint.api_url("audio/transcriptions"), &endpoint.api_key, &endpoint.backend, &endpoint.headers,
+			file, model, language.as_deref()).await?;
+		match chat_id {
+			Some(chat_id) => {
+				let store = openaiapi::JsonDirStore::new(config_dir, chats_dir).with_key(chat_key);
+				let mut ctx = openaiapi::ChatContext::with_store(Box::new(store), endpoint.url, endpoint.api_key)?;
+				ctx.load_or_new_chat(chat_id)?;
+				ctx.add_normal_message("user", &text)?;
+				ctx.save_chat()?;
+				eprintln!("Added the transcription of {} to {}", file.display(), chat_id);
+			},
+			None => println!("{}", text),
+		}
+		return Ok(());
+	}
+	if let Some(Command::Embed { inputs, lines, model, format, out }) = args.command.as_ref() {
+		let mut sources = Vec::new();
+		let mut texts = Vec::new();
+		for input in inputs {
+			let text = if input == "-" {
+				let mut text = String::new();
+				std::io::stdin().read_to_string(&mut text)?;
+				text
+			} else {
+				std::fs::read_to_string(input)?
+			};
+			if *lines {
+				for (index, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+					sources.push(format!("{}:{}", input, index + 1));
+					texts.push(line.to_string());
+				}
+			} else {
+				sources.push(input.clone());
+				texts.push(text);
 			}
-			if let Some(tool_calls) = message.tool_calls.as_ref() {
-				for tool_call in tool_calls.iter() {
-					println!("```{}", &tool_call.function.name);
-					println!("{}", &tool_call.function.arguments);
-					println!("```");
+		}
+		let embeddings = openaiapi::embeddings::embed(&transport, &endpoint.api_url("embeddings"), &endpoint.api_key, &endpoint.backend, &endpoint.headers,
+			model, &texts).await?;
+		let output = match format {
+			EmbedFormat::Json => {
+				let list: Vec<serde_json::Value> = sources.iter().zip(embeddings.iter())
+					.map(|(source, embedding)| serde_json::json!({"source": source, "embedding": embedding}))
+					.collect();
+				format!("{}\n", serde_json::to_string(&list)?).into_bytes()
+			},
+			EmbedFormat::F32 => {
+				eprintln!("{} embeddings of {} dimensions", embeddings.len(), embeddings.first().map(|embedding| embedding.len()).unwrap_or(0));
+				openaiapi::embeddings::to_f32_bytes(&embeddings)
+			},
+		};
+		match out {
+			Some(out) => std::fs::write(out, output)?,
+			None => std::io::stdout().write_all(&output)?,
+		}
+		return Ok(());
+	}
+	if let Some(Command::Index { paths, store, model }) = args.command.as_ref() {
+		let workspace_root = args.workspace_root.clone().or(config.workspace_root.clone());
+		let store_path = store.clone().unwrap_or_else(|| vectorstore::default_path(workspace_root.as_deref()));
+		if let Some(parent) = store_path.parent() {
+			std::fs::create_dir_all(parent)?;
+		}
+		let mut store = vectorstore::VectorStore::open(&store_path)?;
+		index_files(&mut store, paths, &transport, &endpoint, model).await?;
+		return Ok(());
+	}
+	if let Some(Command::Commitmsg { commit, chat_id, model }) = args.command.as_ref() {
+		let output = std::process::Command::new("git").args(["diff", "--staged"]).output()?;
+		if !output.status.success() {
+			return Err(format!("git diff --staged failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+		}
+		let mut diff = String::from_utf8_lossy(&output.stdout).to_string();
+		if diff.trim().is_empty() {
+			return Err("Nothing is staged, stage the changes to commit with git add".into());
+		}
+		if diff.len() > MAX_COMMIT_DIFF {
+			let mut end = MAX_COMMIT_DIFF;
+			while !diff.is_char_boundary(end) {
+				end -= 1;
+			}
+			diff.truncate(end);
+			diff.push_str("\n[the rest of the diff is left out]\n");
+		}
+		let prompt = std::fs::read_to_string(config_dir.join("commitmsg_prompt.md")).unwrap_or(COMMIT_PROMPT.to_string());
+		let store: Box<dyn openaiapi::ChatStore> = match chat_id {
+			Some(chat_id) => {
+				let store = openaiapi::JsonDirStore::new(config_dir.clone(), chats_dir.clone()).with_key(chat_key.clone());
+				// the chat is made of the prompt and the diff, a conversation would be lost
+				if openaiapi::ChatStore::exists(&store, chat_id) {
+					return Err(format!("{} already exists, commitmsg --chat-id only creates a new chat", chat_id).into());
 				}
+				Box::new(store)
+			},
+			None => Box::new(openaiapi::MemoryStore::new(helpers::read_from_json(config_dir.join("empty_chat.json"))?)),
+		};
+		let mut ctx = openaiapi::ChatContext::with_store(store, endpoint.url.clone(), endpoint.api_key.clone())?;
+		ctx.backend = endpoint.backend.clone();
+		ctx.headers = endpoint.headers.clone();
+		ctx.transport = transport.clone();
+		ctx.extra_body = profile.extra_body.clone();
+		let chat_id = chat_id.clone().unwrap_or("commitmsg".to_string());
+		ctx.new_chat(&chat_id)?;
+		let chat = ctx.current_chat()?;
+		chat.messages = vec![openaiapi::Message::normal("system".to_string(), prompt)];
+		chat.tools = None;
+		if let Some(model) = model.clone().or(profile.model.clone()) {
+			chat.model = model;
+		}
+		ctx.add_normal_message("user", &diff)?;
+		let reply = ctx.call_api().await?;
+		ctx.save_chat()?;
+		let message = strip_code_fence(&reply);
+		println!("{}", message);
+		if *commit {
+			let status = std::process::Command::new("git").args(["commit", "-m", message]).status()?;
+			if !status.success() {
+				return Err("git commit failed".into());
 			}
 		}
 		return Ok(());
 	}
+	if let Some(Command::Query { query, top, store, model }) = args.command.as_ref() {
+		let workspace_root = args.workspace_root.clone().or(config.workspace_root.clone());
+		let store = vectorstore::VectorStore::open(&store.clone().unwrap_or_else(|| vectorstore::default_path(workspace_root.as_deref())))?;
+		let embedding = openaiapi::embeddings::embed(&transport, &endpoint.api_url("embeddings"), &endpoint.api_key, &endpoint.backend, &endpoint.headers,
+			model, &[query.clone()]).await?.remove(0);
+		for found in store.search(&embedding, model, *top)? {
+			println!("==> {}:{}-{} ({:.3}) <==\n{}\n", found.chunk.path, found.chunk.start_line, found.chunk.end_line, found.score, found.chunk.text);
+		}
+		return Ok(());
+	}
+	if let Some(Command::Speak { chat_id, voice, format, model, out, play }) = args.command.as_ref() {
+		let store = openaiapi::JsonDirStore::new(config_dir, chats_dir).with_key(chat_key);
+		let mut ctx = openaiapi::ChatContext::with_store(Box::new(store), endpoint.url.clone(), endpoint.api_key.clone())?;
+		ctx.load_or_new_chat(chat_id)?;
+		let reply = ctx.current_chat()?.messages.iter().rev()
+			.filter(|message| message.role == "assistant")
+			.find_map(|message| message.text().filter(|text| !text.is_empty()).map(|text| text.to_string()))
+			.ok_or(format!("No reply of the assistant in {}", chat_id))?;
+		let audio = openaiapi::audio::speech(&transport, &endpoint.api_url("audio/speech"), &endpoint.api_key, &endpoint.backend, &endpoint.headers,
+			&reply, model, voice, format.name()).await?;
+		let out = out.clone().unwrap_or_else(|| PathBuf::from(format!("{}.{}", chat_id, format.name())));
+		std::fs::write(&out, audio)?;
+		eprintln!("Wrote {}", out.display());
+		if *play {
+			helpers::play_audio(&out)?;
+		}
+		return Ok(());
+	}
 
-	let message = match args.message.chars().nth(0).unwrap() {
-		'@' => {
-			let mut filename = args.message.clone();
-			filename.remove(0);
-			let mut content = String::new();
-			File::open(&filename)?.read_to_string(&mut content)?;
-			content
-		},
-		_ => args.message,
+	let text_output = args.output == OutputFormat::Text;
+	eprintln!("Got chat_id: {} and message: {}", &chat_id, &message_arg);
+
+	let plugins_dir = config_dir.join("tools.d");
+	let audit_file = config_dir.join("audit.jsonl");
+	let ratelimits_file = config_dir.join("ratelimits.json");
+	if let Some(fork_from) = args.fork_from.as_ref() {
+		chats::fork(&chats_dir, fork_from, &chat_id, chat_key.as_ref())?;
+	}
+	let tokenizers_dir = config_dir.clone();
+	let (budget_chats_dir, budget_key) = (chats_dir.clone(), chat_key.clone());
+	// the instructions of the project worked on, in the workspace or the repository
+	let instructions_start = match args.workspace_root.clone().or(config.workspace_root.clone()) {
+		Some(root) => root,
+		None => env::current_dir()?,
+	};
+	let instructions = config::find_instructions(&instructions_start, config.instructions_file.as_deref().unwrap_or(config::INSTRUCTIONS_FILE));
+	let store = openaiapi::JsonDirStore::new(config_dir, chats_dir).with_key(chat_key).with_template(args.template.clone())
+		.with_system_prompt(config.system_prompt.clone()).with_prompt_project(config.system_prompt_project.clone()).with_instructions(instructions);
+	let new_chat = !openaiapi::ChatStore::exists(&store, &chat_id);
+	if args.template.is_some() && !new_chat {
+		return Err(format!("{} already exists, --template is only used for a new chat", chat_id).into());
+	}
+	// the settings of the template chosen for this chat are kept
+	let profile = if args.template.is_some() {
+		config::Profile{ model: None, temperature: None, top_p: None, max_tokens: None, ..profile }
+	} else {
+		profile
+	};
+	let embeddings_api = openaiapi::embeddings::EmbeddingsApi{ transport: transport.clone(), url: endpoint.api_url("embeddings"),
+		api_key: endpoint.api_key.clone(), backend: endpoint.backend.clone(), headers: endpoint.headers.clone() };
+	let mut ctx = openaiapi::ChatContext::with_store(Box::new(store), endpoint.url, endpoint.api_key)?;
+	ctx.write_req_resp = args.write_req_resp;
+	// the response is only printed once it is complete
+	ctx.stream = args.stream && text_output;
+	ctx.backend = endpoint.backend;
+	ctx.headers = endpoint.headers;
+	ctx.transport = transport;
+	let profile_name = args.profile.clone().or(config.default_profile.clone()).unwrap_or("default".to_string());
+	ctx.rate_limits = Some(openaiapi::ratelimit::RateLimitStore::new(ratelimits_file, &profile_name));
+	ctx.compact_at = args.compact_at.or(config.compact_at);
+	ctx.extra_body = profile.extra_body.clone();
+	ctx.load_or_new_chat(&chat_id)?;
+	if let Some(system) = args.system.as_ref() {
+		let system = match system.strip_prefix('@') {
+			Some(path) => std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?,
+			None => system.clone(),
+		};
+		// --system replaces the message made from the template of a new chat
+		ctx.set_system_message(&system, args.force || new_chat)?;
+		if args.message.is_none() {
+			ctx.save_chat()?;
+			return Ok(());
+		}
+	}
+	if let Some(index) = args.edit_message {
+		let content = match args.message.as_ref() {
+			Some(content) => content.clone(),
+			None => {
+				let mut content = String::new();
+				std::io::stdin().read_to_string(&mut content)?;
+				content
+			},
+		};
+		ctx.edit_message(index, &content)?;
+		ctx.save_chat()?;
+		return Ok(());
+	}
+	if let Some(index) = args.delete_message {
+		ctx.delete_message(index)?;
+		ctx.save_chat()?;
+		return Ok(());
+	}
+	if let Some(budget) = config.budget.clone().filter(|_| !args.force) {
+		let pricing = chats::cost::Pricing::new(&config.prices);
+		let today = chats::cost::today(now());
+		let mut others_today = chats::cost::CostSummary::default();
+		for (other, cost) in chats::cost::costs(&budget_chats_dir, &pricing, today, budget_key.as_ref())? {
+			if other != chat_id {
+				others_today.prompt_tokens += cost.prompt_tokens;
+				others_today.completion_tokens += cost.completion_tokens;
+				others_today.cost += cost.cost;
+				others_today.unpriced += cost.unpriced;
+			}
+		}
+		ctx.budget = Some(chats::cost::BudgetCheck{ budget: budget, pricing: pricing, today: today, others_today: others_today });
+	}
+	if args.cost {
+		let pricing = chats::cost::Pricing::new(&config.prices);
+		let cost = chats::cost::chat_cost(ctx.current_chat()?, &pricing, None);
+		println!("{} prompt and {} completion tokens, {}", cost.prompt_tokens, cost.completion_tokens, format_cost(&cost));
+		return Ok(());
+	}
+	if let Some(count) = args.rewind {
+		let removed = ctx.rewind(count)?;
+		ctx.save_chat()?;
+		println!("Removed {} message(s)", removed);
+		return Ok(());
+	}
+	let overrides = openaiapi::SamplingOverrides{
+		model: args.model.clone(),
+		temperature: args.temperature,
+		max_tokens: args.max_tokens,
+		top_p: args.top_p,
+		stop: if args.stop.is_empty() { None } else { Some(args.stop.clone()) },
+		response_format: if args.json_mode { Some(serde_json::json!({"type": "json_object"})) } else { None },
+		parallel_tool_calls: args.parallel_tool_calls,
+		seed: args.seed,
+		logit_bias: if args.logit_bias.is_empty() { None } else { Some(args.logit_bias.iter().cloned().collect()) },
+	};
+	// the parameters of the profile are only used for this invocation
+	let profile_defaults = openaiapi::SamplingOverrides{
+		model: profile.model.clone(),
+		temperature: profile.temperature,
+		max_tokens: profile.max_tokens,
+		top_p: profile.top_p,
+		logit_bias: profile.logit_bias.clone(),
+		..Default::default()
 	};
+	if args.set {
+		ctx.set_sampling(&overrides)?;
+		if args.message.is_none() {
+			ctx.save_chat()?;
+			return Ok(());
+		}
+	}
+	ctx.overrides = overrides.with_defaults(&profile_defaults);
+	if ctx.compact_at.is_some() || args.count_tokens {
+		let model = match ctx.overrides.model.clone() {
+			Some(model) => model,
+			None => ctx.current_chat()?.model.clone(),
+		};
+		ctx.tokenizer = openaiapi::tokens::Tokenizer::for_model(&tokenizers_dir, &model)?;
+	}
+	if args.compact {
+		let removed = ctx.compact(openaiapi::COMPACT_KEEP).await?;
+		ctx.save_chat()?;
+		println!("Compacted {} message(s) into a summary", removed);
+		return Ok(());
+	}
+	let workspace_root = match args.workspace_root.clone().or(config.workspace_root) {
+		Some(root) => Some(root.canonicalize()?),
+		None => None,
+	};
+	let backups = backups_dir(workspace_root.as_ref(), &chat_id);
+	if args.undo {
+		let library = tools::files::FileLibrary::new(workspace_root).with_backups(Some(backups));
+		println!("{}", library.undo()?);
+		return Ok(());
+	}
+	let vector_store = vectorstore::default_path(workspace_root.as_deref());
+	let mut registry = tools::ToolRegistry::with_builtin(workspace_root, args.shell.clone().or(config.shell), Some(backups));
+	// semantic_search is only offered once the project has been indexed
+	if vector_store.exists() {
+		registry.register(Box::new(tools::semantic::SemanticSearchTool::new(vector_store, embeddings_api)));
+	}
+	if let Some(web_search) = config.web_search.clone() {
+		registry.register(Box::new(tools::web::WebSearchTool::new(web_search)));
+	}
+	if let Some(http_request) = config.http_request.clone() {
+		registry.register(Box::new(tools::web::HttpRequestTool::new(http_request)));
+	}
+	if !config.sql_databases.is_empty() {
+		registry.register(Box::new(tools::sql::SqlQueryTool::new(config.sql_databases.clone())));
+	}
+	let mut extra_tools = tools::plugin::register_plugins(&mut registry, &plugins_dir);
+	#[cfg(feature = "wasm")]
+	extra_tools.extend(tools::wasm::register_wasm_plugins(&mut registry, &plugins_dir));
+	extra_tools.extend(mcp::register_servers(&mut registry, &config.mcp_servers));
+	let mut dispatcher = tools::Dispatcher::new(registry);
+	dispatcher.approve = args.approve;
+	dispatcher.pretend = args.pretend_tools;
+	dispatcher.audit = Some(tools::audit::AuditLog::new(audit_file, &chat_id));
+	if let Some(policy) = config.tool_policy {
+		dispatcher.policies.push(policy);
+	}
+	if let Some(policy) = config.project_tool_policy {
+		dispatcher.policies.push(policy);
+	}
+	if let Some(policy) = ctx.current_chat()?.tool_policy.clone() {
+		dispatcher.policies.push(policy);
+	}
+	if args.tools {
+		ctx.current_chat()?.tools = Some(dispatcher.registry.definitions()?);
+	} else if !extra_tools.is_empty() {
+		ctx.current_chat()?.merge_tools(dispatcher.registry.definitions_of(&extra_tools)?);
+	}
+	// the model is not told about tools it may not call
+	if let Some(chat_tools) = ctx.current_chat()?.tools.as_mut() {
+		chat_tools.retain(|tool| dispatcher.allows(tool.name()));
+	}
 
-	// Here only one tool call may be added and if more tool calls
-	// are pending then the call_api() function will fail and the
-	// binary may be called again to add more tool call responses
-	// TODO: this needs to be better.
+	if args.dump || message_arg == "dump" {
+		let dump = chats::dump(ctx.current_chat()?, args.format)?;
+		match args.format {
+			chats::DumpFormat::Text | chats::DumpFormat::Markdown => print_markdown(&dump, args.plain),
+			chats::DumpFormat::Html | chats::DumpFormat::Json => print!("{}", dump),
+		}
+		return Ok(());
+	}
+
+	// the images and recordings sent with the message
+	let mut media: Vec<openaiapi::ContentPart> = Vec::new();
+	let mut texts: Vec<String> = Vec::new();
+	let mut specs: Vec<&str> = Vec::new();
+	match message_arg.strip_prefix('@') {
+		Some(spec) => specs.push(spec),
+		None if message_arg == "-" => specs.push("-"),
+		None => texts.push(message_arg.clone()),
+	}
+	specs.extend(args.attachments.iter().map(|spec| spec.as_str()));
+	for spec in specs {
+		match attachment(spec)? {
+			openaiapi::ContentPart::Text { text } => texts.push(text),
+			part => media.push(part),
+		}
+	}
+	let message = texts.join("\n\n");
+	for image in args.image.iter() {
+		media.push(openaiapi::content::image_part(image)?);
+	}
+	for url in args.image_url.iter() {
+		media.push(openaiapi::content::remote_image(url)?);
+	}
+	if let Some(detail) = args.image_detail {
+		let detail = match detail {
+			ImageDetail::Low => "low",
+			ImageDetail::High => "high",
+			ImageDetail::Auto => "auto",
+		};
+		for image in media.iter_mut() {
+			if let openaiapi::ContentPart::ImageUrl { image_url } = image {
+				image_url.detail = Some(detail.to_string());
+			}
+		}
+	}
 
-	// If the name is supplied then the response is from a tool
+	// If the name is supplied then the response is from a tool, the
+	// API refuses the request while any tool call is without a response
+	// so the remaining pending tool calls are performed by the Dispatcher
 	match args.name {
-		Some(name) => ctx.add_tool_message(&args.role, &name, args.tool_call_id.as_deref(), &message),
-		None => ctx.add_normal_message(&args.role, &message),
+		Some(name) => {
+			ctx.add_tool_message(&args.role, &name, args.tool_call_id.as_deref(), &message)?;
+			for tool_call in ctx.get_pending_tool_calls()?.iter() {
+				if text_output {
+					println!("```{}", &tool_call.function.name);
+					println!("{}", &tool_call.function.arguments);
+					println!("```");
+				}
+				ctx.add_message(dispatcher.dispatch(tool_call))?;
+			}
+		},
+		None if args.count_tokens && message.is_empty() && media.is_empty() => {},
+		None if !media.is_empty() => {
+			let mut parts = Vec::new();
+			if !message.is_empty() {
+				parts.push(openaiapi::ContentPart::Text { text: message });
+			}
+			parts.append(&mut media);
+			ctx.add_content_message(&args.role, openaiapi::MessageContent::Multi(parts))?;
+		},
+		None => ctx.add_normal_message(&args.role, &message)?,
 	};
 
-	let response = ctx.call_api().await?;
+	// nothing is saved, the chat is only counted
+	if args.count_tokens {
+		let chat = ctx.chat.as_ref().ok_or("No chat currently loaded")?;
+		for (index, message) in chat.messages.iter().enumerate() {
+			println!("{}\t{}\t{}", index, message.role, ctx.tokenizer.count_message(message));
+		}
+		println!("total\t{}{}", ctx.tokenizer.count_chat(chat), if ctx.tokenizer.is_exact() { "" } else { " (estimated)" });
+		return Ok(());
+	}
+
+	let json_mode = args.json_mode || ctx.current_chat()?.wants_json();
+	let mut response = call_api_or_save(&mut ctx).await?;
+	let mut iterations = 0;
+	while args.auto_tools {
+		let tool_calls = ctx.get_pending_tool_calls()?;
+		if tool_calls.is_empty() {
+			break;
+		}
+		if iterations >= args.max_iterations {
+			eprintln!("Stopping after {} tool call iterations, the tool calls are still pending", iterations);
+			break;
+		}
+		iterations += 1;
+		if text_output && !ctx.stream {
+			print_markdown(&response, args.plain);
+		}
+		for tool_call in tool_calls.iter() {
+			if text_output {
+				println!("```{}", &tool_call.function.name);
+				println!("{}", &tool_call.function.arguments);
+				println!("```");
+			}
+			ctx.add_message(dispatcher.dispatch(tool_call))?;
+		}
+		// keep the tool results even if the next call fails
+		ctx.save_chat()?;
+		response = call_api_or_save(&mut ctx).await?;
+	}
 	//let mut resp_file = OpenOptions::new()
 	//	.read(true)
 	//	.write(true)
@@ -114,14 +1154,43 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	//	.open("response.json")?;
 	//writeln!(resp_file, "{}", response)?;
 	ctx.save_chat()?;
-	println!("{}", response);
+	if args.out.is_some() || args.copy {
+		let reply = if args.code_only {
+			let blocks = render::code_blocks(&response);
+			if blocks.is_empty() {
+				eprintln!("The response has no code blocks");
+			}
+			blocks.join("\n")
+		} else {
+			response.clone()
+		};
+		if let Some(out) = args.out.as_ref() {
+			helpers::write_atomic(out, &reply)?;
+		}
+		if args.copy {
+			helpers::copy_to_clipboard(&reply)?;
+		}
+	}
+	if !text_output {
+		let message = ctx.current_chat()?.messages.last().ok_or("No response in the chat")?;
+		println!("{}", serde_json::to_string_pretty(message)?);
+	}
+	if json_mode {
+		// the response has been shown as it was streamed, it is only checked
+		match serde_json::from_str::<serde_json::Value>(&response) {
+			Ok(value) if text_output && !ctx.stream => println!("{}", serde_json::to_string_pretty(&value)?),
+			Ok(_) => {},
+			Err(err) => {
+				if text_output && !ctx.stream {
+					println!("{}", response);
+				}
+				return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("The response is not valid JSON: {}", err))));
+			},
+		}
+	} else if text_output && !ctx.stream {
+		print_markdown(&response, args.plain);
+	}
 	Ok(())
 }
 
-async fn get_information(url_str: &str) -> Result<(), Box<dyn std::error::Error>> {
-	let url = Url::parse(url_str)?;
-	let body = reqwest::get(url).await?.text().await?;
-	println!("body = {:?}", body);
-	Ok(())
-}
 
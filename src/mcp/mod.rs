@@ -0,0 +1,279 @@
+// Model Context Protocol client (stdio transport), the tools offered by
+// the servers in the configuration are added to the ToolRegistry. The
+// built-in tools may also be served to other clients, see serve().
+//
+// See: https://modelcontextprotocol.io/specification/2024-11-05
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::rc::Rc;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::config::{McpServerConfig, APP_NAME};
+use crate::tools::{Dispatcher, Tool, ToolRegistry};
+
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+#[derive(Debug, Error)]
+pub enum McpError {
+	#[error("IO error: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("Serde error: {0}")]
+	Serde(#[from] serde_json::Error),
+	#[error("Server error: {0}")]
+	Server(String),
+	#[error("Server closed the connection")]
+	Closed,
+}
+
+pub struct McpClient {
+	child: Child,
+	stdin: ChildStdin,
+	stdout: BufReader<ChildStdout>,
+	next_id: u64,
+}
+
+impl McpClient {
+	pub fn connect(server: &McpServerConfig) -> Result<Self, McpError> {
+		let mut child = Command::new(&server.command)
+			.args(&server.args)
+			.envs(&server.env)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::inherit())
+			.spawn()?;
+		let stdin = child.stdin.take().ok_or(McpError::Closed)?;
+		let stdout = BufReader::new(child.stdout.take().ok_or(McpError::Closed)?);
+		let mut client = McpClient{ child: child, stdin: stdin, stdout: stdout, next_id: 0 };
+		client.request("initialize", json!({
+			"protocolVersion": PROTOCOL_VERSION,
+			"capabilities": {},
+			"clientInfo": { "name": APP_NAME, "version": env!("CARGO_PKG_VERSION") },
+		}))?;
+		client.notify("notifications/initialized", json!({}))?;
+		Ok(client)
+	}
+
+	fn send(&mut self, message: &Value) -> Result<(), McpError> {
+		// messages are delimited by newlines so must not contain any
+		writeln!(self.stdin, "{}", serde_json::to_string(message)?)?;
+		self.stdin.flush()?;
+		Ok(())
+	}
+
+	pub fn notify(&mut self, method: &str, params: Value) -> Result<(), McpError> {
+		self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+	}
+
+	pub fn request(&mut self, method: &str, params: Value) -> Result<Value, McpError> {
+		self.next_id += 1;
+		let id = self.next_id;
+		self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+		loop {
+			let mut line = String::new();
+			if self.stdout.read_line(&mut line)? == 0 {
+				return Err(McpError::Closed);
+			}
+			if line.trim().is_empty() {
+				continue;
+			}
+			let message: Value = serde_json::from_str(&line)?;
+			// notifications and requests from the server are not supported
+			if message.get("method").is_some() || message.get("id").and_then(|i| i.as_u64()) != Some(id) {
+				continue;
+			}
+			if let Some(error) = message.get("error") {
+				let error_message = error.get("message").and_then(|m| m.as_str()).unwrap_or("unknown error");
+				return Err(McpError::Server(error_message.to_string()));
+			}
+			return Ok(message.get("result").cloned().unwrap_or(Value::Null));
+		}
+	}
+
+	pub fn list_tools(&mut self) -> Result<Vec<Value>, McpError> {
+		let mut tools = Vec::new();
+		let mut cursor: Option<String> = None;
+		loop {
+			let params = match cursor.as_ref() {
+				Some(cursor) => json!({ "cursor": cursor }),
+				None => json!({}),
+			};
+			let result = self.request("tools/list", params)?;
+			if let Some(page) = result.get("tools").and_then(|t| t.as_array()) {
+				tools.extend(page.iter().cloned());
+			}
+			cursor = result.get("nextCursor").and_then(|c| c.as_str()).map(|c| c.to_string());
+			if cursor.is_none() {
+				return Ok(tools);
+			}
+		}
+	}
+
+	/// Call the tool, the text content of the result is returned
+	pub fn call_tool(&mut self, name: &str, arguments: Value) -> Result<String, String> {
+		let result = self.request("tools/call", json!({ "name": name, "arguments": arguments })).map_err(|e| e.to_string())?;
+		let mut text = Vec::new();
+		if let Some(content) = result.get("content").and_then(|c| c.as_array()) {
+			for part in content {
+				match part.get("text").and_then(|t| t.as_str()) {
+					Some(part_text) => text.push(part_text.to_string()),
+					None => text.push(part.to_string()),
+				}
+			}
+		}
+		if result.get("isError").and_then(|e| e.as_bool()).unwrap_or(false) {
+			Err(text.join("\n"))
+		} else {
+			Ok(text.join("\n"))
+		}
+	}
+}
+
+impl Drop for McpClient {
+	fn drop(&mut self) {
+		let _ = self.child.kill();
+		let _ = self.child.wait();
+	}
+}
+
+/// A tool provided by an MCP server
+pub struct McpTool {
+	client: Rc<RefCell<McpClient>>,
+	name: String,
+	description: String,
+	input_schema: Value,
+}
+
+impl Tool for McpTool {
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn json_schema(&self) -> Value {
+		json!({
+			"name": self.name,
+			"description": self.description,
+			"parameters": self.input_schema,
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let arguments: Value = if args.trim().is_empty() {
+			json!({})
+		} else {
+			serde_json::from_str(args).map_err(|e| e.to_string())?
+		};
+		self.client.borrow_mut().call_tool(&self.name, arguments)
+	}
+}
+
+/// Start each server and register its tools, a tool with the same name as a
+/// built-in tool replaces it. Servers that fail to start are reported and skipped.
+/// The names of the registered tools are returned.
+pub fn register_servers(registry: &mut ToolRegistry, servers: &HashMap<String, McpServerConfig>) -> Vec<String> {
+	let mut names = Vec::new();
+	for (name, server) in servers.iter() {
+		let tools = McpClient::connect(server).and_then(|mut client| {
+			let tools = client.list_tools()?;
+			Ok((Rc::new(RefCell::new(client)), tools))
+		});
+		let (client, tools) = match tools {
+			Ok(result) => result,
+			Err(err) => {
+				eprintln!("MCP server {} not available: {}", name, err);
+				continue;
+			},
+		};
+		for tool in tools.iter() {
+			let tool_name = match tool.get("name").and_then(|n| n.as_str()) {
+				Some(tool_name) => tool_name.to_string(),
+				None => continue,
+			};
+			names.push(tool_name.clone());
+			registry.register(Box::new(McpTool{
+				client: client.clone(),
+				name: tool_name,
+				description: tool.get("description").and_then(|d| d.as_str()).unwrap_or("").to_string(),
+				input_schema: tool.get("inputSchema").cloned().unwrap_or(json!({ "type": "object" })),
+			}));
+		}
+	}
+	names
+}
+
+/// Serve the tools of the dispatcher over stdio until stdin is closed, the
+/// client is expected to send one request at a time. The calls go through
+/// the dispatcher so its policies, pretending and audit log apply.
+pub fn serve(dispatcher: &Dispatcher) -> Result<(), McpError> {
+	let stdin = std::io::stdin();
+	let mut stdout = std::io::stdout();
+	for line in stdin.lock().lines() {
+		let line = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let request: Value = match serde_json::from_str(&line) {
+			Ok(request) => request,
+			Err(err) => {
+				let response = json!({ "jsonrpc": "2.0", "id": Value::Null, "error": { "code": -32700, "message": err.to_string() } });
+				writeln!(stdout, "{}", serde_json::to_string(&response)?)?;
+				stdout.flush()?;
+				continue;
+			},
+		};
+		// notifications (no id) do not get a response
+		let id = match request.get("id") {
+			Some(id) => id.clone(),
+			None => continue,
+		};
+		let params = request.get("params").cloned().unwrap_or(json!({}));
+		let result = match request.get("method").and_then(|m| m.as_str()).unwrap_or("") {
+			"initialize" => Ok(json!({
+				"protocolVersion": PROTOCOL_VERSION,
+				"capabilities": { "tools": {} },
+				"serverInfo": { "name": APP_NAME, "version": env!("CARGO_PKG_VERSION") },
+			})),
+			"ping" => Ok(json!({})),
+			"tools/list" => {
+				let mut tools = Vec::new();
+				// the tools no policy allows are not offered
+				for name in dispatcher.registry.names().into_iter().filter(|name| dispatcher.allows(name)) {
+					if let Some(tool) = dispatcher.registry.get(&name) {
+						let schema = tool.json_schema();
+						tools.push(json!({
+							"name": name,
+							"description": schema.get("description").cloned().unwrap_or(json!("")),
+							"inputSchema": schema.get("parameters").cloned().unwrap_or(json!({ "type": "object" })),
+						}));
+					}
+				}
+				Ok(json!({ "tools": tools }))
+			},
+			"tools/call" => {
+				let name = params.get("name").and_then(|n| n.as_str()).unwrap_or("");
+				let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+				match dispatcher.registry.get(name) {
+					Some(_) => {
+						let (text, is_error) = match dispatcher.call(name, &arguments.to_string()) {
+							Ok(text) => (text, false),
+							Err(text) => (text, true),
+						};
+						Ok(json!({ "content": [{ "type": "text", "text": text }], "isError": is_error }))
+					},
+					None => Err((-32602, format!("Unknown tool: {}", name))),
+				}
+			},
+			method => Err((-32601, format!("Method not found: {}", method))),
+		};
+		let response = match result {
+			Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+			Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+		};
+		writeln!(stdout, "{}", serde_json::to_string(&response)?)?;
+		stdout.flush()?;
+	}
+	Ok(())
+}
@@ -0,0 +1,113 @@
+// The audio APIs: transcriptions (Whisper) and speech.
+// See: https://platform.openai.com/docs/api-reference/audio
+//
+// The form is encoded here rather than with reqwest's multipart, whose body
+// is a stream that cannot be sent over a Unix socket.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use reqwest::header::CONTENT_TYPE;
+
+use super::{authorise, Backend, Transport};
+
+/// The default model for `transcribe`
+pub const TRANSCRIPTION_MODEL: &str = "whisper-1";
+/// The default model and voice for `speak`
+pub const SPEECH_MODEL: &str = "tts-1";
+pub const SPEECH_VOICE: &str = "alloy";
+
+/// A `multipart/form-data` body of text fields and one file
+pub struct MultipartForm {
+	boundary: String,
+	body: Vec<u8>,
+}
+
+impl Default for MultipartForm {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl MultipartForm {
+	pub fn new() -> Self {
+		let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+		MultipartForm{ boundary: format!("openaiclient-{:x}", nanos), body: Vec::new() }
+	}
+
+	pub fn text(mut self, name: &str, value: &str) -> Self {
+		self.body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", self.boundary, name, value).as_bytes());
+		self
+	}
+
+	pub fn file(mut self, name: &str, filename: &str, mime: &str, data: &[u8]) -> Self {
+		let filename = filename.replace('"', "");
+		self.body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+			self.boundary, name, filename, mime).as_bytes());
+		self.body.extend_from_slice(data);
+		self.body.extend_from_slice(b"\r\n");
+		self
+	}
+
+	pub fn content_type(&self) -> String {
+		format!("multipart/form-data; boundary={}", self.boundary)
+	}
+
+	/// The body with the closing boundary
+	pub fn finish(mut self) -> Vec<u8> {
+		self.body.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+		self.body
+	}
+}
+
+/// The MIME type of a recording from its extension
+pub fn audio_mime(path: &Path) -> &'static str {
+	match path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_ascii_lowercase().as_str() {
+		"mp3" | "mpga" | "mpeg" => "audio/mpeg",
+		"wav" => "audio/wav",
+		"m4a" | "mp4" => "audio/mp4",
+		"ogg" | "oga" => "audio/ogg",
+		"flac" => "audio/flac",
+		"webm" => "audio/webm",
+		_ => "application/octet-stream",
+	}
+}
+
+/// The text spoken in a recording, `url` is the `audio/transcriptions` url
+/// of the endpoint
+pub async fn transcribe(transport: &Transport, url: &str, api_key: &str, backend: &Backend, headers: &HashMap<String, String>,
+	file: &Path, model: &str, language: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+	let data = fs::read(file)?;
+	let filename = file.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or("audio".to_string());
+	let mut form = MultipartForm::new().text("model", model).text("response_format", "json");
+	if let Some(language) = language {
+		form = form.text("language", language);
+	}
+	let form = form.file("file", &filename, audio_mime(file), &data);
+	let builder = transport.client.post(url).header(CONTENT_TYPE, form.content_type()).body(form.finish());
+	let response = transport.execute(authorise(builder, backend, api_key, headers)).await?;
+	let status = response.status();
+	let body = response.text().await?;
+	if !status.is_success() {
+		return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("{} from {}: {}", status, url, body))));
+	}
+	let json: serde_json::Value = serde_json::from_str(&body)?;
+	Ok(json.get("text").and_then(|text| text.as_str())
+		.ok_or(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "No text in the transcription")))?
+		.to_string())
+}
+
+/// The text read aloud, in `format` (mp3, opus, aac, flac, wav or pcm),
+/// `url` is the `audio/speech` url of the endpoint
+pub async fn speech(transport: &Transport, url: &str, api_key: &str, backend: &Backend, headers: &HashMap<String, String>,
+	text: &str, model: &str, voice: &str, format: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+	let body = serde_json::json!({"model": model, "input": text, "voice": voice, "response_format": format});
+	let builder = transport.client.post(url).header(CONTENT_TYPE, "application/json").body(body.to_string());
+	let response = transport.execute(authorise(builder, backend, api_key, headers)).await?;
+	let status = response.status();
+	if !status.is_success() {
+		let body = response.text().await?;
+		return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("{} from {}: {}", status, url, body))));
+	}
+	response.bytes().await
+}
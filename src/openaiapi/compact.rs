@@ -0,0 +1,76 @@
+// Replacing the older messages of a long chat with a summary written by
+// the model, so that the chat stays within the context window.
+
+use super::{now, ChatContext, Message};
+
+/// Messages kept after the summary by `--compact`
+pub const COMPACT_KEEP: usize = 4;
+
+const SUMMARY_PROMPT: &str = "Summarise the conversation below so that it can be continued without it. \
+Keep the facts, decisions, file names, commands and open questions, leave out pleasantries.";
+
+/// The messages that `compact` replaces as `start..end`: everything after
+/// the leading system messages except the last `keep` messages, moved back
+/// so that no tool response is separated from its tool call.
+pub fn compact_range(messages: &[Message], keep: usize) -> (usize, usize) {
+	let start = messages.iter().position(|message| message.role != "system").unwrap_or(messages.len());
+	let mut end = messages.len().saturating_sub(keep).max(start);
+	while end > start && messages.get(end).map(|message| message.tool_call_id.is_some()).unwrap_or(false) {
+		end -= 1;
+	}
+	(start, end)
+}
+
+/// The messages as plain text for the summary request, tool calls are
+/// written out because the summary chat is sent without the tools.
+fn transcript(messages: &[Message]) -> String {
+	let mut out = String::new();
+	for message in messages {
+		if let Some(content) = message.text().filter(|c| !c.is_empty()) {
+			out.push_str(&format!("{}: {}\n\n", message.role, content));
+		}
+		for tool_call in message.tool_calls.iter().flatten() {
+			out.push_str(&format!("{} called {} with {}\n\n", message.role, tool_call.function.name, tool_call.function.arguments));
+		}
+	}
+	out
+}
+
+impl ChatContext {
+	/// Ask the model to summarise the older messages (see `compact_range`)
+	/// and replace them with a single system message. The chat as it was is
+	/// saved first as `<chat_id>-archive-<time>`. Returns the number of
+	/// messages replaced.
+	pub async fn compact(&mut self, keep: usize) -> Result<usize, Box<dyn std::error::Error>> {
+		let chat_id = self.chat_id.clone().ok_or(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "No chat id")))?;
+		let chat = self.current_chat()?.clone();
+		let (start, end) = compact_range(&chat.messages, keep);
+		if end <= start {
+			return Ok(0);
+		}
+		let mut summary_chat = chat.clone();
+		summary_chat.tools = None;
+		// summarised by the model of this request, the other overrides are for the reply
+		if let Some(model) = self.overrides.model.as_ref() {
+			summary_chat.model = model.clone();
+		}
+		summary_chat.messages = vec![
+			Message::normal("system".to_string(), SUMMARY_PROMPT.to_string()),
+			Message::normal("user".to_string(), transcript(&chat.messages[start..end])),
+		];
+		let response = self.send(&summary_chat, false).await?;
+		let summary = response.text().map(|summary| summary.into_owned()).unwrap_or_default();
+		if summary.trim().is_empty() {
+			return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "The summary is empty, the chat was not compacted")));
+		}
+		self.store.save(&format!("{}-archive-{}", chat_id, now()), &chat)?;
+		let mut message = Message::normal("system".to_string(), format!("Summary of the earlier conversation:\n{}", summary));
+		message.created_at = Some(now());
+		// the summary request is paid for too
+		message.model = response.model.or(Some(summary_chat.model.clone()));
+		message.usage = response.usage;
+		self.current_chat()?.messages.splice(start..end, [message]);
+		self.dirty = true;
+		Ok(end - start)
+	}
+}
@@ -0,0 +1,184 @@
+// The content parts of a message made from files, images and recordings are
+// recognised by their first bytes (the extension may be wrong), images are
+// sent as data: URLs and recordings as input_audio.
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::helpers;
+use super::{ContentPart, ImageUrl, InputAudio};
+
+/// The extensions of the files that must be images
+pub const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+/// The extensions of the files that must be recordings
+pub const AUDIO_EXTENSIONS: &[&str] = &["wav", "mp3"];
+
+/// Files larger than this are left out of a directory
+pub const MAX_DIRECTORY_FILE_SIZE: u64 = 100 * 1024;
+/// Files are left out of a directory once its text is this long
+pub const MAX_DIRECTORY_SIZE: usize = 512 * 1024;
+/// Deepest directory attached below the one given
+pub const MAX_DIRECTORY_DEPTH: usize = 32;
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode_base64(data: &[u8]) -> String {
+	let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+	for chunk in data.chunks(3) {
+		let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+		let bits = ((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | bytes[2] as u32;
+		for index in 0..4 {
+			if index <= chunk.len() {
+				out.push(BASE64_ALPHABET[((bits >> (18 - index * 6)) & 0x3f) as usize] as char);
+			} else {
+				out.push('=');
+			}
+		}
+	}
+	out
+}
+
+/// The MIME type of an image from its first bytes, `None` when it is not a
+/// PNG, JPEG, GIF, WebP or BMP image
+pub fn sniff_image(data: &[u8]) -> Option<&'static str> {
+	if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+		Some("image/png")
+	} else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+		Some("image/jpeg")
+	} else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+		Some("image/gif")
+	} else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+		Some("image/webp")
+	} else if data.len() >= 14 && data.starts_with(b"BM") && u32::from_le_bytes([data[2], data[3], data[4], data[5]]) as usize == data.len() {
+		// text starting with BM is not mistaken for a bitmap, the header has the file size
+		Some("image/bmp")
+	} else {
+		None
+	}
+}
+
+fn image_url(mime: &str, data: &[u8]) -> ContentPart {
+	ContentPart::ImageUrl { image_url: ImageUrl{ url: format!("data:{};base64,{}", mime, encode_base64(data)), detail: None } }
+}
+
+/// The format of a recording from its first bytes (`wav` or `mp3`)
+pub fn sniff_audio(data: &[u8]) -> Option<&'static str> {
+	if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WAVE" {
+		Some("wav")
+	} else if data.starts_with(b"ID3") || (data.len() >= 2 && data[0] == 0xff && [0xfb, 0xf3, 0xf2].contains(&data[1])) {
+		// an ID3 tag or the header of a layer III frame
+		Some("mp3")
+	} else {
+		None
+	}
+}
+
+/// An image or audio part for the data, `None` when it is neither
+fn media_part(data: &[u8]) -> Option<ContentPart> {
+	if let Some(mime) = sniff_image(data) {
+		Some(image_url(mime, data))
+	} else {
+		sniff_audio(data).map(|format| ContentPart::InputAudio { input_audio: InputAudio{ data: encode_base64(data), format: format.to_string() } })
+	}
+}
+
+/// The part for a file: an image as a `data:` URL, a recording as audio
+/// and anything else as text. A file named as an image or a recording that
+/// is not a supported one is an error.
+pub fn make_content_part(path: &Path) -> Result<ContentPart, Error> {
+	let data = fs::read(path)?;
+	if let Some(part) = media_part(&data) {
+		return Ok(part);
+	}
+	let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_ascii_lowercase();
+	if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+		return Err(Error::new(ErrorKind::InvalidData, format!("{} is not a PNG, JPEG, GIF, WebP or BMP image (unsupported or corrupt)", path.display())));
+	}
+	if AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+		return Err(Error::new(ErrorKind::InvalidData, format!("{} is not a WAV or MP3 recording (unsupported or corrupt)", path.display())));
+	}
+	let text = String::from_utf8(data).map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} is neither text nor a supported image or recording", path.display())))?;
+	Ok(ContentPart::Text { text: text })
+}
+
+/// The part for data read from stdin: an image, a recording or text
+pub fn stdin_part(data: Vec<u8>) -> Result<ContentPart, Error> {
+	if let Some(part) = media_part(&data) {
+		return Ok(part);
+	}
+	let text = String::from_utf8(data).map_err(|_| Error::new(ErrorKind::InvalidData, "stdin is neither text nor a supported image or recording"))?;
+	Ok(ContentPart::Text { text: text })
+}
+
+/// The part for a file that must be an image
+pub fn image_part(path: &Path) -> Result<ContentPart, Error> {
+	let data = fs::read(path)?;
+	match sniff_image(&data) {
+		Some(mime) => Ok(image_url(mime, &data)),
+		None => Err(Error::new(ErrorKind::InvalidData, format!("{} is not a PNG, JPEG, GIF, WebP or BMP image (unsupported or corrupt)", path.display()))),
+	}
+}
+
+/// A part for an image the provider fetches itself, `url` must be http(s)
+pub fn remote_image(url: &str) -> Result<ContentPart, Error> {
+	let parsed = url::Url::parse(url).map_err(|e| Error::new(ErrorKind::InvalidInput, format!("{}: {}", url, e)))?;
+	if parsed.scheme() != "http" && parsed.scheme() != "https" {
+		return Err(Error::new(ErrorKind::InvalidInput, format!("{}: only http and https image URLs can be sent", url)));
+	}
+	Ok(ContentPart::ImageUrl { image_url: ImageUrl{ url: url.to_string(), detail: None } })
+}
+
+/// The text files below `dir` (except those ignored by git) each under a
+/// `==> path <==` header, and the files left out with the reason
+pub fn directory_text(dir: &Path) -> Result<(String, Vec<String>), Error> {
+	let mut files = helpers::walk_files(dir, helpers::make_gitaccept_matcher(dir), MAX_DIRECTORY_DEPTH)?;
+	files.sort();
+	let mut text = String::new();
+	let mut skipped = Vec::new();
+	for file in files {
+		let size = fs::metadata(&file)?.len();
+		if size > MAX_DIRECTORY_FILE_SIZE {
+			skipped.push(format!("{} ({} bytes)", file.display(), size));
+			continue;
+		}
+		if text.len() + size as usize > MAX_DIRECTORY_SIZE {
+			skipped.push(format!("{} (over the {} byte limit)", file.display(), MAX_DIRECTORY_SIZE));
+			continue;
+		}
+		match String::from_utf8(fs::read(&file)?) {
+			Ok(content) => {
+				text.push_str(&format!("==> {} <==\n{}", file.display(), content));
+				if !content.ends_with('\n') {
+					text.push('\n');
+				}
+				text.push('\n');
+			},
+			Err(_) => skipped.push(format!("{} (not text)", file.display())),
+		}
+	}
+	Ok((text, skipped))
+}
+
+/// `FILE:START-END` as the file and the line numbers (from 1, inclusive)
+pub fn split_line_range(spec: &str) -> Option<(&str, usize, usize)> {
+	let (file, range) = spec.rsplit_once(':')?;
+	let (start, end) = range.split_once('-')?;
+	let (start, end) = (start.parse::<usize>().ok()?, end.parse::<usize>().ok()?);
+	if file.is_empty() || start == 0 || end < start {
+		return None;
+	}
+	Some((file, start, end))
+}
+
+/// Lines `start` to `end` of a text file under a `==> path:start-end <==`
+/// header (the end is that of the file when it is shorter)
+pub fn line_range(path: &Path, start: usize, end: usize) -> Result<String, Error> {
+	let content = fs::read_to_string(path)?;
+	let lines: Vec<&str> = content.lines().collect();
+	if start > lines.len() {
+		return Err(Error::new(ErrorKind::InvalidInput, format!("{} has only {} lines", path.display(), lines.len())));
+	}
+	let end = end.min(lines.len());
+	Ok(format!("==> {}:{}-{} <==\n{}\n", path.display(), start, end, lines[start - 1..end].join("\n")))
+}
@@ -0,0 +1,76 @@
+// The embeddings API, the vectors of some texts.
+// See: https://platform.openai.com/docs/api-reference/embeddings
+
+use std::collections::HashMap;
+use reqwest::header::CONTENT_TYPE;
+
+use super::{authorise, Backend, Transport};
+
+/// The default model for `embed`
+pub const EMBEDDING_MODEL: &str = "text-embedding-3-small";
+/// The most texts sent in one request
+pub const MAX_BATCH: usize = 256;
+
+/// The embeddings API of an endpoint, kept by the tools that embed text
+#[derive(Debug, Clone)]
+pub struct EmbeddingsApi {
+	pub transport: Transport,
+	pub url: String,
+	pub api_key: String,
+	pub backend: Backend,
+	pub headers: HashMap<String, String>,
+}
+
+impl EmbeddingsApi {
+	pub async fn embed(&self, model: &str, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+		embed(&self.transport, &self.url, &self.api_key, &self.backend, &self.headers, model, texts).await
+	}
+}
+
+/// The embedding of each text, in order, `url` is the `embeddings` url of
+/// the endpoint. The texts are sent `MAX_BATCH` at a time.
+pub async fn embed(transport: &Transport, url: &str, api_key: &str, backend: &Backend, headers: &HashMap<String, String>,
+	model: &str, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+	let mut embeddings = Vec::with_capacity(texts.len());
+	for batch in texts.chunks(MAX_BATCH) {
+		let body = serde_json::json!({"model": model, "input": batch});
+		let builder = transport.client.post(url).header(CONTENT_TYPE, "application/json").body(body.to_string());
+		let response = transport.execute(authorise(builder, backend, api_key, headers)).await?;
+		let status = response.status();
+		let body = response.text().await?;
+		if !status.is_success() {
+			return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("{} from {}: {}", status, url, body))));
+		}
+		let mut batch_embeddings = parse_embeddings(&body)?;
+		if batch_embeddings.len() != batch.len() {
+			return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("{} embeddings for {} texts", batch_embeddings.len(), batch.len()))));
+		}
+		embeddings.append(&mut batch_embeddings);
+	}
+	Ok(embeddings)
+}
+
+/// The vectors of an embeddings response, `data[].embedding` in the order
+/// of their `index`
+pub fn parse_embeddings(body: &str) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+	let json: serde_json::Value = serde_json::from_str(body)?;
+	let data = json.get("data").and_then(|data| data.as_array())
+		.ok_or(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "No data in the embeddings response")))?;
+	let mut indexed = Vec::with_capacity(data.len());
+	for (position, item) in data.iter().enumerate() {
+		let index = item.get("index").and_then(|index| index.as_u64()).map(|index| index as usize).unwrap_or(position);
+		let vector = item.get("embedding").and_then(|embedding| embedding.as_array())
+			.ok_or(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "No embedding in the embeddings response")))?
+			.iter().map(|value| value.as_f64().map(|value| value as f32))
+			.collect::<Option<Vec<f32>>>()
+			.ok_or(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "An embedding is not a list of numbers")))?;
+		indexed.push((index, vector));
+	}
+	indexed.sort_by_key(|(index, _)| *index);
+	Ok(indexed.into_iter().map(|(_, vector)| vector).collect())
+}
+
+/// The vectors one after the other as little-endian f32
+pub fn to_f32_bytes(vectors: &[Vec<f32>]) -> Vec<u8> {
+	vectors.iter().flatten().flat_map(|value| value.to_le_bytes()).collect()
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::path::{Path,PathBuf};
 use serde_json;
 use serde_derive::{Deserialize, Serialize};
@@ -5,10 +6,28 @@ use std::collections::HashMap;
 use url::Url;
 use reqwest::header::{CONTENT_TYPE,CONTENT_LENGTH};
 use std::fs;
+use std::io::Write;
 use thiserror::Error;
 //use std::rc::Rc;
 
+use crate::chats::cost::BudgetCheck;
 use crate::helpers;
+use crate::tools::ToolPolicy;
+
+pub mod audio;
+mod compact;
+pub mod content;
+pub mod embeddings;
+mod ollama;
+pub mod ratelimit;
+pub mod store;
+pub mod tokens;
+pub mod transport;
+
+pub use compact::{compact_range, COMPACT_KEEP};
+pub use store::{ChatKey, ChatStore, JsonDirStore, MemoryStore};
+pub use transport::Transport;
+use transport::Response;
 
 #[derive(Debug)]
 pub enum ChatErrorKind {
@@ -50,13 +69,13 @@ impl std::fmt::Display for ChatError {
 	}
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FunctionCall {
 	pub name: String,
 	pub arguments: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ToolCall {
 	pub id: String,
 	#[serde(rename = "type")]
@@ -64,123 +83,651 @@ pub struct ToolCall {
 	pub function: FunctionCall,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The content of a message: text, or parts (text, images and audio) for the
+/// multimodal messages. Chats saved before the parts were supported have
+/// only text.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MessageContent {
+	Text(String),
+	Multi(Vec<ContentPart>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+	Text { text: String },
+	ImageUrl { image_url: ImageUrl },
+	InputAudio { input_audio: InputAudio },
+}
+
+/// An image sent by URL, a remote one or a `data:` URL with the image
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageUrl {
+	pub url: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub detail: Option<String>,
+}
+
+/// A recording sent to an audio model, base64 encoded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputAudio {
+	pub data: String,
+	/// `wav` or `mp3`
+	pub format: String,
+}
+
+impl MessageContent {
+	/// The text of the content, the text parts on separate lines
+	pub fn text(&self) -> Cow<'_, str> {
+		match self {
+			MessageContent::Text(text) => Cow::Borrowed(text),
+			MessageContent::Multi(parts) => Cow::Owned(parts.iter().filter_map(|part| match part {
+				ContentPart::Text { text } => Some(text.as_str()),
+				_ => None,
+			}).collect::<Vec<&str>>().join("\n")),
+		}
+	}
+
+	/// The recordings in the content
+	pub fn audio(&self) -> Vec<&InputAudio> {
+		match self {
+			MessageContent::Text(_) => Vec::new(),
+			MessageContent::Multi(parts) => parts.iter().filter_map(|part| match part {
+				ContentPart::InputAudio { input_audio } => Some(input_audio),
+				_ => None,
+			}).collect(),
+		}
+	}
+
+	/// The URLs of the images in the content
+	pub fn images(&self) -> Vec<&ImageUrl> {
+		match self {
+			MessageContent::Text(_) => Vec::new(),
+			MessageContent::Multi(parts) => parts.iter().filter_map(|part| match part {
+				ContentPart::ImageUrl { image_url } => Some(image_url),
+				_ => None,
+			}).collect(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		match self {
+			MessageContent::Text(text) => text.is_empty(),
+			MessageContent::Multi(parts) => parts.is_empty(),
+		}
+	}
+}
+
+impl From<String> for MessageContent {
+	fn from(text: String) -> Self {
+		MessageContent::Text(text)
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message {
 	pub role: String,
-	pub content: Option<String>,
+	pub content: Option<MessageContent>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub name: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub tool_call_id: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub tool_calls: Option<Vec<ToolCall>>,
+	/// seconds since the epoch when the message was added (not sent to the API)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub created_at: Option<u64>,
+	/// the model that wrote the response (not sent to the API)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub model: Option<String>,
+	/// the tokens used by the call that returned the response (not sent to the API)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub usage: Option<Usage>,
+	/// the backend configuration that wrote the response, with `seed` the
+	/// same fingerprint should give the same response (not sent to the API)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub system_fingerprint: Option<String>,
+	/// the response was interrupted and the content is incomplete (not sent to the API)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub truncated: Option<bool>,
+	/// why the model stopped, e.g. `stop`, `length` or `tool_calls` (not sent to the API)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub finish_reason: Option<String>,
 }
 
 impl Message {
-	pub fn normal(role: String, content: String) -> Self {
-		Message{ role: role, content: Some(content), name: None, tool_calls: None, tool_call_id: None }
+	pub fn normal(role: String, content: impl Into<MessageContent>) -> Self {
+		Message{ role: role, content: Some(content.into()), name: None, tool_calls: None, tool_call_id: None, created_at: None, model: None, usage: None, system_fingerprint: None, truncated: None, finish_reason: None }
 	}
 	pub fn tool_response(role: String, name: String, tool_call_id: String, content: String) -> Self {
-		Message{ role: role, name: Some(name), tool_call_id: Some(tool_call_id), content: Some(content), tool_calls: None }
+		Message{ role: role, name: Some(name), tool_call_id: Some(tool_call_id), content: Some(MessageContent::Text(content)), tool_calls: None, created_at: None, model: None, usage: None, system_fingerprint: None, truncated: None, finish_reason: None }
+	}
+
+	/// The text of the content (see `MessageContent::text`)
+	pub fn text(&self) -> Option<Cow<'_, str>> {
+		self.content.as_ref().map(|content| content.text())
+	}
+}
+
+/// The `usage` object of a chat completion
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Usage {
+	#[serde(default)]
+	pub prompt_tokens: u64,
+	#[serde(default)]
+	pub completion_tokens: u64,
+	#[serde(default)]
+	pub total_tokens: u64,
+}
+
+/// A message as it is sent to the API, without the metadata kept in the chat
+#[derive(Serialize)]
+pub struct RequestMessage<'a> {
+	pub role: &'a str,
+	pub content: Option<&'a MessageContent>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub name: Option<&'a str>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub tool_call_id: Option<&'a str>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub tool_calls: Option<&'a Vec<ToolCall>>,
+}
+
+impl<'a> From<&'a Message> for RequestMessage<'a> {
+	fn from(message: &'a Message) -> Self {
+		RequestMessage{
+			role: &message.role,
+			content: message.content.as_ref(),
+			name: message.name.as_deref(),
+			tool_call_id: message.tool_call_id.as_deref(),
+			tool_calls: message.tool_calls.as_ref(),
+		}
 	}
 }
 
-#[derive(Serialize, Deserialize)]
+fn now() -> u64 {
+	std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct FunctionProperty {
-	#[serde(rename = "type")]
-	property_type: String,
-	description: String,
+	#[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+	property_type: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	description: Option<String>,
 	#[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
-	accepted_values_enum: Option<Vec<String>>,
+	accepted_values_enum: Option<Vec<serde_json::Value>>,
+	/// the rest of the JSON schema (items, anyOf, default...) is passed through unchanged
+	#[serde(flatten)]
+	schema: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct FunctionParameters {
 	#[serde(rename = "type")]
 	parameter_type: String,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	properties: Option<HashMap<String, FunctionProperty>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	required: Option<Vec<String>>,
+	#[serde(flatten)]
+	schema: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Function {
 	name: String,
+	#[serde(default)]
 	description: String,
 	parameters: FunctionParameters,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Tool {
 	#[serde(rename = "type")]
 	tool_type: String,
 	function: Function,
 }
 
-#[derive(Serialize, Deserialize)]
+impl Tool {
+	pub fn name(&self) -> &str {
+		&self.function.name
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct Chat {
-	model: String,
+	pub model: String,
 	pub messages: Vec<Message>,
 	#[serde(skip_serializing_if = "Option::is_none")]
-	tools: Option<Vec<Tool>>,
-	max_tokens: u32,
-	temperature: f64,
-	frequency_penalty: u32,
-	presence_penalty: u32,
-	top_p: f64,
-	stop: Option<Vec<String>>,
+	pub tools: Option<Vec<Tool>>,
+	// the sampling parameters missing from the chat are not sent
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub max_tokens: Option<u32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub temperature: Option<f64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub frequency_penalty: Option<f64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub presence_penalty: Option<f64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub top_p: Option<f64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub stop: Option<Vec<String>>,
+	/// e.g. `{"type": "json_object"}` for a response that is a JSON object
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub response_format: Option<serde_json::Value>,
+	/// whether the model may make several tool calls in one response
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub parallel_tool_calls: Option<bool>,
+	/// sampling is repeatable (as far as the backend allows) with the same seed
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub seed: Option<i64>,
+	/// token id to a bias from -100 (never) to 100 (only that token)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub logit_bias: Option<HashMap<String, i32>>,
+	/// merged into the request, for the parameters of a particular provider
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub extra_body: Option<serde_json::Value>,
+	/// which tools may be called in this chat
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub tool_policy: Option<ToolPolicy>,
+}
+
+/// Fields of the stored chat that are only used by this client and must not be sent
+pub const LOCAL_FIELDS: &[&str] = &["tool_policy", "extra_body"];
+
+/// Merge `extra` into `target`, objects are merged key by key and any
+/// other value replaces the one in `target`
+pub fn merge_json(target: &mut serde_json::Value, extra: &serde_json::Value) {
+	match (target, extra) {
+		(serde_json::Value::Object(target), serde_json::Value::Object(extra)) => {
+			for (key, value) in extra {
+				merge_json(target.entry(key.clone()).or_insert(serde_json::Value::Null), value);
+			}
+		},
+		(target, extra) => *target = extra.clone(),
+	}
+}
+
+fn put_system_message(messages: &mut Vec<Message>, content: String) {
+	match messages.first_mut() {
+		Some(message) if message.role == "system" => *message = Message::normal("system".to_string(), content),
+		_ => messages.insert(0, Message::normal("system".to_string(), content)),
+	}
+}
+
+impl Chat {
+	/// Whether the response is asked to be JSON (`json_object` or `json_schema`)
+	pub fn wants_json(&self) -> bool {
+		self.response_format.as_ref()
+			.and_then(|format| format.get("type"))
+			.and_then(|format_type| format_type.as_str())
+			.map(|format_type| format_type.starts_with("json"))
+			.unwrap_or(false)
+	}
+
+	/// Replace the system message at the start of the chat, or add one there
+	pub fn set_system_message(&mut self, content: String) {
+		put_system_message(&mut self.messages, content);
+	}
+
+	/// Add `content` to the end of the system message, which is added when there is none
+	pub fn append_system_message(&mut self, content: &str) {
+		let system = match self.messages.first() {
+			Some(message) if message.role == "system" => match message.content.as_ref().map(|content| content.text()) {
+				Some(system) if !system.trim().is_empty() => format!("{}\n\n{}", system.trim_end(), content),
+				_ => content.to_string(),
+			},
+			_ => content.to_string(),
+		};
+		self.set_system_message(system);
+	}
+
+	/// Add the tools to those sent with the chat, a tool with the same name is replaced
+	pub fn merge_tools(&mut self, tools: Vec<Tool>) {
+		let chat_tools = self.tools.get_or_insert_with(Vec::new);
+		for tool in tools {
+			chat_tools.retain(|t| t.name() != tool.name());
+			chat_tools.push(tool);
+		}
+	}
+}
+
+/// Sampling parameters given on the command line, they replace those of
+/// the chat for a request without being saved unless applied to the chat
+#[derive(Debug, Default, Clone)]
+pub struct SamplingOverrides {
+	pub model: Option<String>,
+	pub temperature: Option<f64>,
+	pub max_tokens: Option<u32>,
+	pub top_p: Option<f64>,
+	pub stop: Option<Vec<String>>,
+	pub response_format: Option<serde_json::Value>,
+	pub parallel_tool_calls: Option<bool>,
+	pub seed: Option<i64>,
+	/// added to the biases of the chat
+	pub logit_bias: Option<HashMap<String, i32>>,
+}
+
+impl SamplingOverrides {
+	/// These overrides, each one not given taken from `defaults` (e.g. the
+	/// parameters of a profile), the biases of both combined
+	pub fn with_defaults(&self, defaults: &SamplingOverrides) -> SamplingOverrides {
+		let logit_bias = match (defaults.logit_bias.as_ref(), self.logit_bias.as_ref()) {
+			(None, None) => None,
+			(defaults, given) => Some(defaults.into_iter().chain(given).flatten().map(|(token, bias)| (token.clone(), *bias)).collect()),
+		};
+		SamplingOverrides{
+			model: self.model.clone().or(defaults.model.clone()),
+			temperature: self.temperature.or(defaults.temperature),
+			max_tokens: self.max_tokens.or(defaults.max_tokens),
+			top_p: self.top_p.or(defaults.top_p),
+			stop: self.stop.clone().or(defaults.stop.clone()),
+			response_format: self.response_format.clone().or(defaults.response_format.clone()),
+			parallel_tool_calls: self.parallel_tool_calls.or(defaults.parallel_tool_calls),
+			seed: self.seed.or(defaults.seed),
+			logit_bias: logit_bias,
+		}
+	}
+
+	pub fn apply(&self, chat: &mut Chat) {
+		if let Some(model) = self.model.as_ref() {
+			chat.model = model.clone();
+		}
+		if self.temperature.is_some() {
+			chat.temperature = self.temperature;
+		}
+		if self.max_tokens.is_some() {
+			chat.max_tokens = self.max_tokens;
+		}
+		if self.top_p.is_some() {
+			chat.top_p = self.top_p;
+		}
+		if self.stop.is_some() {
+			chat.stop = self.stop.clone();
+		}
+		if self.response_format.is_some() {
+			chat.response_format = self.response_format.clone();
+		}
+		if self.parallel_tool_calls.is_some() {
+			chat.parallel_tool_calls = self.parallel_tool_calls;
+		}
+		if self.seed.is_some() {
+			chat.seed = self.seed;
+		}
+		if let Some(logit_bias) = self.logit_bias.as_ref() {
+			chat.logit_bias.get_or_insert_with(HashMap::new).extend(logit_bias.iter().map(|(token, bias)| (token.clone(), *bias)));
+		}
+	}
+}
+
+/// The shape of the API that requests are sent to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backend {
+	/// Azure OpenAI chat/completions, authenticated with the api-key header
+	Azure,
+	/// OpenAI compatible chat/completions, authenticated with a bearer token
+	OpenAi,
+	/// Ollama native /api/chat
+	Ollama,
+}
+
+/// PEM files for endpoints with a private PKI
+#[derive(Debug, Default, Clone)]
+pub struct TlsFiles {
+	/// root certificates trusted as well as the system ones
+	pub ca_cert: Option<PathBuf>,
+	/// certificate and PKCS#8 key presented to the server
+	pub client_cert: Option<PathBuf>,
+	pub client_key: Option<PathBuf>,
+}
+
+/// The HTTP client for an endpoint, trusting the extra root certificates
+/// and presenting the client certificate when they are configured
+pub fn http_client(tls: &TlsFiles) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+	let mut builder = reqwest::Client::builder();
+	if let Some(ca_cert) = tls.ca_cert.as_ref() {
+		let bundle = fs::read_to_string(ca_cert)?;
+		// a bundle may hold several certificates
+		for pem in bundle.split_inclusive("-----END CERTIFICATE-----").filter(|pem| pem.contains("-----BEGIN CERTIFICATE-----")) {
+			builder = builder.add_root_certificate(reqwest::Certificate::from_pem(pem.trim().as_bytes())?);
+		}
+	}
+	match (tls.client_cert.as_ref(), tls.client_key.as_ref()) {
+		(Some(cert), Some(key)) => {
+			builder = builder.identity(reqwest::Identity::from_pkcs8_pem(&fs::read(cert)?, &fs::read(key)?)?);
+		},
+		(None, None) => {},
+		_ => return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, "client_cert and client_key must be given together"))),
+	}
+	Ok(builder.build()?)
+}
+
+/// Counts the Ctrl-C, once the first request has installed the handler
+static INTERRUPTS: std::sync::OnceLock<tokio::sync::watch::Sender<u64>> = std::sync::OnceLock::new();
+
+/// A receiver changed by the next Ctrl-C. Once installed, the handler
+/// replaces the default one for good: a Ctrl-C while no request is
+/// listening (e.g. while a tool runs) exits as the default one would.
+fn interrupts() -> tokio::sync::watch::Receiver<u64> {
+	INTERRUPTS.get_or_init(|| {
+		tokio::spawn(async {
+			while tokio::signal::ctrl_c().await.is_ok() {
+				match INTERRUPTS.get() {
+					Some(sender) if sender.receiver_count() > 0 => sender.send_modify(|count| *count += 1),
+					_ => std::process::exit(130),
+				}
+			}
+		});
+		tokio::sync::watch::channel(0).0
+	}).subscribe()
+}
+
+/// The error returned when the user interrupts a request with Ctrl-C
+fn interrupted() -> Box<dyn std::error::Error> {
+	Box::new(std::io::Error::new(std::io::ErrorKind::Interrupted, "Interrupted, the request was cancelled"))
+}
+
+/// Add the authentication used by `backend` and the configured headers to a request
+fn authorise(builder: reqwest::RequestBuilder, backend: &Backend, api_key: &str, headers: &HashMap<String, String>) -> reqwest::RequestBuilder {
+	let mut builder = match backend {
+		Backend::Azure => builder.header("api-key", api_key),
+		Backend::OpenAi => builder.bearer_auth(api_key),
+		Backend::Ollama => builder,
+	};
+	for (name, value) in headers {
+		builder = builder.header(name, value);
+	}
+	builder
+}
+
+/// The ids of the models served by an endpoint (see `config::Endpoint::models_url`)
+pub async fn list_models(transport: &Transport, models_url: &str, api_key: &str, backend: &Backend, headers: &HashMap<String, String>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+	let response = transport.execute(authorise(transport.client.get(models_url), backend, api_key, headers)).await?;
+	let status = response.status();
+	let body = response.text().await?;
+	if !status.is_success() {
+		return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("{} from {}: {}", status, models_url, body))));
+	}
+	parse_models(&body)
+}
+
+/// The model ids in a models list, `data[].id` (OpenAI and Azure) or
+/// `models[].name` (Ollama), sorted
+pub fn parse_models(body: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+	let json: serde_json::Value = serde_json::from_str(body)?;
+	let list = json.get("data").or(json.get("models")).and_then(|list| list.as_array())
+		.ok_or(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "No data or models in the models list")))?;
+	let mut models: Vec<String> = list.iter()
+		.filter_map(|model| model.get("id").or(model.get("name")).and_then(|id| id.as_str()))
+		.map(|id| id.to_string())
+		.collect();
+	models.sort();
+	models.dedup();
+	Ok(models)
 }
 
 pub struct ChatContext {
 	pub chat: Option<Chat>,
 	chat_id: Option<String>,
-	config_dir: PathBuf,
-	chats_dir: PathBuf,
+	store: Box<dyn ChatStore>,
 	api_key: String,
 	post_url: url::Url,
 	dirty: bool,
 	pub write_req_resp: bool,
+	pub stream: bool,
+	pub backend: Backend,
+	/// compact the chat before sending it when its tokens exceed this
+	pub compact_at: Option<usize>,
+	pub tokenizer: tokens::Tokenizer,
+	/// requests are refused once this budget is used up
+	pub budget: Option<BudgetCheck>,
+	/// applied to the chat sent, not to the chat saved
+	pub overrides: SamplingOverrides,
+	/// merged into every request (before the `extra_body` of the chat)
+	pub extra_body: Option<serde_json::Value>,
+	/// sent with every request
+	pub headers: HashMap<String, String>,
+	/// requests wait for the rate limits kept here
+	pub rate_limits: Option<ratelimit::RateLimitStore>,
+	pub transport: Transport,
+}
+
+/// Assembles a `Message` from the `data:` chunks of a streamed (SSE)
+/// chat completion, tool call deltas are merged by their index.
+pub struct StreamAssembler {
+	role: Option<String>,
+	content: Option<String>,
+	tool_calls: Vec<ToolCall>,
+	model: Option<String>,
+	usage: Option<Usage>,
+	system_fingerprint: Option<String>,
+	finish_reason: Option<String>,
+}
+
+impl StreamAssembler {
+	pub fn new() -> Self {
+		StreamAssembler{ role: None, content: None, tool_calls: Vec::new(), model: None, usage: None, system_fingerprint: None, finish_reason: None }
+	}
+
+	/// Parse a single chunk (the text after `data:`) and return any content
+	/// that should be shown to the user.
+	pub fn push_chunk(&mut self, data: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+		let json: serde_json::Value = serde_json::from_str(data)?;
+		if let Some(model) = json.get("model").and_then(|m| m.as_str()) {
+			self.model = Some(model.to_string());
+		}
+		if let Some(fingerprint) = json.get("system_fingerprint").and_then(|f| f.as_str()) {
+			self.system_fingerprint = Some(fingerprint.to_string());
+		}
+		// only sent (in a chunk without choices) when asked for with stream_options
+		if let Some(usage) = json.get("usage").filter(|u| !u.is_null()) {
+			self.usage = Some(serde_json::from_value(usage.clone())?);
+		}
+		if let Some(finish_reason) = json.pointer("/choices/0/finish_reason").and_then(|f| f.as_str()) {
+			self.finish_reason = Some(finish_reason.to_string());
+		}
+		let delta = match json.get("choices").and_then(|c| c.get(0)).and_then(|c| c.get("delta")) {
+			Some(delta) => delta,
+			None => return Ok(None),
+		};
+		if let Some(role) = delta.get("role").and_then(|r| r.as_str()) {
+			self.role = Some(role.to_string());
+		}
+		if let Some(tool_calls) = delta.get("tool_calls").and_then(|t| t.as_array()) {
+			for tool_call in tool_calls {
+				let index = tool_call.get("index").and_then(|i| i.as_u64()).unwrap_or(0) as usize;
+				while self.tool_calls.len() <= index {
+					self.tool_calls.push(ToolCall{
+						id: String::new(),
+						tool_type: "function".to_string(),
+						function: FunctionCall{ name: String::new(), arguments: String::new() },
+					});
+				}
+				let current = &mut self.tool_calls[index];
+				if let Some(id) = tool_call.get("id").and_then(|i| i.as_str()) {
+					current.id.push_str(id);
+				}
+				if let Some(tool_type) = tool_call.get("type").and_then(|t| t.as_str()) {
+					current.tool_type = tool_type.to_string();
+				}
+				if let Some(function) = tool_call.get("function") {
+					if let Some(name) = function.get("name").and_then(|n| n.as_str()) {
+						current.function.name.push_str(name);
+					}
+					if let Some(arguments) = function.get("arguments").and_then(|a| a.as_str()) {
+						current.function.arguments.push_str(arguments);
+					}
+				}
+			}
+		}
+		match delta.get("content").and_then(|c| c.as_str()) {
+			Some(content) => {
+				self.content.get_or_insert_with(String::new).push_str(content);
+				Ok(Some(content.to_string()))
+			},
+			None => Ok(None),
+		}
+	}
+
+	pub fn finish(self) -> Message {
+		Message{
+			role: self.role.unwrap_or("assistant".to_string()),
+			content: self.content.map(MessageContent::Text),
+			name: None,
+			tool_call_id: None,
+			tool_calls: if self.tool_calls.is_empty() { None } else { Some(self.tool_calls) },
+			created_at: None,
+			model: self.model,
+			usage: self.usage,
+			system_fingerprint: self.system_fingerprint,
+			truncated: None,
+			finish_reason: self.finish_reason,
+		}
+	}
 }
 
 impl ChatContext {
 	pub fn new(config_dir: PathBuf, chats_dir: PathBuf, post_url: String, api_key: String) -> Result<Self, ChatError> {
+		Self::with_store(Box::new(JsonDirStore::new(config_dir, chats_dir)), post_url, api_key)
+	}
+
+	pub fn with_store(store: Box<dyn ChatStore>, post_url: String, api_key: String) -> Result<Self, ChatError> {
 		Ok(ChatContext {
 			chat: None,
 			chat_id: None,
-			config_dir: config_dir,
-			chats_dir: chats_dir,
+			store: store,
 			api_key: api_key,
 			post_url: url::Url::parse(&post_url)?,
 			dirty: true,
 			write_req_resp: false,
+			stream: false,
+			backend: Backend::Azure,
+			compact_at: None,
+			tokenizer: tokens::Tokenizer::Approximate,
+			budget: None,
+			overrides: SamplingOverrides::default(),
+			extra_body: None,
+			headers: HashMap::new(),
+			rate_limits: None,
+			transport: Transport::default(),
 		})
 	}
 
 	pub fn new_chat(&mut self, chat_id: &str) -> Result<(), Box<dyn std::error::Error>> {
-		let mut empty_chat_file: PathBuf = self.config_dir.clone();
-		empty_chat_file.push("empty_chat.json");
-		println!("Loading template from: {}", empty_chat_file.display());
-		let empty_chat = helpers::read_from_json::<Chat>(empty_chat_file)?;
-		self.chat = Some(empty_chat);
+		self.chat = Some(self.store.new_chat(chat_id)?);
 		self.chat_id = Some(chat_id.to_string());
-		let serialised = serde_json::to_string_pretty(&self.chat)?;
-		println!("Serialised Chat: {}", serialised);
-		// if the chats_dir is not found then an error will be sent from this line (the ? operator)
-		let md = fs::metadata(&self.chats_dir)?;
-		if md.permissions().readonly() {
-			Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Cannot write to chats_dir")))
-		} else {
-			//Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Just stop executing here")))
-			Ok(())
-		}
+		self.dirty = true;
+		Ok(())
 	}
 
 	pub fn save_chat(&mut self) -> Result<(), Box<dyn std::error::Error>> {
 		if self.dirty {
-			let mut chat_file: PathBuf = self.chats_dir.clone();
 			if let (Some(chat_id), Some(chat)) = (&self.chat_id, &self.chat) {
-				chat_file.push(chat_id.to_string() + ".json");
-				Ok(helpers::save_to_json(chat_file, chat)?)
+				self.store.save(chat_id, chat)
 			} else {
 				Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "No chat id")))
 			}
@@ -190,23 +737,17 @@ impl ChatContext {
 	}
 
 	pub fn load_chat(&mut self, chat_id: &str) -> Result<(), Box<dyn std::error::Error>> {
-		let mut chat_file: PathBuf = self.chats_dir.clone();
-		chat_file.push(chat_id.to_string() + ".json");
-		match helpers::read_from_json::<Chat>(chat_file) {
-			Ok(chat) => {
-				self.chat = Some(chat);
-				self.chat_id = Some(chat_id.to_string());
-				self.dirty = false;
-				Ok(())
-			},
-			Err(err) => { return Err(Box::new(err)); },
-		}
+		self.chat = Some(self.store.load(chat_id)?);
+		self.chat_id = Some(chat_id.to_string());
+		self.dirty = false;
+		Ok(())
 	}
 
 	pub fn load_or_new_chat(&mut self, chat_id: &str) -> Result<(), Box<dyn std::error::Error>> {
 	//pub fn load_or_new_chat(&mut self, chat_id: &str) -> Result<(), ChatError> {
-		if (self.load_chat(&chat_id).is_ok()) {
-			Ok(())
+		// a chat that cannot be read (or decrypted) is reported, not replaced
+		if self.store.exists(chat_id) {
+			self.load_chat(&chat_id)
 		} else {
 			self.new_chat(&chat_id)
 		}
@@ -219,96 +760,358 @@ impl ChatContext {
 		}
 	}
 
-	pub fn get_last_tool_call_id(&self) -> Result<String, ChatError> {
-		let mut tool_call_id: Option<String> = None;
-		let mut tool_call_ids: Vec<String> = Vec::new();
+	/// The tool calls made by the assistant that do not yet have a response, in the order they were made
+	pub fn get_pending_tool_calls(&self) -> Result<Vec<ToolCall>, ChatError> {
+		let mut pending: Vec<ToolCall> = Vec::new();
 		for message in &self.chat.as_ref().ok_or(ChatError::new(ChatErrorKind::ChatContainsNoMessages, "No Messages"))?.messages {
-			if Option::is_some(&message.tool_calls) {
-				let tool_calls = message.tool_calls.as_ref();
-				for tool_call in tool_calls.ok_or(ChatError::new(ChatErrorKind::Other, "No Tool Calls"))? {
-					tool_call_ids.push(tool_call.id.clone());
+			if let Some(tool_calls) = message.tool_calls.as_ref() {
+				for tool_call in tool_calls {
+					pending.push(tool_call.clone());
 				}
 			}
-			if Option::is_some(&message.tool_call_id) {
-				tool_call_ids.retain(|s| s != message.tool_call_id.as_ref().unwrap());
+			if let Some(tool_call_id) = message.tool_call_id.as_ref() {
+				pending.retain(|t| &t.id != tool_call_id);
 			}
 		}
-		if let Some(last_tool_call_id) = tool_call_ids.first() {
-			Ok(last_tool_call_id.to_string())
+		Ok(pending)
+	}
+
+	pub fn get_last_tool_call_id(&self) -> Result<String, ChatError> {
+		if let Some(last_tool_call) = self.get_pending_tool_calls()?.first() {
+			Ok(last_tool_call.id.to_string())
 		} else {
 			Err(ChatError::new(ChatErrorKind::LastToolCallIdNotFound, "No Last Tool Call ID Found"))
 		}
 	}
 
-	pub fn add_message(&mut self, message: Message) -> Result<(), Box<dyn std::error::Error>> {
-		self.current_chat()?.messages.push(message);
+	/// Remove the last `count` messages, when that removes the response to a
+	/// tool call the assistant message that made it (and the responses after
+	/// it) is removed too. Returns the number of messages removed.
+	pub fn rewind(&mut self, count: usize) -> Result<usize, ChatError> {
+		let pending_before: Vec<String> = self.get_pending_tool_calls()?.into_iter().map(|t| t.id).collect();
+		let messages = &mut self.chat.as_mut().ok_or(ChatError::new(ChatErrorKind::ChatContainsNoMessages, "No Messages"))?.messages;
+		let before = messages.len();
+		messages.truncate(before.saturating_sub(count));
+		while self.get_pending_tool_calls()?.iter().any(|t| !pending_before.contains(&t.id)) {
+			let messages = &mut self.chat.as_mut().unwrap().messages;
+			match messages.iter().rposition(|message| message.tool_calls.is_some()) {
+				Some(index) => messages.truncate(index),
+				None => break,
+			}
+		}
+		let removed = before - self.chat.as_ref().unwrap().messages.len();
+		if removed > 0 {
+			self.dirty = true;
+		}
+		Ok(removed)
+	}
+
+	/// Check that every tool response answers a tool call made earlier in
+	/// `messages` that has not already been answered.
+	pub fn validate_tool_calls(messages: &[Message]) -> Result<(), ChatError> {
+		let mut pending: Vec<&str> = Vec::new();
+		for (index, message) in messages.iter().enumerate() {
+			if let Some(tool_calls) = message.tool_calls.as_ref() {
+				pending.extend(tool_calls.iter().map(|t| t.id.as_str()));
+			}
+			if let Some(tool_call_id) = message.tool_call_id.as_ref() {
+				match pending.iter().position(|id| id == tool_call_id) {
+					Some(position) => { pending.remove(position); },
+					None => return Err(ChatError::new(ChatErrorKind::Other, &format!("Message {} responds to tool call {} which is not pending", index, tool_call_id))),
+				}
+			}
+		}
 		Ok(())
 	}
 
-	pub fn add_normal_message(&mut self, role: &str, message: &str) -> Result<(), Box<dyn std::error::Error>> {
-		self.current_chat()?.messages.push(Message::normal(role.to_string(), message.to_string()));
+	/// Apply `change` to a copy of the messages and keep it only when the
+	/// tool calls and their responses still match.
+	fn change_messages<F>(&mut self, change: F) -> Result<(), ChatError> where F: FnOnce(&mut Vec<Message>) -> Result<(), ChatError> {
+		let chat = self.chat.as_mut().ok_or(ChatError::new(ChatErrorKind::ChatContainsNoMessages, "No Messages"))?;
+		let mut messages = chat.messages.clone();
+		change(&mut messages)?;
+		Self::validate_tool_calls(&messages)?;
+		chat.messages = messages;
 		self.dirty = true;
 		Ok(())
 	}
 
-	pub fn add_tool_message(&mut self, role: &str, name: &str, tool_call_id: Option<&str>, message: &str) -> Result<(), Box<dyn std::error::Error>> {
-		let message = match tool_call_id {
-			Some(tool_call_str) => {
-				Message{
-					role: role.to_string(),
-					name: Some(name.to_string()),
-					tool_call_id: Some(tool_call_str.to_string()),
-					content: Some(message.to_string()),
-					tool_calls: None,
-				}
-			},
-			None => {
-				Message{
-					role: role.to_string(),
-					name: Some(name.to_string()),
-					tool_call_id: Some(self.get_last_tool_call_id()?),
-					content: Some(message.to_string()),
-					tool_calls: None,
-				}
+	/// Replace the content of the message at `index` (from 0)
+	pub fn edit_message(&mut self, index: usize, content: &str) -> Result<(), ChatError> {
+		self.change_messages(|messages| {
+			let count = messages.len();
+			let message = messages.get_mut(index).ok_or(ChatError::new(ChatErrorKind::Other, &format!("No message {}, the chat has {} messages", index, count)))?;
+			message.content = Some(MessageContent::Text(content.to_string()));
+			Ok(())
+		})
+	}
+
+	/// Remove the message at `index` (from 0), an assistant message with tool
+	/// calls cannot be removed while the responses to them remain.
+	pub fn delete_message(&mut self, index: usize) -> Result<(), ChatError> {
+		self.change_messages(|messages| {
+			if index >= messages.len() {
+				return Err(ChatError::new(ChatErrorKind::Other, &format!("No message {}, the chat has {} messages", index, messages.len())));
 			}
-		};
+			messages.remove(index);
+			Ok(())
+		})
+	}
+
+	/// Set the system message at the start of the chat, one that is already
+	/// there is only replaced when `replace` is true
+	pub fn set_system_message(&mut self, content: &str, replace: bool) -> Result<(), ChatError> {
+		self.change_messages(|messages| {
+			if !replace && messages.first().map(|message| message.role == "system").unwrap_or(false) {
+				return Err(ChatError::new(ChatErrorKind::Other, "The chat already has a system message, use --force to replace it"));
+			}
+			put_system_message(messages, content.to_string());
+			Ok(())
+		})
+	}
+
+	/// Save the sampling parameters in the chat
+	pub fn set_sampling(&mut self, overrides: &SamplingOverrides) -> Result<(), Box<dyn std::error::Error>> {
+		overrides.apply(self.current_chat()?);
+		self.dirty = true;
+		Ok(())
+	}
+
+	/// Add a message to the chat, stamped with the time unless it already has one
+	pub fn add_message(&mut self, mut message: Message) -> Result<(), Box<dyn std::error::Error>> {
+		message.created_at.get_or_insert_with(now);
 		self.current_chat()?.messages.push(message);
 		self.dirty = true;
 		Ok(())
 	}
 
+	pub fn add_normal_message(&mut self, role: &str, message: &str) -> Result<(), Box<dyn std::error::Error>> {
+		self.add_message(Message::normal(role.to_string(), message.to_string()))
+	}
+
+	/// Add a message with text and image parts
+	pub fn add_content_message(&mut self, role: &str, content: MessageContent) -> Result<(), Box<dyn std::error::Error>> {
+		self.add_message(Message::normal(role.to_string(), content))
+	}
+
+	pub fn add_tool_message(&mut self, role: &str, name: &str, tool_call_id: Option<&str>, message: &str) -> Result<(), Box<dyn std::error::Error>> {
+		let tool_call_id = match tool_call_id {
+			Some(tool_call_str) => tool_call_str.to_string(),
+			None => self.get_last_tool_call_id()?,
+		};
+		self.add_message(Message::tool_response(role.to_string(), name.to_string(), tool_call_id, message.to_string()))
+	}
+
 	pub async fn call_api(&mut self) -> Result<String, Box<dyn std::error::Error>> {
-		let serialised = serde_json::to_string_pretty(&self.chat)?;
-		if self.write_req_resp {
-			fs::write("last_request.json", &serialised)?;
-		}
 		if let Err(err) = self.get_last_tool_call_id() {
 			if ! matches!(err.kind, ChatErrorKind::LastToolCallIdNotFound) {
 				return Err(Box::new(err));
 			}
 		}
-		let url = self.post_url.clone();
-		let client = reqwest::Client::new();
-		let req = client
-			.post(url)
-			.header("api-key", &self.api_key)
-			.header(CONTENT_TYPE, "application/json")
-			.body(serialised)
-			.send()
-			.await?;
-		let body = req.text().await?;
-		if self.write_req_resp {
-			fs::write("last_response.json", &body)?;
+		// checked before compacting, the summary is paid for too
+		if let (Some(budget), Some(chat)) = (self.budget.as_ref(), self.chat.as_ref()) {
+			budget.check(chat).map_err(|message| std::io::Error::new(std::io::ErrorKind::Other, message))?;
+		}
+		if let Some(limit) = self.compact_at {
+			if self.prompt_tokens() > limit {
+				let removed = self.compact(COMPACT_KEEP).await?;
+				eprintln!("Compacted {} message(s) into a summary", removed);
+			}
 		}
-		let response = Self::parse_response(&body)?;
-		let content = match response.content.as_ref() {
-			Some(content) => content.to_string(),
+		let mut chat = self.chat.clone().ok_or(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Chat not present in context")))?;
+		self.overrides.apply(&mut chat);
+		let mut response = self.send(&chat, self.stream).await?;
+		let content = match response.text() {
+			Some(content) => content.into_owned(),
 			None => "".to_string(),
 		};
-		self.chat.as_mut().ok_or(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Chat not present in context")))?.messages.push(response);
+		response.model.get_or_insert_with(|| chat.model.clone());
+		let truncated = response.truncated.unwrap_or(false);
+		self.add_message(response)?;
+		if truncated {
+			return Err(interrupted());
+		}
 		Ok(content)
 	}
 
+	/// The tokens the chat will be sent as: the usage reported with the last
+	/// response plus the count of the messages after it, or the count of
+	/// the whole chat when no usage has been recorded or messages were
+	/// added before the response since (e.g. a summary by `compact`).
+	pub fn prompt_tokens(&self) -> usize {
+		let chat = match self.chat.as_ref() {
+			Some(chat) => chat,
+			None => return 0,
+		};
+		// the usage of a summary is that of the summary request
+		let last = chat.messages.iter().rposition(|message| message.usage.is_some() && message.role != "system");
+		match last {
+			Some(index) if chat.messages[..index].iter().all(|message| message.created_at <= chat.messages[index].created_at) => {
+				let usage = chat.messages[index].usage.as_ref().unwrap();
+				let after: usize = chat.messages[index + 1..].iter().map(|message| self.tokenizer.count_message(message)).sum();
+				usage.total_tokens as usize + after + tokens::REPLY_OVERHEAD
+			},
+			_ => self.tokenizer.count_chat(chat),
+		}
+	}
+
+	/// Send `chat` to the endpoint and return the response message
+	async fn send(&self, chat: &Chat, stream: bool) -> Result<Message, Box<dyn std::error::Error>> {
+		let request = match self.backend {
+			Backend::Azure | Backend::OpenAi => {
+				let mut request = serde_json::to_value(chat)?;
+				if let Some(object) = request.as_object_mut() {
+					for field in LOCAL_FIELDS {
+						object.remove(*field);
+					}
+					// refused by the API when no tools are sent
+					if chat.tools.as_ref().map(|tools| tools.is_empty()).unwrap_or(true) {
+						object.remove("parallel_tool_calls");
+					}
+				}
+				let messages: Vec<RequestMessage> = chat.messages.iter().map(RequestMessage::from).collect();
+				request["messages"] = serde_json::to_value(messages)?;
+				if stream {
+					request["stream"] = serde_json::Value::Bool(true);
+					// without this the usage is not sent when streaming
+					if self.backend == Backend::OpenAi {
+						request["stream_options"] = serde_json::json!({"include_usage": true});
+					}
+				}
+				request
+			},
+			Backend::Ollama => ollama::make_request(chat, stream)?,
+		};
+		// the chat is more specific than the profile
+		let mut request = request;
+		for extra_body in [self.extra_body.as_ref(), chat.extra_body.as_ref()].into_iter().flatten() {
+			merge_json(&mut request, extra_body);
+		}
+		let serialised = serde_json::to_string_pretty(&request)?;
+		if self.write_req_resp {
+			fs::write("last_request.json", &serialised)?;
+		}
+		if let Some(rate_limits) = self.rate_limits.as_ref() {
+			let tokens = self.tokenizer.count_chat(chat) as u64;
+			match rate_limits.load().delay(tokens, now()) {
+				Some(delay) if delay <= ratelimit::MAX_DELAY => {
+					eprintln!("Waiting {}s for the rate limit to reset", delay);
+					tokio::time::sleep(std::time::Duration::from_secs(delay)).await;
+				},
+				Some(delay) => eprintln!("The rate limit resets in {}s, the request may be refused", delay),
+				None => {},
+			}
+		}
+		let url = self.post_url.clone();
+		let builder = self.transport.client
+			.post(url)
+			.header(CONTENT_TYPE, "application/json");
+		// dropping the request on Ctrl-C closes the connection
+		let mut interrupts = interrupts();
+		let req = tokio::select! {
+			req = self.transport.execute(authorise(builder, &self.backend, &self.api_key, &self.headers).body(serialised)) => req?,
+			_ = interrupts.changed() => return Err(interrupted()),
+		};
+		if let (Some(rate_limits), Some(limit)) = (self.rate_limits.as_ref(), ratelimit::RateLimit::from_headers(req.headers(), now())) {
+			// the response is still used when the limits cannot be kept
+			if let Err(err) = rate_limits.save(&limit) {
+				eprintln!("Cannot save the rate limits: {}", err);
+			}
+		}
+		// an error is a JSON object describing it, not a chat completion
+		let status = req.status();
+		if !status.is_success() {
+			let body = tokio::select! {
+				body = req.text() => body?,
+				_ = interrupts.changed() => return Err(interrupted()),
+			};
+			return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("{} from {}: {}", status, self.post_url, body))));
+		}
+		if stream {
+			self.read_stream(req, &mut interrupts).await
+		} else {
+			let body = tokio::select! {
+				body = req.text() => body?,
+				_ = interrupts.changed() => return Err(interrupted()),
+			};
+			if self.write_req_resp {
+				fs::write("last_response.json", &body)?;
+			}
+			match self.backend {
+				Backend::Azure | Backend::OpenAi => Self::parse_response(&body),
+				Backend::Ollama => ollama::parse_response(&body),
+			}
+		}
+	}
+
+	async fn read_stream(&self, mut resp: Response, interrupts: &mut tokio::sync::watch::Receiver<u64>) -> Result<Message, Box<dyn std::error::Error>> {
+		let mut assembler = StreamAssembler::new();
+		let mut buffer: Vec<u8> = Vec::new();
+		let mut raw = String::new();
+		let mut stdout = std::io::stdout();
+		let mut cancelled = false;
+		'chunks: loop {
+			let chunk = tokio::select! {
+				chunk = resp.chunk() => chunk?,
+				_ = interrupts.changed() => {
+					cancelled = true;
+					break 'chunks;
+				},
+			};
+			let chunk = match chunk {
+				Some(chunk) => chunk,
+				None => break,
+			};
+			buffer.extend_from_slice(&chunk);
+			// only complete lines are parsed, a multi-byte character may be split between chunks
+			while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+				let line = String::from_utf8(buffer.drain(..=pos).collect())?;
+				raw.push_str(&line);
+				let (content, done) = match self.backend {
+					Backend::Azure | Backend::OpenAi => {
+						let data = match line.trim().strip_prefix("data:") {
+							Some(data) => data.trim(),
+							None => continue,
+						};
+						if data == "[DONE]" {
+							(None, true)
+						} else {
+							(assembler.push_chunk(data)?, false)
+						}
+					},
+					Backend::Ollama => {
+						if line.trim().is_empty() {
+							continue;
+						}
+						assembler.push_ollama_chunk(line.trim())?
+					},
+				};
+				if let Some(content) = content {
+					print!("{}", content);
+					stdout.flush()?;
+				}
+				if done {
+					break 'chunks;
+				}
+			}
+		}
+		println!();
+		if self.write_req_resp {
+			fs::write("last_response.json", &raw)?;
+		}
+		let mut message = assembler.finish();
+		if cancelled {
+			// the arguments of a partial tool call cannot be used
+			message.tool_calls = None;
+			if message.content.as_ref().map(|content| content.is_empty()).unwrap_or(true) {
+				return Err(interrupted());
+			}
+			eprintln!("Interrupted, the partial response is saved marked as truncated");
+			message.truncated = Some(true);
+		}
+		Ok(message)
+	}
+
 	pub fn parse_response(response: &str) -> Result<Message, Box<dyn std::error::Error>> {
 		let mut json: serde_json::Value = serde_json::from_str(&response)?;
 		let mut message = if let Some(mut mesg) = json
@@ -319,7 +1122,14 @@ impl ChatContext {
 		} else {
 			return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "No message in the choices element 0")));
 		};
-		let res: Message = serde_json::from_value(message)?;
+		let mut res: Message = serde_json::from_value(message)?;
+		res.model = json.get("model").and_then(|m| m.as_str()).map(|m| m.to_string());
+		res.system_fingerprint = json.get("system_fingerprint").and_then(|f| f.as_str()).map(|f| f.to_string());
+		res.finish_reason = json.pointer("/choices/0/finish_reason").and_then(|f| f.as_str()).map(|f| f.to_string());
+		res.usage = match json.get("usage").filter(|u| !u.is_null()) {
+			Some(usage) => Some(serde_json::from_value(usage.clone())?),
+			None => None,
+		};
 		Ok(res)
 	}
 }
@@ -0,0 +1,133 @@
+// Conversion between the stored chat (OpenAI shape) and the native
+// Ollama /api/chat request and response objects.
+//
+// See: https://github.com/ollama/ollama/blob/main/docs/api.md#generate-a-chat-completion
+
+use serde_json::{json, Value};
+
+use super::{Chat, FunctionCall, Message, StreamAssembler, ToolCall, Usage};
+
+pub fn make_request(chat: &Chat, stream: bool) -> Result<Value, Box<dyn std::error::Error>> {
+	let mut messages = Vec::new();
+	for message in chat.messages.iter() {
+		let mut mesg = json!({
+			"role": message.role,
+			"content": message.text().unwrap_or_default(),
+		});
+		// Ollama takes the images base64 encoded, it cannot fetch remote ones
+		let images: Vec<&str> = message.content.iter().flat_map(|content| content.images())
+			.filter_map(|image| image.url.strip_prefix("data:").and_then(|data| data.split_once(";base64,")).map(|(_, data)| data))
+			.collect();
+		if !images.is_empty() {
+			mesg["images"] = json!(images);
+		}
+		if let Some(name) = message.name.as_ref() {
+			mesg["tool_name"] = json!(name);
+		}
+		if let Some(tool_calls) = message.tool_calls.as_ref() {
+			let mut calls = Vec::new();
+			for tool_call in tool_calls.iter() {
+				// Ollama sends and expects the arguments as an object, not a string
+				let arguments: Value = serde_json::from_str(&tool_call.function.arguments)?;
+				calls.push(json!({
+					"function": { "name": tool_call.function.name, "arguments": arguments },
+				}));
+			}
+			mesg["tool_calls"] = Value::Array(calls);
+		}
+		messages.push(mesg);
+	}
+	let mut request = json!({
+		"model": chat.model,
+		"messages": messages,
+		"stream": stream,
+		"options": {},
+	});
+	let options = [
+		("num_predict", json!(chat.max_tokens)),
+		("temperature", json!(chat.temperature)),
+		("top_p", json!(chat.top_p)),
+		("frequency_penalty", json!(chat.frequency_penalty)),
+		("presence_penalty", json!(chat.presence_penalty)),
+		("stop", json!(chat.stop)),
+		("seed", json!(chat.seed)),
+	];
+	for (name, value) in options {
+		if !value.is_null() {
+			request["options"][name] = value;
+		}
+	}
+	// Ollama takes "json" or the schema itself
+	if let Some(response_format) = chat.response_format.as_ref() {
+		match response_format.get("json_schema").and_then(|schema| schema.get("schema")) {
+			Some(schema) => request["format"] = schema.clone(),
+			None if chat.wants_json() => request["format"] = json!("json"),
+			None => {},
+		}
+	}
+	if let Some(tools) = chat.tools.as_ref() {
+		request["tools"] = serde_json::to_value(tools)?;
+	}
+	Ok(request)
+}
+
+pub fn parse_response(response: &str) -> Result<Message, Box<dyn std::error::Error>> {
+	let mut assembler = StreamAssembler::new();
+	assembler.push_ollama_chunk(response)?;
+	Ok(assembler.finish())
+}
+
+impl StreamAssembler {
+	/// Parse one line of the NDJSON stream returned by Ollama, returns the
+	/// content to show the user and whether this was the final line.
+	pub fn push_ollama_chunk(&mut self, line: &str) -> Result<(Option<String>, bool), Box<dyn std::error::Error>> {
+		let json: Value = serde_json::from_str(line)?;
+		if let Some(error) = json.get("error").and_then(|e| e.as_str()) {
+			return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, error.to_string())));
+		}
+		let done = json.get("done").and_then(|d| d.as_bool()).unwrap_or(false);
+		if let Some(model) = json.get("model").and_then(|m| m.as_str()) {
+			self.model = Some(model.to_string());
+		}
+		// the final line has the token counts
+		if done {
+			let prompt_tokens = json.get("prompt_eval_count").and_then(|c| c.as_u64()).unwrap_or(0);
+			let completion_tokens = json.get("eval_count").and_then(|c| c.as_u64()).unwrap_or(0);
+			self.usage = Some(Usage{ prompt_tokens: prompt_tokens, completion_tokens: completion_tokens, total_tokens: prompt_tokens + completion_tokens });
+			if let Some(done_reason) = json.get("done_reason").and_then(|r| r.as_str()) {
+				self.finish_reason = Some(done_reason.to_string());
+			}
+		}
+		let message = match json.get("message") {
+			Some(message) => message,
+			None => return Ok((None, done)),
+		};
+		if let Some(role) = message.get("role").and_then(|r| r.as_str()) {
+			self.role = Some(role.to_string());
+		}
+		if let Some(tool_calls) = message.get("tool_calls").and_then(|t| t.as_array()) {
+			for tool_call in tool_calls {
+				let function = tool_call.get("function").unwrap_or(&Value::Null);
+				let arguments = function.get("arguments").unwrap_or(&Value::Null);
+				// Ollama does not give tool calls an id, one is needed to match the
+				// responses and must not repeat one of an earlier reply in the chat
+				let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_micros()).unwrap_or(0);
+				self.tool_calls.push(ToolCall{
+					id: format!("call_{}_{}", time, self.tool_calls.len()),
+					tool_type: "function".to_string(),
+					function: FunctionCall{
+						name: function.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string(),
+						arguments: serde_json::to_string(arguments)?,
+					},
+				});
+			}
+		}
+		match message.get("content").and_then(|c| c.as_str()) {
+			Some(content) if !content.is_empty() => {
+				self.content.get_or_insert_with(String::new).push_str(content);
+				Ok((Some(content.to_string()), done))
+			},
+			_ => Ok((None, done)),
+		}
+	}
+}
@@ -0,0 +1,114 @@
+// The rate limits reported in the x-ratelimit-* headers of OpenAI (and
+// compatible) responses, kept per profile so that a request that would
+// be refused is delayed until the limit resets instead.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use reqwest::header::HeaderMap;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::helpers;
+
+/// The longest a request is delayed, a longer reset is only warned about
+pub const MAX_DELAY: u64 = 60;
+
+/// The state of the limits after the last response, times are seconds since the epoch
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct RateLimit {
+	pub remaining_requests: Option<u64>,
+	pub remaining_tokens: Option<u64>,
+	pub requests_reset_at: Option<u64>,
+	pub tokens_reset_at: Option<u64>,
+}
+
+/// Parse a reset duration such as `1s`, `6m0s`, `20ms` or `1h2m3.5s` as seconds
+pub fn parse_duration(text: &str) -> Option<f64> {
+	let mut seconds = 0.0;
+	let mut number = String::new();
+	let mut chars = text.trim().chars().peekable();
+	if chars.peek().is_none() {
+		return None;
+	}
+	while let Some(c) = chars.next() {
+		if c.is_ascii_digit() || c == '.' {
+			number.push(c);
+			continue;
+		}
+		let value: f64 = number.parse().ok()?;
+		number.clear();
+		seconds += match c {
+			'h' => value * 3600.0,
+			'm' if chars.peek() == Some(&'s') => {
+				chars.next();
+				value / 1000.0
+			},
+			'm' => value * 60.0,
+			's' => value,
+			_ => return None,
+		};
+	}
+	// a number without a unit is seconds
+	if !number.is_empty() {
+		seconds += number.parse::<f64>().ok()?;
+	}
+	Some(seconds)
+}
+
+impl RateLimit {
+	/// The limits in the headers of a response, None when there are none
+	pub fn from_headers(headers: &HeaderMap, now: u64) -> Option<Self> {
+		let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(|value| value.to_string());
+		let number = |name: &str| header(name).and_then(|value| value.trim().parse::<u64>().ok());
+		let reset = |name: &str| header(name).and_then(|value| parse_duration(&value)).map(|seconds| now + seconds.ceil() as u64);
+		let limit = RateLimit{
+			remaining_requests: number("x-ratelimit-remaining-requests"),
+			remaining_tokens: number("x-ratelimit-remaining-tokens"),
+			requests_reset_at: reset("x-ratelimit-reset-requests"),
+			tokens_reset_at: reset("x-ratelimit-reset-tokens"),
+		};
+		if limit == RateLimit::default() { None } else { Some(limit) }
+	}
+
+	/// How long to wait before sending a request of about `tokens` tokens,
+	/// None when it can be sent now
+	pub fn delay(&self, tokens: u64, now: u64) -> Option<u64> {
+		let requests = match (self.remaining_requests, self.requests_reset_at) {
+			(Some(0), Some(reset_at)) if reset_at > now => reset_at - now,
+			_ => 0,
+		};
+		let tokens = match (self.remaining_tokens, self.tokens_reset_at) {
+			(Some(remaining), Some(reset_at)) if remaining < tokens && reset_at > now => reset_at - now,
+			_ => 0,
+		};
+		match requests.max(tokens) {
+			0 => None,
+			delay => Some(delay),
+		}
+	}
+}
+
+/// The limits of each profile in a JSON file
+pub struct RateLimitStore {
+	path: PathBuf,
+	profile: String,
+}
+
+impl RateLimitStore {
+	pub fn new(path: PathBuf, profile: &str) -> Self {
+		RateLimitStore{ path: path, profile: profile.to_string() }
+	}
+
+	fn read_all(&self) -> HashMap<String, RateLimit> {
+		helpers::read_from_json(&self.path).unwrap_or_default()
+	}
+
+	pub fn load(&self) -> RateLimit {
+		self.read_all().remove(&self.profile).unwrap_or_default()
+	}
+
+	pub fn save(&self, limit: &RateLimit) -> Result<(), helpers::HelperError> {
+		let mut limits = self.read_all();
+		limits.insert(self.profile.clone(), limit.clone());
+		helpers::save_to_json(&self.path, &limits)
+	}
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::helpers;
+use super::Chat;
+
+/// The start of an encrypted chat file, followed by the nonce and the ciphertext
+pub const ENCRYPTED_MAGIC: &[u8] = b"openaiclient-chacha20poly1305\n";
+const NONCE_LENGTH: usize = 12;
+
+/// A ChaCha20-Poly1305 key for the chat files
+#[derive(Clone)]
+pub struct ChatKey([u8; 32]);
+
+impl ChatKey {
+	/// Parse a key written as 64 hexadecimal digits
+	pub fn from_hex(hex: &str) -> Result<Self, Error> {
+		let hex = hex.trim();
+		let invalid = || Error::new(ErrorKind::InvalidInput, "the chat key must be 64 hexadecimal digits");
+		if hex.len() != 64 || !hex.is_ascii() {
+			return Err(invalid());
+		}
+		let mut key = [0u8; 32];
+		for (index, byte) in key.iter_mut().enumerate() {
+			*byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid())?;
+		}
+		Ok(ChatKey(key))
+	}
+
+	pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+		let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.0));
+		let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+		let ciphertext = cipher.encrypt(&nonce, plaintext).map_err(|_| Error::new(ErrorKind::Other, "encryption failed"))?;
+		let mut data = ENCRYPTED_MAGIC.to_vec();
+		data.extend_from_slice(&nonce);
+		data.extend_from_slice(&ciphertext);
+		Ok(data)
+	}
+
+	/// Decrypt the content of an encrypted file (starting with `ENCRYPTED_MAGIC`)
+	pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+		let data = data.strip_prefix(ENCRYPTED_MAGIC).ok_or(Error::new(ErrorKind::InvalidData, "not an encrypted chat"))?;
+		if data.len() < NONCE_LENGTH {
+			return Err(Error::new(ErrorKind::InvalidData, "encrypted chat is too short"));
+		}
+		let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+		let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.0));
+		cipher.decrypt(Nonce::from_slice(nonce), ciphertext).map_err(|_| Error::new(ErrorKind::InvalidData, "cannot decrypt the chat (wrong key?)"))
+	}
+}
+
+/// Read a chat file, encrypted files are decrypted with `key` and plain
+/// JSON files are read as they are.
+pub fn read_chat_file(path: &Path, key: Option<&ChatKey>) -> Result<Chat, helpers::HelperError> {
+	let data = fs::read(path)?;
+	if !data.starts_with(ENCRYPTED_MAGIC) {
+		return Ok(serde_json::from_slice(&data)?);
+	}
+	let key = key.ok_or(Error::new(ErrorKind::PermissionDenied, format!("{} is encrypted and no chat key is configured", path.display())))?;
+	Ok(serde_json::from_slice(&key.decrypt(&data)?)?)
+}
+
+/// Write a chat file, encrypted when there is a key
+pub fn write_chat_file(path: &Path, chat: &Chat, key: Option<&ChatKey>) -> Result<(), helpers::HelperError> {
+	match key {
+		Some(key) => {
+			let serialised = serde_json::to_vec_pretty(chat)?;
+			Ok(helpers::write_atomic(path, key.encrypt(&serialised)?)?)
+		},
+		None => helpers::save_to_json(path, chat),
+	}
+}
+
+/// The names of the chat templates in the `templates` directory of the config directory
+pub fn list_templates(config_dir: &Path) -> Result<Vec<String>, Error> {
+	let dir = config_dir.join("templates");
+	if !dir.exists() {
+		return Ok(Vec::new());
+	}
+	let mut names = Vec::new();
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		if helpers::has_specific_extension(&path, "json") {
+			if let Some(name) = path.file_stem() {
+				names.push(name.to_string_lossy().to_string());
+			}
+		}
+	}
+	names.sort();
+	Ok(names)
+}
+
+/// The file of a named chat template, an error listing the templates when there is none
+pub fn template_file(config_dir: &Path, name: &str) -> Result<PathBuf, Error> {
+	let path = config_dir.join("templates").join(format!("{}.json", name));
+	if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') || !path.is_file() {
+		let templates = list_templates(config_dir)?;
+		return Err(Error::new(ErrorKind::NotFound, format!("No template {} in {}, the templates are: {}",
+			name, config_dir.join("templates").display(), if templates.is_empty() { "none".to_string() } else { templates.join(", ") })));
+	}
+	Ok(path)
+}
+
+/// Where `ChatContext` loads and saves chats
+pub trait ChatStore {
+	fn exists(&self, chat_id: &str) -> bool;
+	/// The chat stored as `chat_id`, an error when there is none
+	fn load(&self, chat_id: &str) -> Result<Chat, Box<dyn std::error::Error>>;
+	fn save(&mut self, chat_id: &str, chat: &Chat) -> Result<(), Box<dyn std::error::Error>>;
+	/// The chat to start `chat_id` with (it is not saved until `save`)
+	fn new_chat(&self, chat_id: &str) -> Result<Chat, Box<dyn std::error::Error>>;
+}
+
+/// Chats stored as `<chat_id>.json` in the chats directory, new chats are
+/// copies of `empty_chat.json` in the config directory (or of a named
+/// template in its `templates` directory) with the system message made from
+/// the system prompt template (`SYSTEM_PROMPT.md` there unless another is
+/// given) when there is one, followed by the instructions of the project.
+/// The system message of a named template is kept as it is.
+pub struct JsonDirStore {
+	config_dir: PathBuf,
+	chats_dir: PathBuf,
+	template: Option<String>,
+	system_prompt: Option<PathBuf>,
+	/// the project the system prompt template comes from, when it is not the
+	/// user's own and so processed untrusted
+	prompt_project: Option<PathBuf>,
+	instructions: Option<PathBuf>,
+	/// when present the chats are saved encrypted
+	key: Option<ChatKey>,
+}
+
+impl JsonDirStore {
+	pub fn new(config_dir: PathBuf, chats_dir: PathBuf) -> Self {
+		JsonDirStore{ config_dir: config_dir, chats_dir: chats_dir, template: None, system_prompt: None, prompt_project: None, instructions: None, key: None }
+	}
+
+	/// New chats are copies of `templates/<name>.json` in the config directory
+	pub fn with_template(mut self, template: Option<String>) -> Self {
+		self.template = template;
+		self
+	}
+
+	/// The template of the system message of new chats, it must exist
+	pub fn with_system_prompt(mut self, system_prompt: Option<PathBuf>) -> Self {
+		self.system_prompt = system_prompt;
+		self
+	}
+
+	/// The directory of the project the system prompt template comes from,
+	/// for templates that are not the user's own (see `TemplateProcessor::untrusted`)
+	pub fn with_prompt_project(mut self, project_dir: Option<PathBuf>) -> Self {
+		self.prompt_project = project_dir;
+		self
+	}
+
+	/// A file (e.g. `AGENTS.md`) added to the system message of new chats
+	pub fn with_instructions(mut self, instructions: Option<PathBuf>) -> Self {
+		self.instructions = instructions;
+		self
+	}
+
+	pub fn with_key(mut self, key: Option<ChatKey>) -> Self {
+		self.key = key;
+		self
+	}
+
+	fn chat_file(&self, chat_id: &str) -> PathBuf {
+		self.chats_dir.join(chat_id.to_string() + ".json")
+	}
+}
+
+impl ChatStore for JsonDirStore {
+	fn exists(&self, chat_id: &str) -> bool {
+		self.chat_file(chat_id).exists()
+	}
+
+	fn load(&self, chat_id: &str) -> Result<Chat, Box<dyn std::error::Error>> {
+		Ok(read_chat_file(&self.chat_file(chat_id), self.key.as_ref())?)
+	}
+
+	fn save(&mut self, chat_id: &str, chat: &Chat) -> Result<(), Box<dyn std::error::Error>> {
+		Ok(write_chat_file(&self.chat_file(chat_id), chat, self.key.as_ref())?)
+	}
+
+	fn new_chat(&self, chat_id: &str) -> Result<Chat, Box<dyn std::error::Error>> {
+		let empty_chat_file = match self.template.as_ref() {
+			Some(name) => template_file(&self.config_dir, name)?,
+			None => self.config_dir.join("empty_chat.json"),
+		};
+		if !empty_chat_file.exists() {
+			return Err(Box::new(Error::new(ErrorKind::NotFound,
+				format!("The template of new chats {} does not exist, openaiclient init creates it", empty_chat_file.display()))));
+		}
+		eprintln!("Loading template from: {}", empty_chat_file.display());
+		let mut empty_chat = helpers::read_from_json::<Chat>(empty_chat_file)?;
+		let has_own_system = self.template.is_some() && empty_chat.messages.first().map(|message| message.role == "system").unwrap_or(false);
+		let system_prompt_file = self.system_prompt.clone().unwrap_or(self.config_dir.join("SYSTEM_PROMPT.md"));
+		if !has_own_system && (self.system_prompt.is_some() || system_prompt_file.exists()) {
+			let mut processor = helpers::TemplateProcessor::new(std::env::current_dir()?);
+			if let Some(project_dir) = self.prompt_project.as_ref() {
+				processor = processor.untrusted(project_dir);
+			}
+			processor.set_project_context();
+			processor.set("chat_id", chat_id);
+			processor.set("model", empty_chat.model.clone());
+			let template = fs::read_to_string(&system_prompt_file)
+				.map_err(|e| Error::new(e.kind(), format!("{}: {}", system_prompt_file.display(), e)))?;
+			let system_prompt = processor.process(&template)?;
+			empty_chat.set_system_message(system_prompt);
+		}
+		if let Some(instructions) = self.instructions.as_ref() {
+			let content = fs::read_to_string(instructions)
+				.map_err(|e| Error::new(e.kind(), format!("{}: {}", instructions.display(), e)))?;
+			empty_chat.append_system_message(content.trim());
+		}
+		let serialised = serde_json::to_string_pretty(&empty_chat)?;
+		eprintln!("Serialised Chat: {}", serialised);
+		// if the chats_dir is not found then an error will be sent from this line (the ? operator)
+		let md = fs::metadata(&self.chats_dir)?;
+		if md.permissions().readonly() {
+			Err(Box::new(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Cannot write to chats_dir")))
+		} else {
+			Ok(empty_chat)
+		}
+	}
+}
+
+/// Chats kept in memory (for tests), new chats are copies of the template
+pub struct MemoryStore {
+	pub chats: HashMap<String, Chat>,
+	template: Chat,
+}
+
+impl MemoryStore {
+	pub fn new(template: Chat) -> Self {
+		MemoryStore{ chats: HashMap::new(), template: template }
+	}
+}
+
+impl ChatStore for MemoryStore {
+	fn exists(&self, chat_id: &str) -> bool {
+		self.chats.contains_key(chat_id)
+	}
+
+	fn load(&self, chat_id: &str) -> Result<Chat, Box<dyn std::error::Error>> {
+		match self.chats.get(chat_id) {
+			Some(chat) => Ok(chat.clone()),
+			None => Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, format!("Chat {} not found", chat_id)))),
+		}
+	}
+
+	fn save(&mut self, chat_id: &str, chat: &Chat) -> Result<(), Box<dyn std::error::Error>> {
+		self.chats.insert(chat_id.to_string(), chat.clone());
+		Ok(())
+	}
+
+	fn new_chat(&self, _chat_id: &str) -> Result<Chat, Box<dyn std::error::Error>> {
+		Ok(self.template.clone())
+	}
+}
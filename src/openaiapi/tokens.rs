@@ -0,0 +1,162 @@
+// Counting the tokens of a chat before it is sent. With the tiktoken rank
+// file of the model's encoding (e.g. cl100k_base.tiktoken) in the config
+// directory the text is split with byte pair encoding like the OpenAI
+// tokenizer, otherwise the count is estimated from the length of the text.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use regex::Regex;
+
+use crate::helpers;
+use super::{Chat, Message};
+
+/// Tokens added for every message (the role and the separators)
+pub const MESSAGE_OVERHEAD: usize = 4;
+/// Tokens added for the start of the reply
+pub const REPLY_OVERHEAD: usize = 3;
+/// Tokens counted for an image, the size is not known so this is the cost
+/// of a 1024x1024 image at high detail
+pub const IMAGE_TOKENS: usize = 765;
+/// Tokens for an image sent with `detail` low, whatever its size
+pub const LOW_DETAIL_IMAGE_TOKENS: usize = 85;
+
+// the cl100k_base pattern without the look-ahead that the regex crate does
+// not support, only runs of whitespace may be split differently
+const SPLIT_PATTERN: &str = r"'(?i:[sdmt]|ll|ve|re)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+";
+
+pub enum Tokenizer {
+	/// byte pair encoding with the ranks of a tiktoken file
+	Bpe { ranks: HashMap<Vec<u8>, u32>, split: Regex },
+	/// about four characters per token
+	Approximate,
+}
+
+impl Default for Tokenizer {
+	fn default() -> Self {
+		Tokenizer::Approximate
+	}
+}
+
+/// The name of the encoding used by a model
+pub fn encoding_for_model(model: &str) -> &'static str {
+	if ["gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4"].iter().any(|prefix| model.starts_with(prefix)) {
+		"o200k_base"
+	} else {
+		"cl100k_base"
+	}
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+	let mut bytes = Vec::new();
+	let mut buffer: u32 = 0;
+	let mut bits = 0;
+	for c in text.bytes() {
+		let value = match c {
+			b'A'..=b'Z' => c - b'A',
+			b'a'..=b'z' => c - b'a' + 26,
+			b'0'..=b'9' => c - b'0' + 52,
+			b'+' => 62,
+			b'/' => 63,
+			b'=' => break,
+			_ => return None,
+		};
+		buffer = (buffer << 6) | value as u32;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			bytes.push((buffer >> bits) as u8);
+			buffer &= (1 << bits) - 1;
+		}
+	}
+	Some(bytes)
+}
+
+/// The number of tokens `piece` is encoded as, the adjacent parts with the
+/// lowest rank are merged until no pair of parts is a token.
+fn bpe_count(ranks: &HashMap<Vec<u8>, u32>, piece: &[u8]) -> usize {
+	if ranks.contains_key(piece) {
+		return 1;
+	}
+	// the boundaries between the parts
+	let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+	loop {
+		let mut best: Option<(u32, usize)> = None;
+		for index in 0..bounds.len().saturating_sub(2) {
+			if let Some(rank) = ranks.get(&piece[bounds[index]..bounds[index + 2]]) {
+				if best.map(|(best_rank, _)| *rank < best_rank).unwrap_or(true) {
+					best = Some((*rank, index));
+				}
+			}
+		}
+		match best {
+			Some((_, index)) => { bounds.remove(index + 1); },
+			None => break,
+		}
+	}
+	bounds.len() - 1
+}
+
+impl Tokenizer {
+	/// The tokenizer for `model` from `<encoding>.tiktoken` in `dir`, the
+	/// approximate count is used when there is no such file.
+	pub fn for_model(dir: &Path, model: &str) -> Result<Self, helpers::HelperError> {
+		let file = dir.join(format!("{}.tiktoken", encoding_for_model(model)));
+		if !file.is_file() {
+			return Ok(Tokenizer::Approximate);
+		}
+		Self::from_tiktoken(&fs::read_to_string(file)?)
+	}
+
+	/// Parse a tiktoken rank file, a base64 token and its rank on each line
+	pub fn from_tiktoken(content: &str) -> Result<Self, helpers::HelperError> {
+		let invalid = |line: usize| helpers::HelperError::Io(Error::new(ErrorKind::InvalidData, format!("Invalid tiktoken line {}", line)));
+		let mut ranks = HashMap::new();
+		for (index, line) in content.lines().enumerate() {
+			if line.trim().is_empty() {
+				continue;
+			}
+			let (token, rank) = line.split_once(' ').ok_or(invalid(index + 1))?;
+			let token = decode_base64(token).ok_or(invalid(index + 1))?;
+			let rank = rank.trim().parse::<u32>().map_err(|_| invalid(index + 1))?;
+			ranks.insert(token, rank);
+		}
+		let split = Regex::new(SPLIT_PATTERN).map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+		Ok(Tokenizer::Bpe{ ranks: ranks, split: split })
+	}
+
+	/// Whether the counts are those of the model's tokenizer
+	pub fn is_exact(&self) -> bool {
+		matches!(self, Tokenizer::Bpe { .. })
+	}
+
+	pub fn count(&self, text: &str) -> usize {
+		match self {
+			Tokenizer::Bpe { ranks, split } => split.find_iter(text).map(|piece| bpe_count(ranks, piece.as_str().as_bytes())).sum(),
+			Tokenizer::Approximate => (text.chars().count() + 3) / 4,
+		}
+	}
+
+	pub fn count_message(&self, message: &Message) -> usize {
+		let mut count = MESSAGE_OVERHEAD + self.count(&message.role);
+		if let Some(content) = message.content.as_ref() {
+			count += self.count(&content.text());
+			for image in content.images() {
+				count += if image.detail.as_deref() == Some("low") { LOW_DETAIL_IMAGE_TOKENS } else { IMAGE_TOKENS };
+			}
+		}
+		if let Some(name) = message.name.as_ref() {
+			count += self.count(name);
+		}
+		for tool_call in message.tool_calls.iter().flatten() {
+			count += self.count(&tool_call.function.name) + self.count(&tool_call.function.arguments);
+		}
+		count
+	}
+
+	/// The tokens of the prompt sent with `chat` (the tool definitions are not counted)
+	pub fn count_chat(&self, chat: &Chat) -> usize {
+		chat.messages.iter().map(|message| self.count_message(message)).sum::<usize>() + REPLY_OVERHEAD
+	}
+}
@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+
+/// How requests reach an endpoint: the HTTP client, or HTTP/1.1 over a Unix
+/// socket when `unix_socket` is set (the host of the url is then only sent
+/// as the `Host` header).
+#[derive(Debug, Clone, Default)]
+pub struct Transport {
+	pub client: reqwest::Client,
+	pub unix_socket: Option<PathBuf>,
+}
+
+impl Transport {
+	/// Send a request built with `client`
+	pub async fn execute(&self, builder: reqwest::RequestBuilder) -> Result<Response, Box<dyn std::error::Error>> {
+		match self.unix_socket.as_ref() {
+			#[cfg(unix)]
+			Some(socket) => Ok(Response::Unix(unix::send(socket, &builder.build()?).await?)),
+			#[cfg(not(unix))]
+			Some(_) => Err(Box::new(std::io::Error::new(std::io::ErrorKind::Unsupported, "Unix sockets are not supported on this platform"))),
+			None => Ok(Response::Http(builder.send().await?)),
+		}
+	}
+}
+
+/// A response read by `Transport::execute`
+pub enum Response {
+	Http(reqwest::Response),
+	#[cfg(unix)]
+	Unix(unix::UnixResponse),
+}
+
+impl Response {
+	pub fn status(&self) -> StatusCode {
+		match self {
+			Response::Http(response) => response.status(),
+			#[cfg(unix)]
+			Response::Unix(response) => response.status,
+		}
+	}
+
+	pub fn headers(&self) -> &HeaderMap {
+		match self {
+			Response::Http(response) => response.headers(),
+			#[cfg(unix)]
+			Response::Unix(response) => &response.headers,
+		}
+	}
+
+	/// The next part of the body, `None` at the end
+	pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+		match self {
+			Response::Http(response) => Ok(response.chunk().await?.map(|chunk| chunk.to_vec())),
+			#[cfg(unix)]
+			Response::Unix(response) => Ok(response.chunk().await?),
+		}
+	}
+
+	/// The whole body
+	pub async fn bytes(mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+		let mut body = Vec::new();
+		while let Some(chunk) = self.chunk().await? {
+			body.extend_from_slice(&chunk);
+		}
+		Ok(body)
+	}
+
+	pub async fn text(self) -> Result<String, Box<dyn std::error::Error>> {
+		Ok(String::from_utf8(self.bytes().await?)?)
+	}
+}
+
+#[cfg(unix)]
+pub mod unix {
+	use std::io::{Error, ErrorKind};
+	use std::path::Path;
+	use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, TRANSFER_ENCODING};
+	use reqwest::StatusCode;
+	use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+	use tokio::net::UnixStream;
+
+	const READ_SIZE: usize = 8192;
+
+	fn invalid(message: &str) -> Error {
+		Error::new(ErrorKind::InvalidData, message.to_string())
+	}
+
+	/// A response read from the socket, the body is read by `chunk`
+	pub struct UnixResponse {
+		pub status: StatusCode,
+		pub headers: HeaderMap,
+		stream: BufReader<UnixStream>,
+		chunked: bool,
+		/// the bytes of the body left to read, `None` reads to the end
+		remaining: Option<usize>,
+		done: bool,
+	}
+
+	/// Send `request` as HTTP/1.1 over the socket and read the response head
+	pub async fn send(socket: &Path, request: &reqwest::Request) -> Result<UnixResponse, Error> {
+		let url = request.url();
+		let mut target = url.path().to_string();
+		if let Some(query) = url.query() {
+			target.push('?');
+			target.push_str(query);
+		}
+		let body = request.body().and_then(|body| body.as_bytes()).unwrap_or_default();
+		let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+			request.method(), target, url.host_str().unwrap_or("localhost"), body.len());
+		for (name, value) in request.headers() {
+			let value = value.to_str().map_err(|_| Error::new(ErrorKind::InvalidInput, format!("header {} is not text", name)))?;
+			head.push_str(&format!("{}: {}\r\n", name, value));
+		}
+		head.push_str("\r\n");
+
+		let mut stream = BufReader::new(UnixStream::connect(socket).await?);
+		stream.get_mut().write_all(head.as_bytes()).await?;
+		stream.get_mut().write_all(body).await?;
+
+		let mut line = String::new();
+		stream.read_line(&mut line).await?;
+		let status = line.split_whitespace().nth(1)
+			.and_then(|code| code.parse::<u16>().ok())
+			.and_then(|code| StatusCode::from_u16(code).ok())
+			.ok_or(invalid("no HTTP status line in the response"))?;
+		let mut headers = HeaderMap::new();
+		loop {
+			line.clear();
+			if stream.read_line(&mut line).await? == 0 {
+				return Err(invalid("the response ended in the headers"));
+			}
+			let header = line.trim_end();
+			if header.is_empty() {
+				break;
+			}
+			if let Some((name, value)) = header.split_once(':') {
+				let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| invalid("invalid header name in the response"))?;
+				let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid("invalid header value in the response"))?;
+				headers.append(name, value);
+			}
+		}
+		let chunked = headers.get(TRANSFER_ENCODING).and_then(|value| value.to_str().ok())
+			.map(|value| value.to_ascii_lowercase().contains("chunked"))
+			.unwrap_or(false);
+		let remaining = if chunked {
+			None
+		} else {
+			headers.get(CONTENT_LENGTH).and_then(|value| value.to_str().ok()).and_then(|value| value.trim().parse::<usize>().ok())
+		};
+		Ok(UnixResponse{ status: status, headers: headers, stream: stream, chunked: chunked, remaining: remaining, done: false })
+	}
+
+	impl UnixResponse {
+		/// The next part of the body (a chunk of a chunked body), `None` at the end
+		pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+			if self.done {
+				return Ok(None);
+			}
+			if self.chunked {
+				let mut line = String::new();
+				self.stream.read_line(&mut line).await?;
+				let size = line.trim().split(';').next().and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+					.ok_or(invalid("invalid chunk size in the response"))?;
+				if size == 0 {
+					// the trailers are not needed as the connection is closed
+					self.done = true;
+					return Ok(None);
+				}
+				let mut chunk = vec![0; size + 2];
+				self.stream.read_exact(&mut chunk).await?;
+				chunk.truncate(size);
+				return Ok(Some(chunk));
+			}
+			let size = self.remaining.map(|remaining| remaining.min(READ_SIZE)).unwrap_or(READ_SIZE);
+			let mut chunk = vec![0; size];
+			let read = if size == 0 { 0 } else { self.stream.read(&mut chunk).await? };
+			if read == 0 {
+				self.done = true;
+				return Ok(None);
+			}
+			chunk.truncate(read);
+			self.remaining = self.remaining.map(|remaining| remaining - read);
+			Ok(Some(chunk))
+		}
+	}
+}
@@ -0,0 +1,280 @@
+use regex::Regex;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const CODE: &str = "\x1b[36m";
+
+/// The width used when it cannot be found from the terminal
+pub const DEFAULT_WIDTH: usize = 80;
+/// Paragraphs are not wrapped wider than this, long lines are hard to read
+pub const MAX_WIDTH: usize = 100;
+/// Indented blocks are still given this many columns on narrow terminals
+const MIN_TEXT_WIDTH: usize = 20;
+const THEME: &str = "base16-ocean.dark";
+
+/// The width to wrap to, that of the terminal up to `MAX_WIDTH`
+pub fn terminal_width() -> usize {
+	terminal_size::terminal_size()
+		.map(|(terminal_size::Width(width), _)| width as usize)
+		.unwrap_or(DEFAULT_WIDTH)
+		.min(MAX_WIDTH)
+}
+
+/// Lines of text joined into one block and wrapped, the first line starts
+/// with `prefix` (e.g. a list bullet) and the others with `indent`
+struct Block {
+	prefix: String,
+	indent: String,
+	text: String,
+}
+
+/// The inline markup (`code`, **bold** and *italic*) kept between the lines
+/// of a wrapped block, so a span continues on the next line
+#[derive(Default)]
+struct Inline {
+	code: bool,
+	bold: bool,
+	italic: bool,
+}
+
+impl Inline {
+	fn codes(&self) -> String {
+		let mut codes = String::new();
+		if self.code {
+			codes.push_str(CODE);
+		}
+		if self.bold {
+			codes.push_str(BOLD);
+		}
+		if self.italic {
+			codes.push_str(ITALIC);
+		}
+		codes
+	}
+
+	/// The line with the markup replaced by escape codes, the styles are
+	/// reset at the end of the line
+	fn style(&mut self, line: &str) -> String {
+		let mut out = self.codes();
+		let chars: Vec<char> = line.chars().collect();
+		let mut index = 0;
+		while index < chars.len() {
+			let c = chars[index];
+			let next = chars.get(index + 1).copied();
+			let toggled = if c == '`' {
+				self.code = !self.code;
+				1
+			} else if self.code || c != '*' {
+				0
+			} else if next == Some('*') {
+				self.bold = !self.bold;
+				2
+			} else if self.italic || next.map(|next| !next.is_whitespace()).unwrap_or(false) {
+				// `2 * 3` is not italic
+				self.italic = !self.italic;
+				1
+			} else {
+				0
+			};
+			if toggled == 0 {
+				out.push(c);
+				index += 1;
+			} else {
+				out.push_str(RESET);
+				out.push_str(&self.codes());
+				index += toggled;
+			}
+		}
+		out.push_str(RESET);
+		out
+	}
+}
+
+/// The columns taken by a word once its markup is removed
+fn visible_width(word: &str) -> usize {
+	word.replace("**", "").chars().filter(|c| *c != '`').count()
+}
+
+/// The fence starting a code block (at least three backticks or tildes)
+fn fence(line: &str) -> Option<&str> {
+	for marker in ['`', '~'] {
+		let length = line.chars().take_while(|c| *c == marker).count();
+		if length >= 3 {
+			return Some(&line[..length]);
+		}
+	}
+	None
+}
+
+/// The content of the fenced code blocks in some markdown
+pub fn code_blocks(markdown: &str) -> Vec<String> {
+	let mut blocks = Vec::new();
+	let mut lines = markdown.lines();
+	while let Some(line) = lines.next() {
+		if let Some(fence) = fence(line.trim_start()) {
+			let mut code = String::new();
+			for line in lines.by_ref() {
+				if line.trim_start().starts_with(fence) {
+					break;
+				}
+				code.push_str(line);
+				code.push('\n');
+			}
+			blocks.push(code);
+		}
+	}
+	blocks
+}
+
+/// `---`, `***` or `___` (spaces allowed)
+fn is_rule(line: &str) -> bool {
+	let marks: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+	marks.len() >= 3 && ['-', '*', '_'].contains(&marks[0]) && marks.iter().all(|c| *c == marks[0])
+}
+
+/// Markdown written for a terminal: headings, lists and quotes styled,
+/// paragraphs wrapped and code blocks highlighted
+pub struct Renderer {
+	width: usize,
+	syntaxes: SyntaxSet,
+	theme: Theme,
+	list_item: Regex,
+}
+
+impl Renderer {
+	pub fn new(width: usize) -> Self {
+		let mut themes = ThemeSet::load_defaults();
+		Renderer{
+			width: width,
+			syntaxes: SyntaxSet::load_defaults_newlines(),
+			theme: themes.themes.remove(THEME).unwrap_or_default(),
+			list_item: Regex::new(r"^(\s*)([-*+]|\d+[.)])\s+(.*)$").unwrap(),
+		}
+	}
+
+	pub fn render(&self, markdown: &str) -> String {
+		let mut out = String::new();
+		let mut block: Option<Block> = None;
+		let mut lines = markdown.lines();
+		while let Some(line) = lines.next() {
+			let trimmed = line.trim_start();
+			if let Some(fence) = fence(trimmed) {
+				self.flush(&mut out, block.take());
+				let language = trimmed[fence.len()..].trim();
+				let mut code = String::new();
+				for line in lines.by_ref() {
+					if line.trim_start().starts_with(fence) {
+						break;
+					}
+					code.push_str(line);
+					code.push('\n');
+				}
+				out.push_str(&self.highlight(&code, language));
+			} else if trimmed.is_empty() {
+				self.flush(&mut out, block.take());
+				out.push('\n');
+			} else if is_rule(trimmed) {
+				self.flush(&mut out, block.take());
+				out.push_str(&format!("{}{}{}\n", DIM, "─".repeat(self.width), RESET));
+			} else if trimmed.starts_with('#') && trimmed.trim_start_matches('#').starts_with(' ') && trimmed.len() - trimmed.trim_start_matches('#').len() <= 6 {
+				self.flush(&mut out, block.take());
+				let level = trimmed.len() - trimmed.trim_start_matches('#').len();
+				let style = if level == 1 { format!("{}{}", BOLD, UNDERLINE) } else { BOLD.to_string() };
+				out.push_str(&format!("{}{}{}\n", style, trimmed[level..].trim(), RESET));
+			} else if trimmed.starts_with('|') {
+				// tables are left as they are, wrapping would break the columns
+				self.flush(&mut out, block.take());
+				out.push_str(line);
+				out.push('\n');
+			} else if let Some(quote) = trimmed.strip_prefix('>') {
+				if block.as_ref().map(|block| !block.prefix.contains('│')).unwrap_or(true) {
+					self.flush(&mut out, block.take());
+					let prefix = format!("{}│{} ", DIM, RESET);
+					block = Some(Block{ prefix: prefix.clone(), indent: prefix, text: String::new() });
+				}
+				if let Some(block) = block.as_mut() {
+					block.text.push(' ');
+					block.text.push_str(quote.trim());
+				}
+			} else if let Some(item) = self.list_item.captures(line) {
+				self.flush(&mut out, block.take());
+				let marker = match &item[2] {
+					"-" | "*" | "+" => "•",
+					number => number,
+				};
+				let prefix = format!("{}{} ", &item[1], marker);
+				let indent = " ".repeat(prefix.chars().count());
+				block = Some(Block{ prefix: prefix, indent: indent, text: item[3].to_string() });
+			} else {
+				match block.as_mut() {
+					Some(block) => {
+						block.text.push(' ');
+						block.text.push_str(trimmed);
+					},
+					None => block = Some(Block{ prefix: String::new(), indent: String::new(), text: trimmed.to_string() }),
+				}
+			}
+		}
+		self.flush(&mut out, block);
+		out
+	}
+
+	/// Wrap the block to the width and style its inline markup
+	fn flush(&self, out: &mut String, block: Option<Block>) {
+		let block = match block {
+			Some(block) => block,
+			None => return,
+		};
+		// the escape codes in a quote prefix take no columns
+		let prefix_width = if block.prefix.contains('│') { 2 } else { block.prefix.chars().count() };
+		let available = self.width.saturating_sub(prefix_width).max(MIN_TEXT_WIDTH);
+		let mut inline = Inline::default();
+		let mut lines: Vec<String> = Vec::new();
+		let mut line = String::new();
+		let mut line_width = 0;
+		for word in block.text.split_whitespace() {
+			let width = visible_width(word);
+			if line_width > 0 && line_width + 1 + width > available {
+				lines.push(std::mem::take(&mut line));
+				line_width = 0;
+			}
+			if line_width > 0 {
+				line.push(' ');
+				line_width += 1;
+			}
+			line.push_str(word);
+			line_width += width;
+		}
+		lines.push(line);
+		for (index, line) in lines.iter().enumerate() {
+			out.push_str(if index == 0 { &block.prefix } else { &block.indent });
+			out.push_str(&inline.style(line));
+			out.push('\n');
+		}
+	}
+
+	/// A code block highlighted for the language of its fence (plain text
+	/// when the language is unknown)
+	fn highlight(&self, code: &str, language: &str) -> String {
+		let syntax = self.syntaxes.find_syntax_by_token(language).unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+		let mut highlighter = HighlightLines::new(syntax, &self.theme);
+		let mut out = String::new();
+		for line in LinesWithEndings::from(code) {
+			match highlighter.highlight_line(line, &self.syntaxes) {
+				Ok(ranges) => out.push_str(as_24_bit_terminal_escaped(&ranges, false).trim_end_matches('\n')),
+				Err(_) => out.push_str(line.trim_end_matches('\n')),
+			}
+			// the colours do not run on past the end of the line
+			out.push_str(RESET);
+			out.push('\n');
+		}
+		out
+	}
+}
@@ -8,6 +8,28 @@ fn it_works() {
 	assert_eq!(result, 4);
 }
 
+#[test]
+fn cli_parse_test() {
+	let args = Cli::try_parse_from(["openaiclient", "--chats-dir", "x", "chats", "list"]).unwrap();
+	assert!(matches!(args.command, Some(Command::Chats { action: ChatsAction::List })));
+	assert_eq!(args.chats_dir, Some(PathBuf::from("x")));
+	let args = Cli::try_parse_from(["openaiclient", "--profile", "p", "models"]).unwrap();
+	assert!(matches!(args.command, Some(Command::Models)));
+	assert_eq!(args.profile.as_deref(), Some("p"));
+	let args = Cli::try_parse_from(["openaiclient", "--config-dir", "c", "init", "--model", "m"]).unwrap();
+	assert!(matches!(args.command, Some(Command::Init { .. })));
+	assert_eq!(args.config_dir, Some(PathBuf::from("c")));
+	let args = Cli::try_parse_from(["openaiclient", "--stream", "1001", "hello"]).unwrap();
+	assert!(args.command.is_none());
+	assert_eq!(args.chat_id.as_deref(), Some("1001"));
+	assert_eq!(args.message.as_deref(), Some("hello"));
+	// --system alone sets the system message without sending one
+	let args = Cli::try_parse_from(["openaiclient", "--system", "@reviewer.md", "--force", "1004"]).unwrap();
+	assert_eq!(args.system.as_deref(), Some("@reviewer.md"));
+	assert_eq!(args.chat_id.as_deref(), Some("1004"));
+	assert!(args.message.is_none());
+}
+
 #[test]
 fn azure_response_parse_test() {
 	let mut file = std::fs::File::open("testdata/sampleresponse.json").unwrap();
@@ -18,6 +40,1031 @@ fn azure_response_parse_test() {
 	println!("{:?}", chat_response);
 }
 
+#[test]
+fn stream_assembler_test() {
+	let mut assembler = openaiapi::StreamAssembler::new();
+	assembler.push_chunk(r#"{"choices":[{"index":0,"delta":{"role":"assistant","content":""}}]}"#).unwrap();
+	assert_eq!(assembler.push_chunk(r#"{"choices":[{"index":0,"delta":{"content":"Hello"}}]}"#).unwrap(), Some("Hello".to_string()));
+	assembler.push_chunk(r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"execute","arguments":""}}]}}]}"#).unwrap();
+	assembler.push_chunk(r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"command\":"}}]}}]}"#).unwrap();
+	assembler.push_chunk(r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"ls\"}"}}]}}]}"#).unwrap();
+	assembler.push_chunk(r#"{"choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}"#).unwrap();
+	let message = assembler.finish();
+	assert_eq!(message.finish_reason.as_deref(), Some("tool_calls"));
+	assert_eq!(message.role, "assistant");
+	assert_eq!(message.text().as_deref(), Some("Hello"));
+	let tool_calls = message.tool_calls.unwrap();
+	assert_eq!(tool_calls[0].id, "call_1");
+	assert_eq!(tool_calls[0].function.arguments, r#"{"command":"ls"}"#);
+	// Ollama gives no ids, those made must differ between replies
+	let ollama_call = |line: &str| {
+		let mut assembler = openaiapi::StreamAssembler::new();
+		assembler.push_ollama_chunk(line).unwrap();
+		assembler.finish().tool_calls.unwrap().remove(0).id
+	};
+	let line = r#"{"message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"execute","arguments":{"command":"ls"}}}]},"done":true}"#;
+	let first = ollama_call(line);
+	std::thread::sleep(std::time::Duration::from_millis(1));
+	assert_ne!(first, ollama_call(line));
+}
+
+#[test]
+fn message_metadata_test() {
+	let message = openaiapi::ChatContext::parse_response(r#"{"model":"gpt-4o-2024-05-13","choices":[{"index":0,"message":{"role":"assistant","content":"Hi"},"finish_reason":"stop"}],"usage":{"prompt_tokens":9,"completion_tokens":1,"total_tokens":10}}"#).unwrap();
+	assert_eq!(message.model.as_deref(), Some("gpt-4o-2024-05-13"));
+	assert_eq!(message.finish_reason.as_deref(), Some("stop"));
+	assert_eq!(message.usage, Some(openaiapi::Usage{ prompt_tokens: 9, completion_tokens: 1, total_tokens: 10 }));
+	let seeded = openaiapi::ChatContext::parse_response(r#"{"system_fingerprint":"fp_44709d6fcb","choices":[{"index":0,"message":{"role":"assistant","content":"4"}}]}"#).unwrap();
+	assert_eq!(seeded.system_fingerprint.as_deref(), Some("fp_44709d6fcb"));
+	let mut ctx = openaiapi::ChatContext::new(PathBuf::from("data"), PathBuf::from("chats"), "http://localhost/".to_string(), String::new()).unwrap();
+	ctx.chat = Some(helpers::read_from_json("data/empty_chat.json").unwrap());
+	ctx.add_message(message).unwrap();
+	let message = ctx.chat.as_ref().unwrap().messages.last().unwrap();
+	assert!(message.created_at.is_some());
+	// the metadata is stored but not sent
+	assert!(serde_json::to_value(message).unwrap().get("usage").is_some());
+	let request = serde_json::to_value(openaiapi::RequestMessage::from(message)).unwrap();
+	assert_eq!(request, serde_json::json!({"role": "assistant", "content": "Hi"}));
+	// the recorded usage is used for the prompt tokens rather than a count
+	ctx.add_normal_message("user", "12345678").unwrap();
+	let after = ctx.tokenizer.count_message(ctx.chat.as_ref().unwrap().messages.last().unwrap());
+	assert_eq!(ctx.prompt_tokens(), 10 + after + openaiapi::tokens::REPLY_OVERHEAD);
+}
+
+#[test]
+fn message_content_test() {
+	// chats saved before the content parts were supported still load
+	let message: openaiapi::Message = serde_json::from_str(r#"{"role":"user","content":"Hello"}"#).unwrap();
+	assert_eq!(message.content, Some(openaiapi::MessageContent::Text("Hello".to_string())));
+	let json = r#"{"role":"user","content":[{"type":"text","text":"What is this?"},{"type":"image_url","image_url":{"url":"data:image/png;base64,iVBORw0KGgo="}}]}"#;
+	let message: openaiapi::Message = serde_json::from_str(json).unwrap();
+	assert_eq!(message.text().as_deref(), Some("What is this?"));
+	let images = message.content.as_ref().unwrap().images();
+	assert_eq!(images[0].url, "data:image/png;base64,iVBORw0KGgo=");
+	assert_eq!(serde_json::to_value(openaiapi::RequestMessage::from(&message)).unwrap(), serde_json::from_str::<serde_json::Value>(json).unwrap());
+	let mut chat: openaiapi::Chat = helpers::read_from_json("data/empty_chat.json").unwrap();
+	chat.messages.push(message);
+	let dump = chats::dump(&chat, chats::DumpFormat::Markdown).unwrap();
+	assert!(dump.ends_with("What is this?\n\n_[image (embedded)]_\n\n"));
+	let tokenizer = openaiapi::tokens::Tokenizer::default();
+	let high = tokenizer.count_message(chat.messages.last().unwrap());
+	if let Some(openaiapi::MessageContent::Multi(parts)) = chat.messages.last_mut().unwrap().content.as_mut() {
+		if let openaiapi::ContentPart::ImageUrl { image_url } = &mut parts[1] {
+			image_url.detail = Some("low".to_string());
+		}
+	}
+	let low = tokenizer.count_message(chat.messages.last().unwrap());
+	assert_eq!(high - low, openaiapi::tokens::IMAGE_TOKENS - openaiapi::tokens::LOW_DETAIL_IMAGE_TOKENS);
+	assert!(serde_json::to_string(&chat.messages.last().unwrap().content).unwrap().contains(r#""detail":"low""#));
+}
+
+#[test]
+fn image_content_test() {
+	assert_eq!(openaiapi::content::encode_base64(b"Man"), "TWFu");
+	assert_eq!(openaiapi::content::encode_base64(b"Ma"), "TWE=");
+	assert_eq!(openaiapi::content::encode_base64(b"M"), "TQ==");
+	assert_eq!(openaiapi::content::sniff_image(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+	assert_eq!(openaiapi::content::sniff_image(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("image/jpeg"));
+	assert_eq!(openaiapi::content::sniff_image(b"GIF89a\x01\0\x01\0"), Some("image/gif"));
+	assert_eq!(openaiapi::content::sniff_image(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+	assert_eq!(openaiapi::content::sniff_image(b"BM\x0e\0\0\0\0\0\0\0\0\0\0\0"), Some("image/bmp"));
+	assert_eq!(openaiapi::content::sniff_image(b"BMW makes cars, not bitmaps"), None);
+	let dir = std::env::temp_dir().join("openaiclient_image_content_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	// the content decides, not the extension
+	fs::write(dir.join("photo.txt"), b"GIF89a\x01\0\x01\0").unwrap();
+	match openaiapi::content::make_content_part(&dir.join("photo.txt")).unwrap() {
+		openaiapi::ContentPart::ImageUrl { image_url } => assert_eq!(image_url.url, "data:image/gif;base64,R0lGODlhAQABAA=="),
+		other => panic!("not an image: {:?}", other),
+	}
+	fs::write(dir.join("notes.md"), "# Notes\n").unwrap();
+	assert_eq!(openaiapi::content::make_content_part(&dir.join("notes.md")).unwrap(), openaiapi::ContentPart::Text { text: "# Notes\n".to_string() });
+	fs::write(dir.join("broken.png"), "not really a png").unwrap();
+	assert!(openaiapi::content::make_content_part(&dir.join("broken.png")).is_err());
+	fs::write(dir.join("recording.wav"), "not really a recording").unwrap();
+	assert!(openaiapi::content::make_content_part(&dir.join("recording.wav")).is_err());
+	assert!(openaiapi::content::image_part(&dir.join("notes.md")).is_err());
+	assert!(matches!(openaiapi::content::stdin_part(b"\x89PNG\r\n\x1a\n\0\0".to_vec()).unwrap(), openaiapi::ContentPart::ImageUrl { .. }));
+	assert_eq!(openaiapi::content::stdin_part(b"what is wrong here".to_vec()).unwrap(), openaiapi::ContentPart::Text { text: "what is wrong here".to_string() });
+	assert!(openaiapi::content::stdin_part(vec![0xff, 0xfe, 0x00]).is_err());
+	assert_eq!(openaiapi::content::sniff_audio(b"RIFF\x24\0\0\0WAVEfmt "), Some("wav"));
+	assert_eq!(openaiapi::content::sniff_audio(b"ID3\x04\0\0"), Some("mp3"));
+	assert_eq!(openaiapi::content::sniff_audio(b"RIFF\x24\0\0\0WEBPVP8 "), None);
+	assert_eq!(openaiapi::content::stdin_part(b"ID3\x04\0\0".to_vec()).unwrap(),
+		openaiapi::ContentPart::InputAudio { input_audio: openaiapi::InputAudio{ data: "SUQzBAAA".to_string(), format: "mp3".to_string() } });
+	assert_eq!(openaiapi::content::remote_image("https://example.com/diagram.png").unwrap(),
+		openaiapi::ContentPart::ImageUrl { image_url: openaiapi::ImageUrl{ url: "https://example.com/diagram.png".to_string(), detail: None } });
+	assert!(openaiapi::content::remote_image("file:///etc/passwd").is_err());
+	fs::create_dir_all(dir.join("src")).unwrap();
+	fs::write(dir.join("src").join("lib.rs"), "pub fn one() -> u32 { 1 }").unwrap();
+	fs::write(dir.join(".gitignore"), "*.md\n").unwrap();
+	fs::write(dir.join("big.txt"), "x".repeat(openaiapi::content::MAX_DIRECTORY_FILE_SIZE as usize + 1)).unwrap();
+	let (text, skipped) = openaiapi::content::directory_text(&dir).unwrap();
+	assert!(text.contains(&format!("==> {} <==\npub fn one() -> u32 {{ 1 }}\n\n", dir.join("src").join("lib.rs").display())));
+	assert!(!text.contains("# Notes"));
+	assert!(skipped.iter().any(|file| file.contains("big.txt")));
+	assert_eq!(openaiapi::content::split_line_range("src/main.rs:100-180"), Some(("src/main.rs", 100, 180)));
+	assert_eq!(openaiapi::content::split_line_range("src/main.rs"), None);
+	assert_eq!(openaiapi::content::split_line_range("src/main.rs:180-100"), None);
+	fs::write(dir.join("lines.txt"), "one\ntwo\nthree\nfour\n").unwrap();
+	assert_eq!(openaiapi::content::line_range(&dir.join("lines.txt"), 2, 10).unwrap(), format!("==> {}:2-4 <==\ntwo\nthree\nfour\n", dir.join("lines.txt").display()));
+	assert!(openaiapi::content::line_range(&dir.join("lines.txt"), 5, 6).is_err());
+	let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn web_fetch_test() {
+	assert_eq!(tools::web::decode_entities("Tom &amp; Jerry &lt;3 &#39;&#x41;&apos; &bogus; & more"), "Tom & Jerry <3 'A' &bogus; & more");
+	let page = r#"<!DOCTYPE html><html><head><title> Rust &amp; Docs </title><style>body { color: red }</style></head>
+<body><nav><a href="/">Home</a></nav><main><h1>Getting  started</h1>
+<p>Install with <code>cargo</code>, see <a href="https://doc.rust-lang.org/">the <b>book</b></a>.</p>
+<script>if (a < b) { alert("no"); }</script>
+<ul><li>one</li><li><em>two</em></li></ul>
+<pre>fn main() {
+    println!("hi");
+}</pre><!-- a comment --></main><footer>Copyright</footer></body></html>"#;
+	assert_eq!(tools::web::html_title(page).as_deref(), Some("Rust & Docs"));
+	assert_eq!(tools::web::html_to_markdown(page), "# Getting started\n\nInstall with `cargo`, see [the **book**](https://doc.rust-lang.org/).\n\n- one\n- *two*\n\n```\nfn main() {\n    println!(\"hi\");\n}\n```");
+}
+
+#[test]
+fn web_search_test() {
+	let searxng = r#"{"query":"rust","results":[{"title":"Rust","url":"https://www.rust-lang.org/","content":"A language empowering everyone"}]}"#;
+	assert_eq!(tools::web::parse_search_results(tools::web::SearchBackend::Searxng, searxng).unwrap(),
+		vec![tools::web::SearchResult{ title: "Rust".to_string(), url: "https://www.rust-lang.org/".to_string(), snippet: "A language empowering everyone".to_string() }]);
+	let brave = r#"{"web":{"results":[{"title":"The <strong>Rust</strong> Book","url":"https://doc.rust-lang.org/book/","description":"Learn &amp; build"}]}}"#;
+	let results = tools::web::parse_search_results(tools::web::SearchBackend::Brave, brave).unwrap();
+	assert_eq!((results[0].title.as_str(), results[0].snippet.as_str()), ("The **Rust** Book", "Learn & build"));
+	let bing = r#"{"webPages":{"value":[{"name":"Crates","url":"https://crates.io/","snippet":"The registry"}]}}"#;
+	assert_eq!(tools::web::parse_search_results(tools::web::SearchBackend::Bing, bing).unwrap()[0].title, "Crates");
+	assert!(tools::web::parse_search_results(tools::web::SearchBackend::Bing, "{}").unwrap().is_empty());
+	let config: config::Config = toml::from_str("[web_search]\nbackend = \"brave\"\napi_key = \"key\"\n").unwrap();
+	assert_eq!(config.web_search.unwrap().backend, tools::web::SearchBackend::Brave);
+}
+
+#[test]
+fn http_request_test() {
+	use tools::Tool;
+	let config = tools::web::HttpRequestConfig{ allowed: vec!["http://localhost:8080/api/".to_string()], ..Default::default() };
+	let allows = |url: &str| config.allows(&url::Url::parse(url).unwrap());
+	assert!(allows("http://localhost:8080/api/tickets?open=true"));
+	assert!(!allows("http://localhost:8080/admin"));
+	assert!(!allows("http://localhost:9090/api/tickets"));
+	assert!(!allows("https://localhost:8080/api/tickets"));
+	assert!(!allows("http://localhost.example.com:8080/api/"));
+	let tool = tools::web::HttpRequestTool::new(config.clone());
+	assert!(tool.call(r#"{"url":"http://example.com/"}"#).is_err());
+	assert_eq!(tool.preview(r#"{"method":"post","url":"http://localhost:8080/api/tickets","body":"{}"}"#).unwrap(),
+		"Would send POST http://localhost:8080/api/tickets with:\n{}");
+}
+
+#[test]
+fn git_tools_test() {
+	let status = tools::git::parse_status("## main...origin/main [ahead 1]\n M src/main.rs\n?? notes.md\n");
+	assert_eq!(status["branch"], "main...origin/main [ahead 1]");
+	assert_eq!(status["files"][0]["status"], "M");
+	assert_eq!(status["files"][1]["path"], "notes.md");
+	let dir = std::env::temp_dir().join("openaiclient_git_tools_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	let git = tools::git::Git::new(tools::files::FileLibrary::new(Some(dir.canonicalize().unwrap())));
+	git.run(&["init", "--quiet"]).unwrap();
+	git.run(&["config", "user.name", "Test"]).unwrap();
+	git.run(&["config", "user.email", "test@example.com"]).unwrap();
+	fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+	let commit: serde_json::Value = serde_json::from_str(&git.commit(tools::git::GitCommitArgs{ message: "Add hello".to_string(), paths: Vec::new(), all: true }).unwrap()).unwrap();
+	let log: serde_json::Value = serde_json::from_str(&git.log(tools::git::GitLogArgs{ max_count: None, paths: Vec::new() }).unwrap()).unwrap();
+	assert_eq!(log["commits"][0]["commit"], commit["commit"]);
+	assert_eq!(log["commits"][0]["subject"], "Add hello");
+	fs::write(dir.join("hello.txt"), "hello world\n").unwrap();
+	let diff: serde_json::Value = serde_json::from_str(&git.diff(tools::git::GitDiffArgs{ staged: false, revision: None, paths: vec!["hello.txt".to_string()] }).unwrap()).unwrap();
+	assert!(diff["diff"].as_str().unwrap().contains("+hello world"));
+	assert!(git.diff(tools::git::GitDiffArgs{ staged: false, revision: Some("--output=/tmp/x".to_string()), paths: Vec::new() }).is_err());
+	assert!(git.log(tools::git::GitLogArgs{ max_count: None, paths: vec!["../outside".to_string()] }).is_err());
+	let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn sql_query_test() {
+	use tools::Tool;
+	let path = std::env::temp_dir().join("openaiclient_sql_query_test.sqlite3");
+	let _ = fs::remove_file(&path);
+	let conn = rusqlite::Connection::open(&path).unwrap();
+	conn.execute_batch("CREATE TABLE albums (title TEXT, year INTEGER, cover BLOB); INSERT INTO albums VALUES ('Blue', 1971, x'0102'), ('Hejira', 1976, NULL);").unwrap();
+	drop(conn);
+	let config = tools::sql::SqlDatabaseConfig{ path: path.clone(), statements: None };
+	assert!(config.allows("select * from albums"));
+	assert!(config.allows("WITH x AS (SELECT 1) SELECT * FROM x"));
+	assert!(!config.allows("DELETE FROM albums"));
+	assert!(!config.allows("selection"));
+	let tool = tools::sql::SqlQueryTool::new(std::collections::HashMap::from([("music".to_string(), config)]));
+	let result: serde_json::Value = serde_json::from_str(&tool.call(r#"{"database":"music","sql":"SELECT title, year, cover FROM albums WHERE year > ?1 ORDER BY year","params":[1900]}"#).unwrap()).unwrap();
+	assert_eq!(result["columns"], serde_json::json!(["title", "year", "cover"]));
+	assert_eq!(result["rows"][0], serde_json::json!(["Blue", 1971, "<2 bytes>"]));
+	assert_eq!(result["rows"][1][2], serde_json::Value::Null);
+	assert!(tool.call(r#"{"database":"music","sql":"DELETE FROM albums"}"#).is_err());
+	assert!(tool.call(r#"{"database":"music","sql":"WITH x AS (SELECT 1) DELETE FROM albums"}"#).is_err());
+	assert!(tool.call(r#"{"database":"other","sql":"SELECT 1"}"#).is_err());
+	let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn cargo_tools_test() {
+	let output = concat!(
+		r#"{"reason":"compiler-artifact","package_id":"serde"}"#, "\n",
+		r#"{"reason":"compiler-message","message":{"level":"error","message":"mismatched types","code":{"code":"E0308"},"spans":[{"file_name":"src/lib.rs","line_start":3,"column_start":5,"is_primary":false},{"file_name":"src/main.rs","line_start":12,"column_start":9,"is_primary":true}]}}"#, "\n",
+		r#"{"reason":"compiler-message","message":{"level":"warning","message":"unused variable: `x`","code":null,"spans":[{"file_name":"src/main.rs","line_start":4,"column_start":6,"is_primary":true}]}}"#, "\n",
+		r#"{"reason":"compiler-message","message":{"level":"error","message":"aborting due to 1 previous error","code":null,"spans":[]}}"#, "\n",
+		"running 2 tests\n");
+	let diagnostics = tools::cargo::parse_diagnostics(output);
+	assert_eq!(diagnostics.len(), 2);
+	assert_eq!(diagnostics[0].code.as_deref(), Some("E0308"));
+	assert_eq!(diagnostics[0].file.as_deref(), Some("src/main.rs"));
+	assert_eq!(diagnostics[0].line, Some(12));
+	assert_eq!(diagnostics[1].level, "warning");
+	let tests = tools::cargo::parse_test_output("running 2 tests\ntest a ... ok\ntest b ... FAILED\n\nfailures:\n\n---- b stdout ----\nthread 'b' panicked at src/lib.rs:9:5:\nassertion failed\n\nfailures:\n    b\n\ntest result: FAILED. 1 passed; 1 failed; 0 ignored; 0 measured; 0 filtered out\n");
+	assert_eq!(tests["failures"][0]["test"], "b");
+	assert_eq!(tests["failures"][0]["output"], "thread 'b' panicked at src/lib.rs:9:5:\nassertion failed");
+	assert_eq!(tests["results"][0], "FAILED. 1 passed; 1 failed; 0 ignored; 0 measured; 0 filtered out");
+	let diffs = tools::cargo::parse_fmt_output("Diff in /src/main.rs at line 4:\n-fn main(){}\n+fn main() {}\nDiff in /src/lib.rs:10:\n");
+	assert_eq!(diffs[0]["file"], "/src/main.rs");
+	assert_eq!(diffs[0]["line"], 4);
+	assert_eq!(diffs[1]["file"], "/src/lib.rs");
+	assert_eq!(diffs[1]["line"], 10);
+}
+
+#[test]
+fn commit_message_test() {
+	assert_eq!(strip_code_fence("fix: handle empty input\n"), "fix: handle empty input");
+	assert_eq!(strip_code_fence("```\nfeat(cli): add commitmsg\n\nWrites the message.\n```"), "feat(cli): add commitmsg\n\nWrites the message.");
+	assert_eq!(strip_code_fence("```text\ndocs: fix typo\n```\n"), "docs: fix typo");
+}
+
+#[test]
+fn template_processor_test() {
+	let dir = std::env::temp_dir().join("openaiclient_template_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(dir.join("notes.md"), "remember the milk").unwrap();
+	let mut processor = helpers::TemplateProcessor::new(&dir);
+	processor.set("name", "Ada");
+	assert_eq!(processor.process("Hello {{ name }}, {{missing}}!").unwrap(), "Hello Ada, !");
+	assert_eq!(processor.process("{{#if name}}yes{{else}}no{{/if}} {{#if !name}}yes{{else}}no{{/if}}").unwrap(), "yes no");
+	assert_eq!(processor.process("{{#if file:notes.md}}{{file:notes.md}}{{/if}}{{#if file:none.md}}none{{/if}}").unwrap(), "remember the milk");
+	assert_eq!(processor.process("{{#if name}}{{#if missing}}a{{else}}b{{/if}}{{/if}}").unwrap(), "b");
+	assert!(processor.process("{{file:none.md}}").is_err());
+	assert!(processor.process("{{#if name}}open").is_err());
+	assert!(processor.process("{{/if}}").is_err());
+	assert_eq!(processor.process("{{date}}").unwrap().len(), 10);
+	processor.set_project_context();
+	assert_eq!(processor.process("{{file_tree}}").unwrap(), "notes.md");
+	assert!(processor.process("{{project_context}}").unwrap().contains("Files:\nnotes.md\n"));
+	#[cfg(unix)]
+	{
+		assert_eq!(processor.process("{{cmd:echo hi}}").unwrap(), "hi");
+		assert!(helpers::TemplateProcessor::new(&dir).untrusted(&dir).process("{{cmd:echo hi}}").is_err());
+	}
+	let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn tool_registry_test() {
+	let dispatcher = tools::Dispatcher::new(tools::ToolRegistry::with_builtin(None, None, None));
+	let definitions = dispatcher.registry.definitions().unwrap();
+	assert_eq!(definitions.len(), dispatcher.registry.names().len());
+	let result = dispatcher.call("execute", r#"{"command":"echo hello"}"#).unwrap();
+	let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+	assert_eq!(result["stdout"], "hello\n");
+	assert!(dispatcher.call("no_such_tool", "{}").is_err());
+	let result = dispatcher.call("execute", r#"{"argv":["echo","no","shell"]}"#).unwrap();
+	let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+	assert_eq!(result["stdout"], "no shell\n");
+	let result = dispatcher.call("execute", r#"{"command":"echo $GREETING; pwd","cwd":"/","env":{"GREETING":"hi"}}"#).unwrap();
+	let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+	assert_eq!(result["stdout"], "hi\n/\n");
+	let result = dispatcher.call("execute", r#"{"command":"sleep 5","timeout_secs":1}"#).unwrap();
+	let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+	assert_eq!(result["timed_out"], true);
+	// a background child holding the pipes open is stopped as well
+	let start = std::time::Instant::now();
+	let result = dispatcher.call("execute", r#"{"command":"sleep 30 & sleep 30","timeout_secs":1}"#).unwrap();
+	let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+	assert_eq!(result["timed_out"], true);
+	assert!(start.elapsed() < std::time::Duration::from_secs(20));
+	let result = dispatcher.call("execute", r#"{"command":"tr a-z A-Z","stdin":"shout"}"#).unwrap();
+	let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+	assert_eq!(result["stdout"], "SHOUT");
+}
+
+#[test]
+fn project_config_test() {
+	let dir = std::env::temp_dir().join("openaiclient_project_config_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(dir.join("src/deep")).unwrap();
+	fs::write(dir.join(config::PROJECT_CONFIG), "model = \"small\"\nchats_dir = \"chats\"\n[tool_policy]\ndenied = [\"execute\"]\n").unwrap();
+	let path = config::ProjectConfig::find(&dir.join("src/deep")).unwrap();
+	assert_eq!(path, dir.join(config::PROJECT_CONFIG));
+	let project: config::ProjectConfig = helpers::read_from_toml(&path).unwrap();
+	let mut config: config::Config = toml::from_str("model = \"large\"\n[profiles.local]\nmodel = \"llama\"\n").unwrap();
+	config.merge_project(project, &dir);
+	assert_eq!(config.profile(None).unwrap().model.as_deref(), Some("small"));
+	assert_eq!(config.profile(Some("local")).unwrap().model.as_deref(), Some("small"));
+	assert_eq!(config.chats_dir, Some(dir.join("chats")));
+	assert!(config.tool_policy.is_none());
+	assert!(!config.project_tool_policy.unwrap().allows("execute"));
+	fs::write(dir.join("AGENTS.md"), "Run the tests.").unwrap();
+	assert_eq!(config::find_instructions(&dir.join("src/deep"), config::INSTRUCTIONS_FILE), Some(dir.join("AGENTS.md")));
+	fs::create_dir_all(dir.join("src/.git")).unwrap();
+	assert_eq!(config::find_instructions(&dir.join("src/deep"), config::INSTRUCTIONS_FILE), None);
+	assert_eq!(config::find_instructions(&dir, ""), None);
+	let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn init_test() {
+	let dir = std::env::temp_dir().join("openaiclient_init_test");
+	let _ = fs::remove_dir_all(&dir);
+	let settings = config::InitSettings{ api_base: "http://localhost:11434/v1/".to_string(), api_key: "key \"quoted\"".to_string(), model: String::new() };
+	config::init(&dir.join("config.toml"), &dir, &dir.join("chats"), &settings, false).unwrap();
+	let config: config::Config = helpers::read_from_toml(dir.join("config.toml")).unwrap();
+	assert_eq!(config.profile.api_key.as_deref(), Some("key \"quoted\""));
+	assert_eq!(config.profile.model.as_deref(), Some(config::INIT_MODEL));
+	assert_eq!(config.chats_dir, Some(dir.join("chats")));
+	assert!(dir.join("chats").is_dir());
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		assert_eq!(fs::metadata(dir.join("config.toml")).unwrap().permissions().mode() & 0o777, 0o600);
+	}
+	let chat: openaiapi::Chat = helpers::read_from_json(dir.join("empty_chat.json")).unwrap();
+	assert_eq!(chat.tools.unwrap().len(), tools::ToolRegistry::with_builtin(None, None, None).names().len());
+	assert!(config::init(&dir.join("config.toml"), &dir, &dir.join("chats"), &settings, false).is_err());
+	config::init(&dir.join("config.toml"), &dir, &dir.join("chats"), &settings, true).unwrap();
+	let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn chat_templates_test() {
+	use openaiapi::ChatStore;
+	let dir = std::env::temp_dir().join("openaiclient_chat_templates_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(dir.join("templates")).unwrap();
+	fs::create_dir_all(dir.join("chats")).unwrap();
+	fs::copy("data/empty_chat.json", dir.join("empty_chat.json")).unwrap();
+	fs::write(dir.join("templates/writing.json"), r#"{"model":"writer","messages":[{"role":"system","content":"Write well."}],"temperature":1.2}"#).unwrap();
+	fs::write(dir.join("SYSTEM_PROMPT.md"), "From the prompt template.").unwrap();
+	assert_eq!(openaiapi::store::list_templates(&dir).unwrap(), vec!["writing"]);
+	assert!(openaiapi::store::template_file(&dir, "../empty_chat").is_err());
+	let store = openaiapi::JsonDirStore::new(dir.clone(), dir.join("chats")).with_template(Some("writing".to_string()));
+	let chat = store.new_chat("1").unwrap();
+	assert_eq!(chat.model, "writer");
+	assert_eq!(chat.temperature, Some(1.2));
+	assert_eq!(chat.messages[0].content.as_ref().unwrap().text(), "Write well.");
+	let chat = openaiapi::JsonDirStore::new(dir.clone(), dir.join("chats")).new_chat("2").unwrap();
+	assert_eq!(chat.messages[0].content.as_ref().unwrap().text(), "From the prompt template.");
+	assert!(openaiapi::JsonDirStore::new(dir.clone(), dir.join("chats")).with_template(Some("coding".to_string())).new_chat("3").is_err());
+	// a project's template can read its own files but no secrets
+	let project_dir = dir.join("project");
+	fs::create_dir_all(&project_dir).unwrap();
+	fs::write(project_dir.join("notes.md"), "project notes").unwrap();
+	fs::write(dir.join("secret.txt"), "secret").unwrap();
+	let project_store = |prompt: &str| {
+		fs::write(project_dir.join("project_prompt.md"), prompt).unwrap();
+		openaiapi::JsonDirStore::new(dir.clone(), dir.join("chats")).with_system_prompt(Some(project_dir.join("project_prompt.md"))).with_prompt_project(Some(project_dir.clone()))
+	};
+	assert!(project_store("{{cmd:echo hi}}").new_chat("4").is_err());
+	assert!(project_store("{{env:PATH}}").new_chat("4").is_err());
+	assert!(project_store(&format!("{{{{file:{}}}}}", dir.join("secret.txt").display())).new_chat("4").is_err());
+	assert!(project_store(&format!("{{{{file:{}}}}}", project_dir.join("../secret.txt").display())).new_chat("4").is_err());
+	let chat = project_store(&format!("{{{{file:{}}}}}", project_dir.join("notes.md").display())).new_chat("4").unwrap();
+	assert_eq!(chat.messages[0].content.as_ref().unwrap().text(), "project notes");
+	let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn set_system_message_test() {
+	let mut ctx = openaiapi::ChatContext::new(PathBuf::from("data"), PathBuf::from("chats"), "http://localhost/".to_string(), String::new()).unwrap();
+	ctx.chat = Some(helpers::read_from_json("data/empty_chat.json").unwrap());
+	ctx.current_chat().unwrap().messages.retain(|message| message.role != "system");
+	ctx.add_normal_message("user", "hello").unwrap();
+	ctx.set_system_message("Be brief.", false).unwrap();
+	assert_eq!(ctx.current_chat().unwrap().messages[0].role, "system");
+	assert!(ctx.set_system_message("Be verbose.", false).is_err());
+	ctx.set_system_message("Be verbose.", true).unwrap();
+	let messages = &ctx.current_chat().unwrap().messages;
+	assert_eq!(messages.len(), 2);
+	assert_eq!(messages[0].content.as_ref().unwrap().text(), "Be verbose.");
+	ctx.current_chat().unwrap().append_system_message("Run the tests.");
+	assert_eq!(ctx.current_chat().unwrap().messages[0].content.as_ref().unwrap().text(), "Be verbose.\n\nRun the tests.");
+}
+
+#[test]
+fn pending_tool_calls_test() {
+	let mut ctx = openaiapi::ChatContext::new(PathBuf::from("data"), PathBuf::from("chats"), "http://localhost/".to_string(), String::new()).unwrap();
+	ctx.chat = Some(helpers::read_from_json("data/empty_chat.json").unwrap());
+	ctx.add_normal_message("user", "list the files and the date").unwrap();
+	let mut assistant = openaiapi::Message::normal("assistant".to_string(), String::new());
+	assistant.tool_calls = Some(vec![
+		openaiapi::ToolCall{ id: "call_1".to_string(), tool_type: "function".to_string(), function: openaiapi::FunctionCall{ name: "execute".to_string(), arguments: r#"{"command":"ls"}"#.to_string() } },
+		openaiapi::ToolCall{ id: "call_2".to_string(), tool_type: "function".to_string(), function: openaiapi::FunctionCall{ name: "execute".to_string(), arguments: r#"{"command":"date"}"#.to_string() } },
+	]);
+	ctx.add_message(assistant).unwrap();
+	assert_eq!(ctx.get_pending_tool_calls().unwrap().len(), 2);
+	ctx.add_tool_message("tool", "execute", None, "a.txt").unwrap();
+	let pending = ctx.get_pending_tool_calls().unwrap();
+	assert_eq!(pending.len(), 1);
+	assert_eq!(pending[0].id, "call_2");
+	// removing the response leaves the assistant without it, so its tool calls go too
+	assert_eq!(ctx.rewind(1).unwrap(), 2);
+	assert!(ctx.get_pending_tool_calls().unwrap().is_empty());
+}
+
+#[test]
+fn sampling_overrides_test() {
+	let mut ctx = openaiapi::ChatContext::new(PathBuf::from("data"), PathBuf::from("chats"), "http://localhost/".to_string(), String::new()).unwrap();
+	ctx.chat = Some(helpers::read_from_json("data/empty_chat.json").unwrap());
+	let overrides = openaiapi::SamplingOverrides{ temperature: Some(0.1), stop: Some(vec!["END".to_string()]), ..Default::default() };
+	let mut chat = ctx.chat.clone().unwrap();
+	let max_tokens = chat.max_tokens;
+	overrides.apply(&mut chat);
+	assert_eq!(chat.temperature, Some(0.1));
+	assert_eq!(chat.max_tokens, max_tokens);
+	assert_eq!(serde_json::to_value(&chat).unwrap()["stop"], serde_json::json!(["END"]));
+	let bias = openaiapi::SamplingOverrides{ logit_bias: Some([("1734".to_string(), -100)].into_iter().collect()), ..Default::default() };
+	bias.apply(&mut chat);
+	bias.apply(&mut chat);
+	assert_eq!(serde_json::to_value(&chat).unwrap()["logit_bias"], serde_json::json!({"1734": -100}));
+	ctx.set_sampling(&openaiapi::SamplingOverrides{ top_p: Some(0.5), ..Default::default() }).unwrap();
+	assert_eq!(ctx.chat.as_ref().unwrap().top_p, Some(0.5));
+}
+
+#[test]
+fn profile_sampling_test() {
+	let template: openaiapi::Chat = helpers::read_from_json("data/empty_chat.json").unwrap();
+	let mut ctx = openaiapi::ChatContext::with_store(Box::new(openaiapi::MemoryStore::new(template)), "http://localhost/".to_string(), String::new()).unwrap();
+	ctx.load_or_new_chat("1001").unwrap();
+	ctx.set_sampling(&openaiapi::SamplingOverrides{ temperature: Some(0.3), logit_bias: Some([("1".to_string(), 5)].into_iter().collect()), ..Default::default() }).unwrap();
+	ctx.save_chat().unwrap();
+	// the command line comes before the profile, neither is saved in the chat
+	let profile = openaiapi::SamplingOverrides{ temperature: Some(0.9), top_p: Some(0.5), logit_bias: Some([("2".to_string(), -5)].into_iter().collect()), ..Default::default() };
+	let given = openaiapi::SamplingOverrides{ model: Some("other".to_string()), top_p: Some(0.7), ..Default::default() };
+	for _ in 0..2 {
+		ctx.load_chat("1001").unwrap();
+		ctx.overrides = given.with_defaults(&profile);
+		ctx.add_normal_message("user", "hello").unwrap();
+		ctx.save_chat().unwrap();
+	}
+	assert_eq!((ctx.overrides.temperature, ctx.overrides.top_p), (Some(0.9), Some(0.7)));
+	ctx.load_chat("1001").unwrap();
+	let chat = ctx.chat.as_ref().unwrap();
+	assert_eq!(serde_json::to_value(chat).unwrap()["temperature"], serde_json::json!(0.3));
+	assert_eq!(serde_json::to_value(chat).unwrap()["logit_bias"], serde_json::json!({"1": 5}));
+	assert_eq!(chat.top_p, Some(0.95));
+	assert_ne!(chat.model, "other");
+}
+
+#[test]
+fn rate_limit_test() {
+	use openaiapi::ratelimit::{parse_duration, RateLimit};
+	assert_eq!(parse_duration("1s"), Some(1.0));
+	assert_eq!(parse_duration("6m0s"), Some(360.0));
+	assert_eq!(parse_duration("20ms"), Some(0.02));
+	assert_eq!(parse_duration("1h2m3.5s"), Some(3723.5));
+	assert_eq!(parse_duration("soon"), None);
+	let mut headers = reqwest::header::HeaderMap::new();
+	assert!(RateLimit::from_headers(&headers, 1000).is_none());
+	headers.insert("x-ratelimit-remaining-requests", "0".parse().unwrap());
+	headers.insert("x-ratelimit-reset-requests", "6s".parse().unwrap());
+	headers.insert("x-ratelimit-remaining-tokens", "500".parse().unwrap());
+	headers.insert("x-ratelimit-reset-tokens", "20ms".parse().unwrap());
+	let limit = RateLimit::from_headers(&headers, 1000).unwrap();
+	assert_eq!(limit, RateLimit{ remaining_requests: Some(0), remaining_tokens: Some(500), requests_reset_at: Some(1006), tokens_reset_at: Some(1001) });
+	assert_eq!(limit.delay(100, 1000), Some(6));
+	assert_eq!(limit.delay(100, 1006), None);
+	let tokens = RateLimit{ remaining_requests: Some(10), ..limit };
+	assert_eq!(tokens.delay(100, 1000), None);
+	assert_eq!(tokens.delay(1000, 1000), Some(1));
+}
+
+#[test]
+fn merge_json_test() {
+	let mut request = serde_json::json!({"model": "llama", "options": {"temperature": 0.2, "stop": ["a"]}});
+	openaiapi::merge_json(&mut request, &serde_json::json!({"options": {"top_k": 40, "stop": ["b"]}, "safe_prompt": true}));
+	assert_eq!(request, serde_json::json!({"model": "llama", "options": {"temperature": 0.2, "top_k": 40, "stop": ["b"]}, "safe_prompt": true}));
+	let chat: openaiapi::Chat = serde_json::from_str(r#"{"model":"m","messages":[],"extra_body":{"min_p":0.05}}"#).unwrap();
+	assert_eq!(chat.extra_body, Some(serde_json::json!({"min_p": 0.05})));
+}
+
+#[test]
+fn optional_sampling_test() {
+	// the penalties may be fractions and the missing parameters are not sent
+	let chat: openaiapi::Chat = serde_json::from_str(r#"{"model":"o1","messages":[],"frequency_penalty":0.5}"#).unwrap();
+	assert_eq!(chat.frequency_penalty, Some(0.5));
+	let json = serde_json::to_value(&chat).unwrap();
+	assert!(json.get("temperature").is_none());
+	assert!(json.get("stop").is_none());
+	assert_eq!(json["frequency_penalty"], 0.5);
+	assert!(!chat.wants_json());
+	let chat: openaiapi::Chat = serde_json::from_str(r#"{"model":"gpt-4o","messages":[],"response_format":{"type":"json_object"}}"#).unwrap();
+	assert!(chat.wants_json());
+}
+
+#[test]
+fn compact_range_test() {
+	let call = |id: &str| openaiapi::ToolCall{ id: id.to_string(), tool_type: "function".to_string(), function: openaiapi::FunctionCall{ name: "execute".to_string(), arguments: "{}".to_string() } };
+	let mut assistant = openaiapi::Message::normal("assistant".to_string(), String::new());
+	assistant.tool_calls = Some(vec![call("call_1"), call("call_2")]);
+	let messages = vec![
+		openaiapi::Message::normal("system".to_string(), "be brief".to_string()),
+		openaiapi::Message::normal("user".to_string(), "first".to_string()),
+		openaiapi::Message::normal("assistant".to_string(), "answer".to_string()),
+		openaiapi::Message::normal("user".to_string(), "second".to_string()),
+		assistant,
+		openaiapi::Message::tool_response("tool".to_string(), "execute".to_string(), "call_1".to_string(), "a".to_string()),
+		openaiapi::Message::tool_response("tool".to_string(), "execute".to_string(), "call_2".to_string(), "b".to_string()),
+	];
+	assert_eq!(openaiapi::compact_range(&messages, 4), (1, 3));
+	// the tool responses stay with the assistant message that made the calls
+	assert_eq!(openaiapi::compact_range(&messages, 1), (1, 4));
+	assert_eq!(openaiapi::compact_range(&messages, 0), (1, 7));
+	assert_eq!(openaiapi::compact_range(&messages, 10), (1, 1));
+}
+
+#[test]
+fn tokenizer_test() {
+	let tokenizer = openaiapi::tokens::Tokenizer::from_tiktoken("YQ== 0
+Yg== 1
+YWI= 2
+Yw== 3
+IA== 4
+").unwrap();
+	assert!(tokenizer.is_exact());
+	// "ab" is merged, there is no "abc"
+	assert_eq!(tokenizer.count("abc"), 2);
+	assert_eq!(tokenizer.count("ab ab"), 3);
+	assert!(openaiapi::tokens::Tokenizer::from_tiktoken("YQ== zero").is_err());
+	let approximate = openaiapi::tokens::Tokenizer::Approximate;
+	assert_eq!(approximate.count("12345678"), 2);
+	let mut chat: openaiapi::Chat = helpers::read_from_json("data/empty_chat.json").unwrap();
+	chat.messages = vec![openaiapi::Message::normal("user".to_string(), "abcd".to_string())];
+	assert_eq!(approximate.count_chat(&chat), openaiapi::tokens::MESSAGE_OVERHEAD + 1 + 1 + openaiapi::tokens::REPLY_OVERHEAD);
+	assert_eq!(openaiapi::tokens::encoding_for_model("gpt-4o-mini"), "o200k_base");
+	assert_eq!(openaiapi::tokens::encoding_for_model("gpt-4"), "cl100k_base");
+}
+
+#[test]
+fn chat_store_test() {
+	let template: openaiapi::Chat = helpers::read_from_json("data/empty_chat.json").unwrap();
+	let store = openaiapi::MemoryStore::new(template.clone());
+	let mut ctx = openaiapi::ChatContext::with_store(Box::new(store), "http://localhost/".to_string(), String::new()).unwrap();
+	ctx.load_or_new_chat("1001").unwrap();
+	ctx.add_normal_message("user", "hello").unwrap();
+	ctx.save_chat().unwrap();
+	ctx.load_or_new_chat("1002").unwrap();
+	assert_eq!(ctx.chat.as_ref().unwrap().messages.len(), template.messages.len());
+	ctx.load_chat("1001").unwrap();
+	assert_eq!(ctx.chat.as_ref().unwrap().messages.len(), template.messages.len() + 1);
+	assert!(ctx.load_chat("1003").is_err());
+}
+
+#[test]
+fn chat_encryption_test() {
+	let dir = std::env::temp_dir().join("openaiclient_encryption_test");
+	fs::create_dir_all(&dir).unwrap();
+	let chat: openaiapi::Chat = helpers::read_from_json("data/empty_chat.json").unwrap();
+	assert!(openaiapi::ChatKey::from_hex("00ff").is_err());
+	let key = openaiapi::ChatKey::from_hex(&"0123456789abcdef".repeat(4)).unwrap();
+	let other = openaiapi::ChatKey::from_hex(&"fedcba9876543210".repeat(4)).unwrap();
+	let encrypted = dir.join("encrypted.json");
+	openaiapi::store::write_chat_file(&encrypted, &chat, Some(&key)).unwrap();
+	assert!(fs::read(&encrypted).unwrap().starts_with(openaiapi::store::ENCRYPTED_MAGIC));
+	assert_eq!(openaiapi::store::read_chat_file(&encrypted, Some(&key)).unwrap().messages.len(), chat.messages.len());
+	assert!(openaiapi::store::read_chat_file(&encrypted, None).is_err());
+	assert!(openaiapi::store::read_chat_file(&encrypted, Some(&other)).is_err());
+	// plain chats are still read with a key
+	let plain = dir.join("plain.json");
+	openaiapi::store::write_chat_file(&plain, &chat, None).unwrap();
+	assert_eq!(openaiapi::store::read_chat_file(&plain, Some(&key)).unwrap().model, chat.model);
+	// an encrypted chat is not replaced by a new chat without the key
+	let mut ctx = openaiapi::ChatContext::new(PathBuf::from("data"), dir.clone(), "http://localhost/".to_string(), String::new()).unwrap();
+	assert!(ctx.load_or_new_chat("encrypted").is_err());
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn edit_message_test() {
+	let mut ctx = openaiapi::ChatContext::new(PathBuf::from("data"), PathBuf::from("chats"), "http://localhost/".to_string(), String::new()).unwrap();
+	ctx.chat = Some(helpers::read_from_json("data/empty_chat.json").unwrap());
+	ctx.add_normal_message("user", "what is the date?").unwrap();
+	let mut assistant = openaiapi::Message::normal("assistant".to_string(), String::new());
+	assistant.tool_calls = Some(vec![
+		openaiapi::ToolCall{ id: "call_1".to_string(), tool_type: "function".to_string(), function: openaiapi::FunctionCall{ name: "execute".to_string(), arguments: r#"{"command":"date"}"#.to_string() } },
+	]);
+	ctx.add_message(assistant).unwrap();
+	ctx.add_tool_message("tool", "execute", None, "Monday").unwrap();
+	let count = ctx.chat.as_ref().unwrap().messages.len();
+	ctx.edit_message(count - 1, "Tuesday").unwrap();
+	assert_eq!(ctx.chat.as_ref().unwrap().messages[count - 1].text().as_deref(), Some("Tuesday"));
+	assert!(ctx.edit_message(count, "nothing there").is_err());
+	// the response would no longer answer a tool call
+	assert!(ctx.delete_message(count - 2).is_err());
+	assert_eq!(ctx.chat.as_ref().unwrap().messages.len(), count);
+	ctx.delete_message(count - 1).unwrap();
+	ctx.delete_message(count - 2).unwrap();
+	assert_eq!(ctx.chat.as_ref().unwrap().messages.len(), count - 2);
+}
+
+#[test]
+fn workspace_root_test() {
+	let root = std::env::temp_dir().join("openaiclient_workspace_test");
+	fs::create_dir_all(root.join("src")).unwrap();
+	let root = root.canonicalize().unwrap();
+	let library = tools::files::FileLibrary::new(Some(root.clone()));
+	assert_eq!(library.resolve("src/main.rs").unwrap(), root.join("src/main.rs"));
+	assert_eq!(library.resolve("new/dir/file.txt").unwrap(), root.join("new/dir/file.txt"));
+	assert!(library.resolve("../outside.txt").is_err());
+	assert!(library.resolve("src/../../outside.txt").is_err());
+	assert!(library.resolve("/etc/passwd").is_err());
+}
+
+#[test]
+fn write_atomic_test() {
+	let dir = std::env::temp_dir().join("openaiclient_write_atomic_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	let file = dir.join("chat.json");
+	helpers::write_atomic(&file, "first").unwrap();
+	helpers::write_atomic(&file, "second").unwrap();
+	assert_eq!(fs::read_to_string(&file).unwrap(), "second");
+	// the temporary file is renamed, nothing else is left behind
+	assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+}
+
+#[test]
+fn pretend_tools_test() {
+	let diff = tools::files::unified_diff("greeting.txt", "a\nb\nc\n", "a\nB\nc\n");
+	assert_eq!(diff, "--- a/greeting.txt\n+++ b/greeting.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+	let file = std::env::temp_dir().join("openaiclient_pretend_test.txt");
+	let _ = fs::remove_file(&file);
+	let mut dispatcher = tools::Dispatcher::new(tools::ToolRegistry::with_builtin(None, None, None));
+	dispatcher.pretend = true;
+	let args = serde_json::json!({"filename": file, "content": "new\n"}).to_string();
+	let preview = dispatcher.call("write_file", &args).unwrap();
+	assert!(preview.contains("+new"));
+	assert!(!file.exists());
+}
+
+#[test]
+fn audit_log_test() {
+	assert_eq!(helpers::format_utc(0), "1970-01-01T00:00:00Z");
+	assert_eq!(helpers::format_utc(1709210096), "2024-02-29T12:34:56Z");
+	let file = std::env::temp_dir().join("openaiclient_audit_test.jsonl");
+	let _ = fs::remove_file(&file);
+	let mut dispatcher = tools::Dispatcher::new(tools::ToolRegistry::with_builtin(None, None, None));
+	dispatcher.audit = Some(tools::audit::AuditLog::new(file.clone(), "1001"));
+	assert!(dispatcher.call("no_such_tool", "{}").is_err());
+	let entries = tools::audit::AuditLog::read(&file, Some("1001")).unwrap();
+	assert_eq!(entries.len(), 1);
+	assert_eq!(entries[0].tool, "no_such_tool");
+	assert!(!entries[0].success);
+	assert!(tools::audit::AuditLog::read(&file, Some("1002")).unwrap().is_empty());
+}
+
+#[test]
+fn chats_test() {
+	let dir = std::env::temp_dir().join("openaiclient_chats_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	fs::copy("data/empty_chat.json", dir.join("1001.json")).unwrap();
+	chats::copy(&dir, "1001", "1002").unwrap();
+	assert!(chats::copy(&dir, "1001", "1002").is_err());
+	chats::rename(&dir, "1002", "1003").unwrap();
+	chats::delete(&dir, "1001").unwrap();
+	assert!(chats::delete(&dir, "1001").is_err());
+	let list = chats::list(&dir, None).unwrap();
+	assert_eq!(list.len(), 1);
+	assert_eq!(list[0].chat_id, "1003");
+	assert!(list[0].messages.is_some());
+	chats::fork(&dir, "1003:0", "1004", None).unwrap();
+	let forked: openaiapi::Chat = helpers::read_from_json(dir.join("1004.json")).unwrap();
+	assert!(forked.messages.is_empty());
+	assert!(chats::fork(&dir, "1003:1000", "1005", None).is_err());
+	assert!(chats::fork(&dir, "1003", "1004", None).is_err());
+}
+
+#[test]
+fn chat_search_test() {
+	assert_eq!(helpers::parse_date("2024-02-29"), Some(1709164800));
+	assert_eq!(helpers::parse_date("2024-13-01"), None);
+	let dir = std::env::temp_dir().join("openaiclient_chat_search_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	let mut chat: openaiapi::Chat = helpers::read_from_json("data/empty_chat.json").unwrap();
+	chat.messages.clear();
+	chat.messages.push(openaiapi::Message::normal("user".to_string(), "How does a Borrow Checker work?".to_string()));
+	chat.messages.push(openaiapi::Message::normal("assistant".to_string(), "The borrow checker\nmakes sure...".to_string()));
+	helpers::save_to_json(dir.join("1001.json"), &chat).unwrap();
+	let found = chats::search(&dir, "borrow checker", &chats::SearchFilter::default(), None).unwrap();
+	assert_eq!(found.len(), 2);
+	assert_eq!(found[0].matched, "Borrow Checker");
+	assert_eq!(found[1].after, " makes sure...");
+	let filter = chats::SearchFilter{ role: Some("assistant".to_string()), ..Default::default() };
+	assert_eq!(chats::search(&dir, "borrow", &filter, None).unwrap().len(), 1);
+	let filter = chats::SearchFilter{ until: Some(0), ..Default::default() };
+	assert!(chats::search(&dir, "borrow", &filter, None).unwrap().is_empty());
+}
+
+#[test]
+fn dump_test() {
+	let mut chat: openaiapi::Chat = helpers::read_from_json("data/empty_chat.json").unwrap();
+	chat.messages.clear();
+	chat.messages.push(openaiapi::Message::normal("user".to_string(), "is 1 < 2?".to_string()));
+	let mut assistant = openaiapi::Message::normal("assistant".to_string(), String::new());
+	assistant.tool_calls = Some(vec![
+		openaiapi::ToolCall{ id: "call_1".to_string(), tool_type: "function".to_string(), function: openaiapi::FunctionCall{ name: "execute".to_string(), arguments: r#"{"command":"test 1 -lt 2"}"#.to_string() } },
+	]);
+	assistant.created_at = Some(0);
+	assistant.model = Some("gpt-4o".to_string());
+	assistant.usage = Some(openaiapi::Usage{ prompt_tokens: 12, completion_tokens: 3, total_tokens: 15 });
+	chat.messages.push(assistant);
+	let markdown = chats::dump(&chat, chats::DumpFormat::Markdown).unwrap();
+	assert!(markdown.starts_with("## user\n\nis 1 < 2?\n\n## assistant\n\n_1970-01-01T00:00:00Z, gpt-4o, 12 + 3 = 15 tokens_\n\n"));
+	assert!(markdown.contains("```json\n{\"command\":\"test 1 -lt 2\"}\n```"));
+	assert!(chats::dump(&chat, chats::DumpFormat::Html).unwrap().contains("<pre>is 1 &lt; 2?</pre>"));
+	let json: serde_json::Value = serde_json::from_str(&chats::dump(&chat, chats::DumpFormat::Json).unwrap()).unwrap();
+	assert_eq!(json["messages"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn cost_test() {
+	let mut configured = std::collections::HashMap::new();
+	configured.insert("llama".to_string(), chats::cost::Price{ prompt: 0.0, completion: 0.0 });
+	configured.insert("gpt-4o".to_string(), chats::cost::Price{ prompt: 0.005, completion: 0.015 });
+	let pricing = chats::cost::Pricing::new(&configured);
+	// the longest matching name is used and the configured prices replace the defaults
+	assert_eq!(pricing.price("gpt-4o-2024-05-13").unwrap().prompt, 0.005);
+	assert_eq!(pricing.price("gpt-4o-mini-2024-07-18").unwrap().prompt, 0.00015);
+	assert!(pricing.price("claude").is_none());
+	let (from, until) = chats::cost::month_range("2024-12").unwrap();
+	assert_eq!(helpers::format_utc(from), "2024-12-01T00:00:00Z");
+	assert_eq!(helpers::format_utc(until), "2025-01-01T00:00:00Z");
+	assert!(chats::cost::month_range("2024-13").is_none());
+	let mut chat: openaiapi::Chat = helpers::read_from_json("data/empty_chat.json").unwrap();
+	chat.messages.clear();
+	for (model, created_at) in [("gpt-4o", from), ("gpt-4o", until), ("claude", from)] {
+		let mut message = openaiapi::Message::normal("assistant".to_string(), "ok".to_string());
+		message.model = Some(model.to_string());
+		message.created_at = Some(created_at);
+		message.usage = Some(openaiapi::Usage{ prompt_tokens: 1000, completion_tokens: 2000, total_tokens: 3000 });
+		chat.messages.push(message);
+	}
+	let all = chats::cost::chat_cost(&chat, &pricing, None);
+	assert_eq!((all.prompt_tokens, all.unpriced), (3000, 1));
+	assert!((all.cost - 0.07).abs() < 1e-9);
+	let december = chats::cost::chat_cost(&chat, &pricing, Some((from, until)));
+	assert_eq!(december.prompt_tokens, 2000);
+	assert!((december.cost - 0.035).abs() < 1e-9);
+	let mut check = chats::cost::BudgetCheck{ budget: chats::cost::Budget{ chat_cost: Some(0.1), ..Default::default() }, pricing: pricing, today: (from, until), others_today: chats::cost::CostSummary::default() };
+	assert!(check.check(&chat).is_ok());
+	check.budget.chat_tokens = Some(9000);
+	assert!(check.check(&chat).unwrap_err().contains("--force"));
+	check.budget = chats::cost::Budget{ daily_cost: Some(0.05), ..Default::default() };
+	assert!(check.check(&chat).is_ok());
+	check.others_today.cost = 0.02;
+	assert!(check.check(&chat).is_err());
+	assert_eq!(chats::cost::today(86400 * 3 + 5), (86400 * 3, 86400 * 4));
+}
+
+#[test]
+fn import_test() {
+	let conversation = serde_json::json!({
+		"id": "abc",
+		"current_node": "3",
+		"mapping": {
+			"1": {"message": null, "parent": null, "children": ["2"]},
+			"2": {"message": {"author": {"role": "user"}, "content": {"content_type": "text", "parts": ["Hello"]}}, "parent": "1", "children": ["3", "4"]},
+			"3": {"message": {"author": {"role": "assistant"}, "content": {"content_type": "text", "parts": ["Hi"]}}, "parent": "2", "children": []},
+			"4": {"message": {"author": {"role": "assistant"}, "content": {"content_type": "text", "parts": ["Other branch"]}}, "parent": "2", "children": []},
+		},
+	});
+	let messages = chats::import::chatgpt_messages(&conversation).unwrap();
+	assert_eq!(messages.len(), 2);
+	assert_eq!(messages[0].role, "user");
+	assert_eq!(messages[1].text().as_deref(), Some("Hi"));
+	let messages = chats::import::jsonl_messages("{\"role\":\"user\",\"content\":\"a\"}\n\n{\"role\":\"assistant\",\"content\":\"b\"}\n").unwrap();
+	assert_eq!(messages.len(), 2);
+	assert!(chats::import::jsonl_messages("{\"content\":\"a\"}").is_err());
+}
+
+#[test]
+fn undo_test() {
+	let root = std::env::temp_dir().join("openaiclient_undo_test");
+	let _ = fs::remove_dir_all(&root);
+	fs::create_dir_all(&root).unwrap();
+	let root = root.canonicalize().unwrap();
+	fs::write(root.join("file.txt"), "original\n").unwrap();
+	let library = tools::files::FileLibrary::new(Some(root.clone())).with_backups(Some(root.join("backups")));
+	library.write_file(tools::files::WriteFileArgs{ filename: "file.txt".to_string(), content: "changed\n".to_string() }).unwrap();
+	library.write_file(tools::files::WriteFileArgs{ filename: "new.txt".to_string(), content: "new\n".to_string() }).unwrap();
+	library.undo().unwrap();
+	assert!(!root.join("new.txt").exists());
+	library.undo().unwrap();
+	assert_eq!(fs::read_to_string(root.join("file.txt")).unwrap(), "original\n");
+	assert!(library.undo().is_err());
+}
+
+#[test]
+fn search_replace_test() {
+	let blocks = "Change the greeting:\n<<<<<<< SEARCH\n\tprintln!(\"hello\");\n=======\n\tprintln!(\"goodbye\");\n>>>>>>> REPLACE\n";
+	let edits = tools::files::parse_search_replace(blocks).unwrap();
+	assert_eq!(edits.len(), 1);
+	let content = "fn main() {\n\tprintln!(\"hello\");\n}\n";
+	let edited = tools::files::apply_edit(content, &edits[0]).unwrap();
+	assert_eq!(edited, "fn main() {\n\tprintln!(\"goodbye\");\n}\n");
+	assert!(tools::files::apply_edit(&edited, &edits[0]).is_err());
+	assert!(tools::files::parse_search_replace("<<<<<<< SEARCH\nunfinished\n").is_err());
+}
+
+#[test]
+fn apply_patch_test() {
+	let patch = "--- a/greeting.rs\n+++ b/greeting.rs\n@@ -1,3 +1,3 @@\n fn main() {\n-\tprintln!(\"hello\");\n+\tprintln!(\"goodbye\");\n }\n";
+	let files = tools::patch::parse_patch(patch).unwrap();
+	assert_eq!(files.len(), 1);
+	assert_eq!(files[0].new_path.as_deref(), Some("greeting.rs"));
+	// the hunk is found even though a line was added above it
+	let content = "// greeting\nfn main() {\n\tprintln!(\"hello\");\n}\n";
+	let (patched, report) = tools::patch::apply_hunks(content, &files[0].hunks);
+	assert_eq!(patched.unwrap(), "// greeting\nfn main() {\n\tprintln!(\"goodbye\");\n}\n");
+	assert_eq!(report[0]["line"], 2);
+	let (patched, report) = tools::patch::apply_hunks("fn main() {}\n", &files[0].hunks);
+	assert!(patched.is_none());
+	assert_eq!(report[0]["applied"], false);
+	// a renamed file is read from the old path, which is removed
+	let dir = std::env::temp_dir().join("openaiclient_apply_patch_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(dir.join("old.txt"), "one\n").unwrap();
+	let dispatcher = tools::Dispatcher::new(tools::ToolRegistry::with_builtin(Some(dir.clone()), None, None));
+	let patch = serde_json::json!({ "patch": "--- a/old.txt\n+++ b/new.txt\n@@ -1 +1 @@\n-one\n+two\n" }).to_string();
+	dispatcher.call("apply_patch", &patch).unwrap();
+	assert_eq!(fs::read_to_string(dir.join("new.txt")).unwrap(), "two\n");
+	assert!(!dir.join("old.txt").exists());
+	let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn glob_tool_test() {
+	let dispatcher = tools::Dispatcher::new(tools::ToolRegistry::with_builtin(None, None, None));
+	let result = dispatcher.call("glob", r#"{"pattern":"**/*.rs","root":"src"}"#).unwrap();
+	let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+	let paths = result["paths"].as_array().unwrap();
+	assert!(paths.iter().any(|p| p == "main.rs"));
+	assert!(paths.iter().any(|p| p == "tools/search.rs"));
+}
+
+#[test]
+fn list_files_tool_test() {
+	let dispatcher = tools::Dispatcher::new(tools::ToolRegistry::with_builtin(None, None, None));
+	let result = dispatcher.call("list_files", r#"{"path":"src","depth":2}"#).unwrap();
+	let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+	let entries = result["entries"].as_array().unwrap();
+	let tools = entries.iter().find(|e| e["name"] == "tools").unwrap();
+	assert_eq!(tools["type"], "dir");
+	assert!(tools["children"].as_array().unwrap().iter().any(|e| e["name"] == "search.rs" && e["type"] == "file"));
+	assert!(entries.iter().any(|e| e["name"] == "main.rs"));
+}
+
+#[test]
+fn grep_tool_test() {
+	let dispatcher = tools::Dispatcher::new(tools::ToolRegistry::with_builtin(None, None, None));
+	let result = dispatcher.call("grep", r#"{"pattern":"^mod tools;$","root":"src","include":["*.rs"],"context":1}"#).unwrap();
+	let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+	let matches = result["matches"].as_array().unwrap();
+	assert_eq!(matches.len(), 1);
+	assert_eq!(matches[0]["path"], "main.rs");
+	assert_eq!(matches[0]["before"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn models_test() {
+	assert_eq!(openaiapi::parse_models(r#"{"object":"list","data":[{"id":"gpt-4o"},{"id":"gpt-3.5-turbo"}]}"#).unwrap(), vec!["gpt-3.5-turbo", "gpt-4o"]);
+	assert_eq!(openaiapi::parse_models(r#"{"models":[{"name":"llama3.1:latest","model":"llama3.1:latest"}]}"#).unwrap(), vec!["llama3.1:latest"]);
+	assert!(openaiapi::parse_models(r#"{"error":"unauthorised"}"#).is_err());
+	let mut profile = config::Profile::default();
+	profile.api_base = Some("https://example.openai.azure.com/openai/deployments/gpt4/".to_string());
+	profile.api_version = Some("2024-02-01".to_string());
+	assert_eq!(profile.endpoint().unwrap().models_url, "https://example.openai.azure.com/openai/models?api-version=2024-02-01");
+	profile.api_base = Some("https://openrouter.ai/api/v1/".to_string());
+	profile.api_version = None;
+	assert_eq!(profile.endpoint().unwrap().models_url, "https://openrouter.ai/api/v1/models");
+	assert_eq!(profile.endpoint().unwrap().api_url("audio/transcriptions"), "https://openrouter.ai/api/v1/audio/transcriptions");
+}
+
+#[test]
+fn multipart_form_test() {
+	let form = openaiapi::audio::MultipartForm::new().text("model", "whisper-1").file("file", "note.mp3", "audio/mpeg", b"ID3");
+	let content_type = form.content_type();
+	let boundary = content_type.strip_prefix("multipart/form-data; boundary=").unwrap().to_string();
+	let body = String::from_utf8(form.finish()).unwrap();
+	assert_eq!(body, format!("--{b}\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n\
+		--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"note.mp3\"\r\nContent-Type: audio/mpeg\r\n\r\nID3\r\n--{b}--\r\n", b = boundary));
+	assert_eq!(openaiapi::audio::audio_mime(std::path::Path::new("Meeting.M4A")), "audio/mp4");
+}
+
+#[test]
+fn embeddings_test() {
+	let body = r#"{"object":"list","data":[{"object":"embedding","index":1,"embedding":[0.5,-1]},{"object":"embedding","index":0,"embedding":[0.25,2]}],"model":"text-embedding-3-small"}"#;
+	let embeddings = openaiapi::embeddings::parse_embeddings(body).unwrap();
+	assert_eq!(embeddings, vec![vec![0.25, 2.0], vec![0.5, -1.0]]);
+	assert_eq!(openaiapi::embeddings::to_f32_bytes(&embeddings[..1]), [0.25f32.to_le_bytes(), 2.0f32.to_le_bytes()].concat());
+	assert!(openaiapi::embeddings::parse_embeddings(r#"{"error":{"message":"bad model"}}"#).is_err());
+}
+
+#[test]
+fn vectorstore_test() {
+	let text: String = (1..=70).map(|line| format!("line {}\n", line)).collect();
+	let chunks = vectorstore::chunk_text("notes.md", &text);
+	assert_eq!(chunks.iter().map(|chunk| (chunk.start_line, chunk.end_line)).collect::<Vec<_>>(), vec![(1, 40), (31, 70)]);
+	assert!(vectorstore::chunk_text("blank.md", "\n\n").is_empty());
+	assert!((vectorstore::cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+	assert_eq!(vectorstore::cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+
+	let dir = std::env::temp_dir().join("openaiclient_vectorstore_test");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	let notes = dir.join("notes.md").display().to_string();
+	fs::write(&notes, &text).unwrap();
+	let mut store = vectorstore::VectorStore::open(&dir.join("vectors.sqlite3")).unwrap();
+	assert!(!store.is_current(&notes, 100, "small").unwrap());
+	assert_eq!(store.model().unwrap(), None);
+	store.replace_file(&notes, 100, "small", &chunks, &[vec![1.0, 0.0], vec![0.0, 1.0]]).unwrap();
+	assert!(store.is_current(&notes, 100, "small").unwrap());
+	assert_eq!(store.model().unwrap().as_deref(), Some("small"));
+	assert!(!store.is_current(&notes, 101, "small").unwrap());
+	assert!(!store.is_current(&notes, 100, "large").unwrap());
+	let found = store.search(&[0.1, 0.9], "small", 1).unwrap();
+	assert_eq!(found.len(), 1);
+	assert_eq!((found[0].chunk.start_line, found[0].chunk.end_line), (31, 70));
+	assert!(store.search(&[0.1, 0.9], "large", 1).unwrap().is_empty());
+	fs::remove_file(&notes).unwrap();
+	assert_eq!(store.remove_missing().unwrap(), 1);
+	assert!(store.search(&[0.1, 0.9], "small", 5).unwrap().is_empty());
+	let _ = fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_test() {
+	use std::io::{BufRead, Read, Write};
+	let socket = std::env::temp_dir().join(format!("openaiclient_unix_socket_test.{}", std::process::id()));
+	let _ = fs::remove_file(&socket);
+	let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+	let server = std::thread::spawn(move || {
+		let (stream, _) = listener.accept().unwrap();
+		let mut reader = std::io::BufReader::new(stream);
+		let mut head = Vec::new();
+		let mut line = String::new();
+		while reader.read_line(&mut line).unwrap() > 2 {
+			head.push(line.trim_end().to_string());
+			line.clear();
+		}
+		let length: usize = head.iter().find_map(|header| header.strip_prefix("Content-Length: ")).unwrap().parse().unwrap();
+		let mut body = vec![0; length];
+		reader.read_exact(&mut body).unwrap();
+		reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nx-ratelimit-remaining-requests: 9\r\n\r\n5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n").unwrap();
+		(head, String::from_utf8(body).unwrap())
+	});
+	let transport = openaiapi::Transport{ client: reqwest::Client::new(), unix_socket: Some(socket.clone()) };
+	let runtime = tokio::runtime::Runtime::new().unwrap();
+	let response = runtime.block_on(transport.execute(transport.client.post("http://localhost/v1/chat/completions?x=1").bearer_auth("key").body("{}"))).unwrap();
+	assert_eq!(response.status(), 200);
+	assert_eq!(response.headers()["x-ratelimit-remaining-requests"], "9");
+	assert_eq!(runtime.block_on(response.text()).unwrap(), "hello, world");
+	let (head, body) = server.join().unwrap();
+	assert_eq!(head[0], "POST /v1/chat/completions?x=1 HTTP/1.1");
+	assert!(head.contains(&"authorization: Bearer key".to_string()));
+	assert_eq!(body, "{}");
+	let _ = fs::remove_file(&socket);
+}
+
+#[test]
+fn render_test() {
+	let renderer = render::Renderer::new(30);
+	let rendered = renderer.render("# Title\n\nThe quick brown fox jumps over the lazy dog with `code` and **it**.\n\n- one\n- two\n\n```rust\nfn main() {}\n```\n");
+	let lines: Vec<&str> = rendered.lines().collect();
+	assert_eq!(lines[0], "\x1b[1m\x1b[4mTitle\x1b[0m");
+	assert_eq!(lines[2], "The quick brown fox jumps over\x1b[0m");
+	assert_eq!(lines[3], "the lazy dog with \x1b[0m\x1b[36mcode\x1b[0m and \x1b[0m\x1b[1mit\x1b[0m.\x1b[0m");
+	assert_eq!(lines[5], "• one\x1b[0m");
+	assert_eq!(lines[6], "• two\x1b[0m");
+	assert!(lines[8].contains("\x1b[38;2;") && lines[8].ends_with("\x1b[0m"));
+	assert_eq!(lines.len(), 9);
+	// 2 * 3 is not italic
+	assert_eq!(renderer.render("2 * 3 = *six*"), "2 * 3 = \x1b[0m\x1b[3msix\x1b[0m\x1b[0m\n");
+	assert_eq!(render::code_blocks("Run:\n\n```sh\nls\n```\n\nthen\n\n~~~\npwd\n~~~\n"), vec!["ls\n", "pwd\n"]);
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct SampleConfig {
 	name: String,
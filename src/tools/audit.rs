@@ -0,0 +1,88 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::helpers;
+
+/// Characters of the result kept in the audit log
+pub const SUMMARY_LENGTH: usize = 200;
+
+/// One tool call, a line of `audit.jsonl`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuditEntry {
+	/// UTC, e.g. 2024-01-31T12:00:00Z
+	pub timestamp: String,
+	pub chat_id: String,
+	pub tool: String,
+	/// FNV-1a hash of the arguments, the arguments themselves may be large or secret
+	pub arguments_hash: String,
+	pub success: bool,
+	#[serde(default)]
+	pub pretend: bool,
+	/// the start of the result (or error)
+	pub summary: String,
+}
+
+/// Stable across platforms and Rust versions, unlike `DefaultHasher`
+pub fn fnv1a(data: &[u8]) -> u64 {
+	data.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Append only log of the tool calls made by the Dispatcher
+pub struct AuditLog {
+	path: PathBuf,
+	chat_id: String,
+}
+
+impl AuditLog {
+	pub fn new(path: PathBuf, chat_id: &str) -> Self {
+		AuditLog{ path: path, chat_id: chat_id.to_string() }
+	}
+
+	pub fn record(&self, tool: &str, args: &str, result: &Result<String, String>, pretend: bool) -> io::Result<()> {
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+		let (success, text) = match result {
+			Ok(text) => (true, text),
+			Err(text) => (false, text),
+		};
+		let entry = AuditEntry{
+			timestamp: helpers::format_utc(now),
+			chat_id: self.chat_id.clone(),
+			tool: tool.to_string(),
+			arguments_hash: format!("{:016x}", fnv1a(args.as_bytes())),
+			success: success,
+			pretend: pretend,
+			summary: text.chars().take(SUMMARY_LENGTH).collect(),
+		};
+		if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+			fs::create_dir_all(parent)?;
+		}
+		let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+		// one write per line so concurrent runs do not interleave within a line
+		let line = serde_json::to_string(&entry).map_err(|e| io::Error::new(io::ErrorKind::Other, e))? + "\n";
+		file.write_all(line.as_bytes())
+	}
+
+	/// The recorded calls, oldest first, only those of `chat_id` when given
+	pub fn read(path: &PathBuf, chat_id: Option<&str>) -> io::Result<Vec<AuditEntry>> {
+		let file = match fs::File::open(path) {
+			Ok(file) => file,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(err) => return Err(err),
+		};
+		let mut entries = Vec::new();
+		for line in BufReader::new(file).lines() {
+			// a line cut short by a crash is skipped
+			let entry: AuditEntry = match serde_json::from_str(&line?) {
+				Ok(entry) => entry,
+				Err(_) => continue,
+			};
+			if chat_id.map(|id| id == entry.chat_id).unwrap_or(true) {
+				entries.push(entry);
+			}
+		}
+		Ok(entries)
+	}
+}
@@ -0,0 +1,347 @@
+// cargo check, test and fmt with their output reduced to the errors, the
+// failed tests and the files to format, so an agent does not read the whole
+// build log.
+
+use std::process::Command;
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use super::Tool;
+use super::files::FileLibrary;
+
+/// The most diagnostics (or failed tests) returned, the rest are counted
+pub const MAX_CARGO_ITEMS: usize = 50;
+/// The output of a failed test is cut to this many characters
+const MAX_TEST_OUTPUT: usize = 4000;
+
+/// cargo run in the workspace root (or the current directory)
+#[derive(Clone)]
+pub struct Cargo {
+	library: FileLibrary,
+}
+
+/// An error or warning of the compiler, at its primary span
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+	pub level: String,
+	pub message: String,
+	pub code: Option<String>,
+	pub file: Option<String>,
+	pub line: Option<u64>,
+	pub column: Option<u64>,
+}
+
+impl Diagnostic {
+	fn to_json(&self) -> serde_json::Value {
+		json!({ "level": self.level, "message": self.message, "code": self.code,
+			"file": self.file, "line": self.line, "column": self.column })
+	}
+}
+
+/// The diagnostics in the output of `cargo --message-format=json`, the other
+/// lines (those of the tests) are left out. The summaries of the compiler
+/// ("aborting due to...", "N warnings emitted") are not diagnostics.
+pub fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+	output.lines().filter_map(|line| {
+		let json: serde_json::Value = serde_json::from_str(line).ok()?;
+		if json.get("reason")?.as_str()? != "compiler-message" {
+			return None;
+		}
+		let message = json.get("message")?;
+		let text = message.get("message")?.as_str()?;
+		let spans = message.get("spans").and_then(|spans| spans.as_array()).cloned().unwrap_or_default();
+		if spans.is_empty() && (text.starts_with("aborting due to") || text.ends_with(" emitted")) {
+			return None;
+		}
+		let primary = spans.iter().find(|span| span.get("is_primary").and_then(|primary| primary.as_bool()).unwrap_or(false)).or(spans.first());
+		Some(Diagnostic{
+			level: message.get("level")?.as_str()?.to_string(),
+			message: text.to_string(),
+			code: message.get("code").and_then(|code| code.get("code")).and_then(|code| code.as_str()).map(|code| code.to_string()),
+			file: primary.and_then(|span| span.get("file_name")).and_then(|file| file.as_str()).map(|file| file.to_string()),
+			line: primary.and_then(|span| span.get("line_start")).and_then(|line| line.as_u64()),
+			column: primary.and_then(|span| span.get("column_start")).and_then(|column| column.as_u64()),
+		})
+	}).collect()
+}
+
+/// The tests that failed, their output and the `test result:` lines of the
+/// output of `cargo test`
+pub fn parse_test_output(output: &str) -> serde_json::Value {
+	let mut failed = Vec::new();
+	let mut results = Vec::new();
+	let mut outputs = serde_json::Map::new();
+	let mut current: Option<(String, String)> = None;
+	for line in output.lines() {
+		if let Some((name, text)) = current.as_mut() {
+			// the output of a test ends at the next one or at the list of failures
+			if line.starts_with("---- ") || line == "failures:" {
+				outputs.insert(name.clone(), json!(text.trim_end()));
+				current = None;
+			} else {
+				text.push_str(line);
+				text.push('\n');
+				continue;
+			}
+		}
+		if let Some(name) = line.strip_prefix("---- ").and_then(|rest| rest.strip_suffix(" stdout ----")) {
+			current = Some((name.to_string(), String::new()));
+		} else if let Some(name) = line.strip_prefix("test ").and_then(|rest| rest.strip_suffix(" ... FAILED")) {
+			failed.push(name.to_string());
+		} else if line.starts_with("test result:") {
+			results.push(line.trim_start_matches("test result:").trim().to_string());
+		}
+	}
+	if let Some((name, text)) = current {
+		outputs.insert(name, json!(text.trim_end()));
+	}
+	let failures: Vec<serde_json::Value> = failed.iter().map(|name| {
+		let mut output = outputs.get(name).and_then(|output| output.as_str()).unwrap_or("").to_string();
+		if output.len() > MAX_TEST_OUTPUT {
+			let mut end = MAX_TEST_OUTPUT;
+			while !output.is_char_boundary(end) {
+				end -= 1;
+			}
+			output.truncate(end);
+		}
+		json!({ "test": name, "output": output })
+	}).collect();
+	json!({ "failures": failures, "results": results })
+}
+
+/// The files and lines `cargo fmt --check` would change, from its
+/// `Diff in FILE at line N:` (or `Diff in FILE:N:`) headers
+pub fn parse_fmt_output(output: &str) -> Vec<serde_json::Value> {
+	output.lines().filter_map(|line| {
+		let rest = line.strip_prefix("Diff in ")?.strip_suffix(':')?;
+		let (file, line) = match rest.rsplit_once(" at line ") {
+			Some((file, line)) => (file, line),
+			None => rest.rsplit_once(':')?,
+		};
+		Some(json!({ "file": file, "line": line.trim().parse::<u64>().ok() }))
+	}).collect()
+}
+
+/// At most `MAX_CARGO_ITEMS` of the items and how many were left out
+fn limit(mut items: Vec<serde_json::Value>) -> (Vec<serde_json::Value>, usize) {
+	let omitted = items.len().saturating_sub(MAX_CARGO_ITEMS);
+	items.truncate(MAX_CARGO_ITEMS);
+	(items, omitted)
+}
+
+impl Cargo {
+	pub fn new(library: FileLibrary) -> Self {
+		Cargo{ library: library }
+	}
+
+	/// Run cargo with `args` in the workspace, whether it succeeded and its
+	/// standard output and error
+	fn run(&self, args: &[String]) -> Result<(bool, String, String), String> {
+		let dir = self.library.resolve(".")?;
+		let output = Command::new("cargo").args(args).current_dir(&dir).output().map_err(|e| format!("Failed to start cargo: {}", e))?;
+		Ok((output.status.success(), String::from_utf8_lossy(&output.stdout).to_string(), String::from_utf8_lossy(&output.stderr).to_string()))
+	}
+
+	/// The options selecting the package, which must not be taken for other options
+	fn package_args(package: &Option<String>) -> Result<Vec<String>, String> {
+		match package {
+			Some(package) if package.starts_with('-') => Err(format!("Invalid package {}", package)),
+			Some(package) => Ok(vec!["--package".to_string(), package.clone()]),
+			None => Ok(Vec::new()),
+		}
+	}
+
+	pub fn check_command(&self, args: &CargoCheckArgs) -> Result<Vec<String>, String> {
+		let mut command = vec!["check".to_string(), "--message-format=json".to_string()];
+		if args.all_targets {
+			command.push("--all-targets".to_string());
+		}
+		command.extend(Self::package_args(&args.package)?);
+		Ok(command)
+	}
+
+	pub fn check(&self, args: CargoCheckArgs) -> Result<String, String> {
+		let (success, stdout, stderr) = self.run(&self.check_command(&args)?)?;
+		let diagnostics = parse_diagnostics(&stdout);
+		let errors = diagnostics.iter().filter(|diagnostic| diagnostic.level == "error").count();
+		let warnings = diagnostics.iter().filter(|diagnostic| diagnostic.level == "warning").count();
+		let (diagnostics, omitted) = limit(diagnostics.iter().map(|diagnostic| diagnostic.to_json()).collect());
+		let mut result = json!({ "success": success, "errors": errors, "warnings": warnings, "diagnostics": diagnostics, "omitted": omitted });
+		// cargo itself failed (no manifest, a dependency that cannot be fetched...)
+		if !success && errors == 0 {
+			result["stderr"] = json!(stderr.trim());
+		}
+		Ok(result.to_string())
+	}
+
+	pub fn test_command(&self, args: &CargoTestArgs) -> Result<Vec<String>, String> {
+		let mut command = vec!["test".to_string(), "--message-format=json".to_string()];
+		command.extend(Self::package_args(&args.package)?);
+		if let Some(filter) = args.filter.as_ref() {
+			if filter.starts_with('-') {
+				return Err(format!("Invalid filter {}", filter));
+			}
+			command.push(filter.clone());
+		}
+		Ok(command)
+	}
+
+	pub fn test(&self, args: CargoTestArgs) -> Result<String, String> {
+		let (success, stdout, stderr) = self.run(&self.test_command(&args)?)?;
+		let diagnostics: Vec<Diagnostic> = parse_diagnostics(&stdout).into_iter().filter(|diagnostic| diagnostic.level == "error").collect();
+		let mut result = parse_test_output(&stdout);
+		let (failures, omitted) = limit(result["failures"].as_array().cloned().unwrap_or_default());
+		result["failures"] = json!(failures);
+		result["omitted"] = json!(omitted);
+		result["success"] = json!(success);
+		if !diagnostics.is_empty() {
+			let (errors, _) = limit(diagnostics.iter().map(|diagnostic| diagnostic.to_json()).collect());
+			result["errors"] = json!(errors);
+		} else if !success && failures.is_empty() {
+			result["stderr"] = json!(stderr.trim());
+		}
+		Ok(result.to_string())
+	}
+
+	pub fn fmt_check(&self) -> Result<String, String> {
+		let (success, stdout, stderr) = self.run(&["fmt".to_string(), "--check".to_string()])?;
+		let (files, omitted) = limit(parse_fmt_output(&stdout));
+		let mut result = json!({ "formatted": success, "diffs": files, "omitted": omitted });
+		if !success && files.is_empty() {
+			result["stderr"] = json!(stderr.trim());
+		}
+		Ok(result.to_string())
+	}
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CargoCheckArgs {
+	/// only this package of the workspace
+	pub package: Option<String>,
+	/// the tests, examples and benchmarks too
+	#[serde(default)]
+	pub all_targets: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CargoTestArgs {
+	pub package: Option<String>,
+	/// only the tests whose name contains this
+	pub filter: Option<String>,
+}
+
+pub struct CargoCheckTool {
+	cargo: Cargo,
+}
+
+impl CargoCheckTool {
+	pub fn new(cargo: Cargo) -> Self {
+		CargoCheckTool{ cargo: cargo }
+	}
+}
+
+impl Tool for CargoCheckTool {
+	fn name(&self) -> &str {
+		"cargo_check"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "cargo_check",
+			"description": "run cargo check in the workspace and return the errors and warnings of the compiler with their file, line and column",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"package": {"type": "string", "description": "only this package of the cargo workspace"},
+					"all_targets": {"type": "boolean", "description": "check the tests, examples and benchmarks too"},
+				},
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: CargoCheckArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.cargo.check(args)
+	}
+
+	// destructive as build scripts and procedural macros run during the check
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: CargoCheckArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		Ok(format!("Would run cargo {}", self.cargo.check_command(&args)?.join(" ")))
+	}
+}
+
+pub struct CargoTestTool {
+	cargo: Cargo,
+}
+
+impl CargoTestTool {
+	pub fn new(cargo: Cargo) -> Self {
+		CargoTestTool{ cargo: cargo }
+	}
+}
+
+impl Tool for CargoTestTool {
+	fn name(&self) -> &str {
+		"cargo_test"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "cargo_test",
+			"description": "run cargo test in the workspace and return the summary of each test binary, the tests that failed with their output and any compile errors",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"package": {"type": "string", "description": "only the tests of this package of the cargo workspace"},
+					"filter": {"type": "string", "description": "only the tests whose name contains this"},
+				},
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: CargoTestArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.cargo.test(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: CargoTestArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		Ok(format!("Would run cargo {}", self.cargo.test_command(&args)?.join(" ")))
+	}
+}
+
+pub struct CargoFmtTool {
+	cargo: Cargo,
+}
+
+impl CargoFmtTool {
+	pub fn new(cargo: Cargo) -> Self {
+		CargoFmtTool{ cargo: cargo }
+	}
+}
+
+impl Tool for CargoFmtTool {
+	fn name(&self) -> &str {
+		"cargo_fmt"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "cargo_fmt",
+			"description": "run cargo fmt --check in the workspace and return the files and lines that are not formatted (nothing is changed)",
+			"parameters": {
+				"type": "object",
+				"properties": {},
+			},
+		})
+	}
+
+	fn call(&self, _args: &str) -> Result<String, String> {
+		self.cargo.fmt_check()
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
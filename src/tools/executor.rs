@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use super::Tool;
+
+/// Commands that run for longer than this are terminated
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub const SHELLS: &[&str] = &["sh", "bash", "cmd", "powershell", "pwsh"];
+
+#[derive(Deserialize, Debug)]
+pub struct ExecuteArgs {
+	#[serde(default)]
+	pub command: String,
+	/// the shell used to run `command`
+	pub shell: Option<String>,
+	/// program and arguments to run directly without a shell (instead of `command`)
+	pub argv: Option<Vec<String>>,
+	/// working directory for the command
+	pub cwd: Option<String>,
+	/// environment variables added to those of the client
+	#[serde(default)]
+	pub env: HashMap<String, String>,
+	/// replaces the default timeout
+	pub timeout_secs: Option<u64>,
+	/// written to the standard input of the command
+	pub stdin: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Executor {
+	pub timeout: Duration,
+	/// the shell used when the model does not choose one
+	pub default_shell: String,
+}
+
+/// `sh` everywhere except Windows where it is `cmd`
+pub fn platform_shell() -> String {
+	if cfg!(windows) { "cmd".to_string() } else { "sh".to_string() }
+}
+
+impl Executor {
+	pub fn new(default_shell: Option<String>) -> Self {
+		Executor{ timeout: DEFAULT_TIMEOUT, default_shell: default_shell.unwrap_or_else(platform_shell) }
+	}
+
+	fn command(&self, args: &ExecuteArgs) -> Result<Command, String> {
+		if let Some(argv) = args.argv.as_ref() {
+			let program = argv.first().ok_or("argv must contain the program to run".to_string())?;
+			let mut command = Command::new(program);
+			command.args(&argv[1..]);
+			return Ok(command);
+		}
+		if args.command.is_empty() {
+			return Err("either command or argv is required".to_string());
+		}
+		let shell = args.shell.as_deref().unwrap_or(&self.default_shell);
+		let mut command = Command::new(shell);
+		match shell {
+			"sh" | "bash" => command.arg("-c"),
+			"cmd" => command.arg("/C"),
+			"powershell" | "pwsh" => command.args(&["-NoProfile", "-NonInteractive", "-Command"]),
+			other => return Err(format!("Unsupported shell: {} (use one of {})", other, SHELLS.join(", "))),
+		};
+		command.arg(&args.command);
+		Ok(command)
+	}
+
+	/// The command that would run for `args`, not yet started
+	pub fn prepare(&self, args: &ExecuteArgs) -> Result<Command, String> {
+		let mut command = self.command(args)?;
+		if let Some(cwd) = args.cwd.as_ref() {
+			command.current_dir(cwd);
+		}
+		command.envs(&args.env);
+		Ok(command)
+	}
+
+	/// Describe the command that would run for `args`
+	pub fn preview_execute(&self, args: &ExecuteArgs) -> Result<String, String> {
+		let mut preview = format!("Would run: {:?}", self.prepare(args)?);
+		if let Some(stdin) = args.stdin.as_ref() {
+			preview.push_str(&format!("\nwith stdin:\n{}", stdin));
+		}
+		Ok(preview)
+	}
+
+	pub fn execute(&self, args: ExecuteArgs) -> Result<String, String> {
+		let mut command = self.prepare(&args)?;
+		#[cfg(unix)]
+		{
+			// own process group so that a timeout stops everything the command started
+			use std::os::unix::process::CommandExt;
+			command.process_group(0);
+		}
+		let mut child = command
+			.stdin(if args.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+			.map_err(|e| format!("Failed to start: {}", e))?;
+		// written on its own thread, the command may not read it all (or at all)
+		let writer = match (child.stdin.take(), args.stdin.clone()) {
+			(Some(mut pipe), Some(input)) => Some(thread::spawn(move || {
+				let _ = pipe.write_all(input.as_bytes());
+			})),
+			_ => None,
+		};
+		// read the pipes on their own threads so a full pipe cannot block the child
+		let stdout = read_pipe(child.stdout.take());
+		let stderr = read_pipe(child.stderr.take());
+		let timeout = args.timeout_secs.map(Duration::from_secs).unwrap_or(self.timeout);
+		let deadline = Instant::now() + timeout;
+		let mut timed_out = false;
+		let status = loop {
+			match child.try_wait().map_err(|e| e.to_string())? {
+				Some(status) => break status,
+				None if Instant::now() >= deadline => {
+					timed_out = true;
+					break terminate(&mut child).map_err(|e| e.to_string())?;
+				},
+				None => thread::sleep(Duration::from_millis(50)),
+			}
+		};
+		if let Some(writer) = writer {
+			let _ = writer.join();
+		}
+		let stdout = stdout.join().unwrap_or_default();
+		let stderr = stderr.join().unwrap_or_default();
+		Ok(json!({
+			"exit_code": status.code(),
+			"timed_out": timed_out,
+			"stdout": stdout,
+			"stderr": stderr,
+		}).to_string())
+	}
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+	thread::spawn(move || {
+		let mut output = Vec::new();
+		if let Some(mut pipe) = pipe {
+			let _ = pipe.read_to_end(&mut output);
+		}
+		String::from_utf8_lossy(&output).to_string()
+	})
+}
+
+/// Ask the process to stop (SIGTERM, or taskkill without /F on Windows), if
+/// it is still running after a short grace period then kill it.
+fn terminate(child: &mut Child) -> std::io::Result<std::process::ExitStatus> {
+	if cfg!(windows) {
+		let _ = Command::new("taskkill").args(&["/PID", &child.id().to_string(), "/T"]).status();
+	} else {
+		// negative pid: the process group, the children would keep the pipes open
+		let _ = Command::new("kill").arg("-TERM").arg("--").arg(format!("-{}", child.id())).status();
+	}
+	let deadline = Instant::now() + Duration::from_secs(5);
+	while Instant::now() < deadline {
+		if let Some(status) = child.try_wait()? {
+			return Ok(status);
+		}
+		thread::sleep(Duration::from_millis(50));
+	}
+	if cfg!(windows) {
+		// kill() only stops the shell, the whole tree must go
+		let _ = Command::new("taskkill").args(&["/PID", &child.id().to_string(), "/T", "/F"]).status();
+	} else {
+		let _ = Command::new("kill").arg("-KILL").arg("--").arg(format!("-{}", child.id())).status();
+	}
+	child.kill()?;
+	child.wait()
+}
+
+impl Tool for Executor {
+	fn name(&self) -> &str {
+		"execute"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "execute",
+			"description": "execute a command on the users computer",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"command": {"type": "string", "description": "the command to be executed by the shell"},
+					"shell": {"type": "string", "enum": SHELLS, "description": format!("the shell to use (default: {})", self.default_shell)},
+					"argv": {"type": "array", "items": {"type": "string"}, "description": "run this program with these arguments without a shell instead of command"},
+					"cwd": {"type": "string", "description": "directory to run the command in"},
+					"env": {"type": "object", "additionalProperties": {"type": "string"}, "description": "environment variables to set for the command"},
+					"timeout_secs": {"type": "integer", "description": format!("seconds before the command is stopped (default: {})", self.timeout.as_secs())},
+					"stdin": {"type": "string", "description": "text to write to the standard input of the command"},
+				},
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: ExecuteArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.execute(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: ExecuteArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.preview_execute(&args)
+	}
+}
@@ -0,0 +1,629 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::helpers;
+use super::Tool;
+
+#[derive(Deserialize, Debug)]
+pub struct ReadFileArgs {
+	pub filename: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WriteFileArgs {
+	pub filename: String,
+	pub content: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Edit {
+	pub old_string: String,
+	pub new_string: String,
+	/// replace every occurrence instead of requiring exactly one
+	#[serde(default)]
+	pub replace_all: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EditFileArgs {
+	pub filename: String,
+	#[serde(flatten)]
+	pub edit: Edit,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MultiEditArgs {
+	pub filename: String,
+	pub edits: Vec<Edit>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchReplaceArgs {
+	pub filename: String,
+	/// one or more SEARCH/REPLACE blocks, see `parse_search_replace`
+	pub blocks: String,
+}
+
+/// The content of a file before it was changed by a tool
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupFile {
+	pub path: PathBuf,
+	/// None when the file did not exist
+	pub content: Option<String>,
+}
+
+/// The files changed by one tool call, stored as `<n>.json` in the backups directory
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Backup {
+	pub files: Vec<BackupFile>,
+}
+
+/// Lines of context around the changes shown by `unified_diff`
+pub const DIFF_CONTEXT: usize = 3;
+/// Larger changes are shown as all old lines removed and all new lines added
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// The lines of `old` and `new` marked ' ' (in both), '-' (only in old) or
+/// '+' (only in new), in order.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+	let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+	let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+	let old_mid = &old[prefix..old.len() - suffix];
+	let new_mid = &new[prefix..new.len() - suffix];
+	let mut ops: Vec<(char, &str)> = old[..prefix].iter().map(|line| (' ', *line)).collect();
+	let (n, m) = (old_mid.len(), new_mid.len());
+	if n * m > MAX_DIFF_CELLS {
+		ops.extend(old_mid.iter().map(|line| ('-', *line)));
+		ops.extend(new_mid.iter().map(|line| ('+', *line)));
+	} else {
+		// lcs[i][j] is the length of the longest common subsequence of old_mid[i..] and new_mid[j..]
+		let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+		for i in (0..n).rev() {
+			for j in (0..m).rev() {
+				lcs[i][j] = if old_mid[i] == new_mid[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+			}
+		}
+		let (mut i, mut j) = (0, 0);
+		while i < n || j < m {
+			if i < n && j < m && old_mid[i] == new_mid[j] {
+				ops.push((' ', old_mid[i]));
+				i += 1;
+				j += 1;
+			} else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+				ops.push(('-', old_mid[i]));
+				i += 1;
+			} else {
+				ops.push(('+', new_mid[j]));
+				j += 1;
+			}
+		}
+	}
+	ops.extend(old[old.len() - suffix..].iter().map(|line| (' ', *line)));
+	ops
+}
+
+/// The changes from `old` to `new` as a unified diff of the file `name`
+pub fn unified_diff(name: &str, old: &str, new: &str) -> String {
+	let old_lines: Vec<&str> = old.lines().collect();
+	let new_lines: Vec<&str> = new.lines().collect();
+	let ops = diff_lines(&old_lines, &new_lines);
+	// the line in old and new before each op
+	let mut positions = Vec::with_capacity(ops.len());
+	let (mut old_line, mut new_line) = (0, 0);
+	for (op, _) in ops.iter() {
+		positions.push((old_line, new_line));
+		match op {
+			'-' => old_line += 1,
+			'+' => new_line += 1,
+			_ => {
+				old_line += 1;
+				new_line += 1;
+			},
+		}
+	}
+	let changed: Vec<usize> = ops.iter().enumerate().filter(|(_, (op, _))| *op != ' ').map(|(index, _)| index).collect();
+	let mut diff = format!("--- a/{}\n+++ b/{}\n", name, name);
+	let mut index = 0;
+	while index < changed.len() {
+		let start = changed[index].saturating_sub(DIFF_CONTEXT);
+		let mut end = changed[index];
+		// changes separated by little context are shown in one hunk
+		while index + 1 < changed.len() && changed[index + 1] <= end + 2 * DIFF_CONTEXT + 1 {
+			index += 1;
+			end = changed[index];
+		}
+		let end = (end + DIFF_CONTEXT + 1).min(ops.len());
+		let hunk = &ops[start..end];
+		let old_len = hunk.iter().filter(|(op, _)| *op != '+').count();
+		let new_len = hunk.iter().filter(|(op, _)| *op != '-').count();
+		let (old_line, new_line) = positions[start];
+		// an empty range starts at the line before it
+		let old_start = if old_len == 0 { old_line } else { old_line + 1 };
+		let new_start = if new_len == 0 { new_line } else { new_line + 1 };
+		diff.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_len, new_start, new_len));
+		for (op, line) in hunk.iter() {
+			diff.push(*op);
+			diff.push_str(line);
+			diff.push('\n');
+		}
+		index += 1;
+	}
+	diff
+}
+
+/// Apply the edit to `content`, the text to replace must be present exactly
+/// once unless `replace_all` is set.
+pub fn apply_edit(content: &str, edit: &Edit) -> Result<String, String> {
+	if edit.old_string.is_empty() {
+		return Err("old_string must not be empty".to_string());
+	}
+	match content.matches(&edit.old_string).count() {
+		0 => Err(format!("old_string not found: {}", edit.old_string)),
+		1 => Ok(content.replacen(&edit.old_string, &edit.new_string, 1)),
+		_ if edit.replace_all => Ok(content.replace(&edit.old_string, &edit.new_string)),
+		count => Err(format!("old_string found {} times, include more context or set replace_all: {}", count, edit.old_string)),
+	}
+}
+
+/// Parse blocks in the form:
+///
+/// ```text
+/// <<<<<<< SEARCH
+/// text to find
+/// =======
+/// replacement
+/// >>>>>>> REPLACE
+/// ```
+///
+/// Text outside of the blocks is ignored.
+pub fn parse_search_replace(blocks: &str) -> Result<Vec<Edit>, String> {
+	enum State { Outside, Search, Replace }
+	let mut state = State::Outside;
+	let mut edits = Vec::new();
+	let mut search = String::new();
+	let mut replace = String::new();
+	for line in blocks.lines() {
+		match state {
+			State::Outside if line.trim_end() == "<<<<<<< SEARCH" => {
+				search.clear();
+				replace.clear();
+				state = State::Search;
+			},
+			State::Outside => {},
+			State::Search if line.trim_end() == "=======" => state = State::Replace,
+			State::Search => {
+				search.push_str(line);
+				search.push('\n');
+			},
+			State::Replace if line.trim_end() == ">>>>>>> REPLACE" => {
+				edits.push(Edit{ old_string: search.clone(), new_string: replace.clone(), replace_all: false });
+				state = State::Outside;
+			},
+			State::Replace => {
+				replace.push_str(line);
+				replace.push('\n');
+			},
+		}
+	}
+	if !matches!(state, State::Outside) {
+		return Err("SEARCH/REPLACE block is not complete".to_string());
+	}
+	if edits.is_empty() {
+		return Err("No SEARCH/REPLACE blocks found".to_string());
+	}
+	Ok(edits)
+}
+
+/// File operations available to the model
+#[derive(Clone)]
+pub struct FileLibrary {
+	/// when present (and canonical) files outside of this directory are refused
+	root: Option<PathBuf>,
+	/// where the original content of changed files is kept for undo
+	backups: Option<PathBuf>,
+}
+
+impl FileLibrary {
+	pub fn new(root: Option<PathBuf>) -> Self {
+		FileLibrary{ root: root, backups: None }
+	}
+
+	pub fn with_backups(mut self, backups: Option<PathBuf>) -> Self {
+		self.backups = backups;
+		self
+	}
+
+	/// Numbers of the backups that have not been undone, oldest first
+	fn backup_numbers(dir: &Path) -> Vec<u64> {
+		let mut numbers: Vec<u64> = fs::read_dir(dir).into_iter().flatten()
+			.filter_map(|entry| entry.ok())
+			.filter_map(|entry| {
+				let path = entry.path();
+				if !helpers::has_specific_extension(&path, "json") {
+					return None;
+				}
+				path.file_stem()?.to_str()?.parse::<u64>().ok()
+			})
+			.collect();
+		numbers.sort();
+		numbers
+	}
+
+	/// Save the current content of `paths` before they are changed, does
+	/// nothing when there is no backups directory.
+	pub fn backup(&self, paths: &[PathBuf]) -> Result<(), String> {
+		let dir = match self.backups.as_ref() {
+			Some(dir) => dir,
+			None => return Ok(()),
+		};
+		fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+		let mut files = Vec::new();
+		for path in paths.iter() {
+			// relative paths would not be found by a later run in another directory
+			let path = if path.is_relative() {
+				std::env::current_dir().map_err(|e| e.to_string())?.join(path)
+			} else {
+				path.clone()
+			};
+			let content = if path.exists() {
+				Some(fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?)
+			} else {
+				None
+			};
+			files.push(BackupFile{ path: path, content: content });
+		}
+		let number = Self::backup_numbers(dir).last().map(|n| n + 1).unwrap_or(1);
+		helpers::save_to_json(dir.join(format!("{}.json", number)), &Backup{ files: files }).map_err(|e| e.to_string())
+	}
+
+	/// Restore the files saved by the most recent backup and remove it
+	pub fn undo(&self) -> Result<String, String> {
+		let dir = self.backups.as_ref().ok_or("No backups are kept".to_string())?;
+		let number = Self::backup_numbers(dir).pop().ok_or("Nothing to undo".to_string())?;
+		let backup_file = dir.join(format!("{}.json", number));
+		let backup: Backup = helpers::read_from_json(&backup_file).map_err(|e| format!("{}: {}", backup_file.display(), e))?;
+		let mut restored = Vec::new();
+		for file in backup.files.iter() {
+			let result = match file.content.as_ref() {
+				Some(content) => helpers::write_atomic(&file.path, content),
+				None => fs::remove_file(&file.path),
+			};
+			result.map_err(|e| format!("{}: {}", file.path.display(), e))?;
+			restored.push(file.path.display().to_string());
+		}
+		fs::remove_file(&backup_file).map_err(|e| format!("{}: {}", backup_file.display(), e))?;
+		Ok(format!("Restored {}", restored.join(", ")))
+	}
+
+	/// The path to use for `filename`, relative paths are relative to the
+	/// root. Paths that leave the root (with `..` or through a symlink) are
+	/// refused.
+	pub fn resolve(&self, filename: &str) -> Result<PathBuf, String> {
+		let root = match self.root.as_ref() {
+			Some(root) => root,
+			None => return Ok(PathBuf::from(filename)),
+		};
+		let path = root.join(filename);
+		// the file (and directories) may not exist yet, so the nearest
+		// existing ancestor is canonicalized and the rest appended
+		let mut existing = path.as_path();
+		let mut rest = Vec::new();
+		while fs::symlink_metadata(existing).is_err() {
+			rest.push(existing.file_name().ok_or(format!("{}: invalid path", filename))?);
+			existing = existing.parent().ok_or(format!("{}: invalid path", filename))?;
+		}
+		let mut resolved = existing.canonicalize().map_err(|e| format!("{}: {}", filename, e))?;
+		for component in rest.iter().rev() {
+			resolved.push(component);
+		}
+		if resolved.starts_with(root) {
+			Ok(resolved)
+		} else {
+			Err(format!("{}: outside of the workspace {}", filename, root.display()))
+		}
+	}
+
+	pub fn read_file(&self, args: ReadFileArgs) -> Result<String, String> {
+		let path = self.resolve(&args.filename)?;
+		fs::read_to_string(&path).map_err(|e| format!("{}: {}", args.filename, e))
+	}
+
+	pub fn write_file(&self, args: WriteFileArgs) -> Result<String, String> {
+		let path = self.resolve(&args.filename)?;
+		self.backup(&[path.clone()])?;
+		helpers::write_atomic(&path, &args.content).map_err(|e| format!("{}: {}", args.filename, e))?;
+		Ok(format!("Wrote {} bytes to {}", args.content.len(), args.filename))
+	}
+
+	/// The diff write_file would make
+	pub fn preview_write_file(&self, args: WriteFileArgs) -> Result<String, String> {
+		let path = self.resolve(&args.filename)?;
+		let old = fs::read_to_string(&path).unwrap_or_default();
+		Ok(unified_diff(&args.filename, &old, &args.content))
+	}
+
+	/// The path, current content and edited content of the file
+	fn edited(&self, args: &MultiEditArgs) -> Result<(PathBuf, String, String), String> {
+		let path = self.resolve(&args.filename)?;
+		let old = fs::read_to_string(&path).map_err(|e| format!("{}: {}", args.filename, e))?;
+		let mut content = old.clone();
+		for (index, edit) in args.edits.iter().enumerate() {
+			content = apply_edit(&content, edit).map_err(|e| format!("{}: edit {}: {}", args.filename, index, e))?;
+		}
+		Ok((path, old, content))
+	}
+
+	/// Apply every edit in order, the file is only written when all succeed
+	pub fn multiedit(&self, args: MultiEditArgs) -> Result<String, String> {
+		let (path, _, content) = self.edited(&args)?;
+		self.backup(&[path.clone()])?;
+		helpers::write_atomic(&path, &content).map_err(|e| format!("{}: {}", args.filename, e))?;
+		Ok(format!("Applied {} edit(s) to {}", args.edits.len(), args.filename))
+	}
+
+	/// The diff multiedit would make
+	pub fn preview_multiedit(&self, args: MultiEditArgs) -> Result<String, String> {
+		let (_, old, content) = self.edited(&args)?;
+		Ok(unified_diff(&args.filename, &old, &content))
+	}
+
+	pub fn edit_file(&self, args: EditFileArgs) -> Result<String, String> {
+		self.multiedit(MultiEditArgs{ filename: args.filename, edits: vec![args.edit] })
+	}
+
+	pub fn search_replace(&self, args: SearchReplaceArgs) -> Result<String, String> {
+		let edits = parse_search_replace(&args.blocks)?;
+		self.multiedit(MultiEditArgs{ filename: args.filename, edits: edits })
+	}
+}
+
+pub struct ReadFileTool {
+	library: FileLibrary,
+}
+
+impl ReadFileTool {
+	pub fn new(library: FileLibrary) -> Self {
+		ReadFileTool{ library: library }
+	}
+}
+
+impl Tool for ReadFileTool {
+	fn name(&self) -> &str {
+		"read_file"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "read_file",
+			"description": "read the content of a file",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"filename": {"type": "string", "description": "the name of the file to read, relative and absolute paths are acceptable"},
+				},
+				"required": ["filename"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: ReadFileArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.library.read_file(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
+
+pub struct WriteFileTool {
+	library: FileLibrary,
+}
+
+impl WriteFileTool {
+	pub fn new(library: FileLibrary) -> Self {
+		WriteFileTool{ library: library }
+	}
+}
+
+impl Tool for WriteFileTool {
+	fn name(&self) -> &str {
+		"write_file"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "write_file",
+			"description": "write some data to a file",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"filename": {"type": "string", "description": "the name of the file to write, relative and absolute paths are acceptable"},
+					"content": {"type": "string", "description": "content to write to the file: data should be encoded in UTF-8"},
+				},
+				"required": ["filename", "content"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: WriteFileArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.library.write_file(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: WriteFileArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.library.preview_write_file(args)
+	}
+}
+
+pub struct EditFileTool {
+	library: FileLibrary,
+}
+
+impl EditFileTool {
+	pub fn new(library: FileLibrary) -> Self {
+		EditFileTool{ library: library }
+	}
+}
+
+impl Tool for EditFileTool {
+	fn name(&self) -> &str {
+		"edit_file"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "edit_file",
+			"description": "replace text in a file, old_string must be found exactly once unless replace_all is true",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"filename": {"type": "string", "description": "the name of the file to edit"},
+					"old_string": {"type": "string", "description": "the text to replace"},
+					"new_string": {"type": "string", "description": "the replacement text"},
+					"replace_all": {"type": "boolean", "description": "replace every occurrence of old_string"},
+				},
+				"required": ["filename", "old_string", "new_string"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: EditFileArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.library.edit_file(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: EditFileArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.library.preview_multiedit(MultiEditArgs{ filename: args.filename, edits: vec![args.edit] })
+	}
+}
+
+pub struct MultiEditTool {
+	library: FileLibrary,
+}
+
+impl MultiEditTool {
+	pub fn new(library: FileLibrary) -> Self {
+		MultiEditTool{ library: library }
+	}
+}
+
+impl Tool for MultiEditTool {
+	fn name(&self) -> &str {
+		"multiedit"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "multiedit",
+			"description": "make several replacements in one file, the edits are applied in order and the file is only changed if all of them succeed",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"filename": {"type": "string", "description": "the name of the file to edit"},
+					"edits": {
+						"type": "array",
+						"description": "the replacements to make",
+						"items": {
+							"type": "object",
+							"properties": {
+								"old_string": {"type": "string", "description": "the text to replace"},
+								"new_string": {"type": "string", "description": "the replacement text"},
+								"replace_all": {"type": "boolean", "description": "replace every occurrence of old_string"},
+							},
+							"required": ["old_string", "new_string"],
+						},
+					},
+				},
+				"required": ["filename", "edits"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: MultiEditArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.library.multiedit(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: MultiEditArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.library.preview_multiedit(args)
+	}
+}
+
+pub struct SearchReplaceTool {
+	library: FileLibrary,
+}
+
+impl SearchReplaceTool {
+	pub fn new(library: FileLibrary) -> Self {
+		SearchReplaceTool{ library: library }
+	}
+}
+
+impl Tool for SearchReplaceTool {
+	fn name(&self) -> &str {
+		"search_replace"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "search_replace",
+			"description": "edit a file with SEARCH/REPLACE blocks: a line '<<<<<<< SEARCH', the lines to find, a line '=======', the replacement lines and a line '>>>>>>> REPLACE'",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"filename": {"type": "string", "description": "the name of the file to edit"},
+					"blocks": {"type": "string", "description": "one or more SEARCH/REPLACE blocks"},
+				},
+				"required": ["filename", "blocks"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: SearchReplaceArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.library.search_replace(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: SearchReplaceArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		let edits = parse_search_replace(&args.blocks)?;
+		self.library.preview_multiedit(MultiEditArgs{ filename: args.filename, edits: edits })
+	}
+}
+
+pub struct UndoLastEditTool {
+	library: FileLibrary,
+}
+
+impl UndoLastEditTool {
+	pub fn new(library: FileLibrary) -> Self {
+		UndoLastEditTool{ library: library }
+	}
+}
+
+impl Tool for UndoLastEditTool {
+	fn name(&self) -> &str {
+		"undo_last_edit"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "undo_last_edit",
+			"description": "restore the files changed by the most recent write_file, edit_file, multiedit, search_replace or apply_patch call, can be repeated to undo earlier changes",
+			"parameters": {
+				"type": "object",
+				"properties": {},
+			},
+		})
+	}
+
+	fn call(&self, _args: &str) -> Result<String, String> {
+		self.library.undo()
+	}
+}
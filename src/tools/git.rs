@@ -0,0 +1,320 @@
+use std::process::Command;
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use super::Tool;
+use super::files::FileLibrary;
+
+/// Output of git longer than this is cut
+pub const MAX_GIT_OUTPUT: usize = 100 * 1024;
+/// Default number of commits listed by git_log
+pub const DEFAULT_LOG_COUNT: usize = 10;
+
+/// git run in the workspace root (or the current directory), the paths given
+/// by the model must be inside it
+#[derive(Clone)]
+pub struct Git {
+	library: FileLibrary,
+}
+
+/// The branch and files of `git status --porcelain=v1 --branch`
+pub fn parse_status(output: &str) -> serde_json::Value {
+	let mut branch = None;
+	let mut files = Vec::new();
+	for line in output.lines() {
+		if let Some(header) = line.strip_prefix("## ") {
+			branch = Some(header.to_string());
+		} else if line.len() > 3 {
+			files.push(json!({ "status": line[..2].trim(), "path": &line[3..] }));
+		}
+	}
+	json!({ "branch": branch, "files": files })
+}
+
+/// The commits of `git log --format=%H%x1f%an%x1f%aI%x1f%s`
+pub fn parse_log(output: &str) -> Vec<serde_json::Value> {
+	output.lines().filter_map(|line| {
+		let fields: Vec<&str> = line.split('\x1f').collect();
+		match fields.as_slice() {
+			[commit, author, date, subject] => Some(json!({ "commit": commit, "author": author, "date": date, "subject": subject })),
+			_ => None,
+		}
+	}).collect()
+}
+
+/// The output cut to `MAX_GIT_OUTPUT` (on a character boundary) and whether it was
+fn truncate(mut output: String) -> (String, bool) {
+	if output.len() <= MAX_GIT_OUTPUT {
+		return (output, false);
+	}
+	let mut end = MAX_GIT_OUTPUT;
+	while !output.is_char_boundary(end) {
+		end -= 1;
+	}
+	output.truncate(end);
+	(output, true)
+}
+
+impl Git {
+	pub fn new(library: FileLibrary) -> Self {
+		Git{ library: library }
+	}
+
+	/// Run git with `args` in the workspace, the standard output or the error
+	pub fn run(&self, args: &[&str]) -> Result<String, String> {
+		let dir = self.library.resolve(".")?;
+		let output = Command::new("git").arg("-C").arg(&dir).args(args).output().map_err(|e| format!("Failed to start git: {}", e))?;
+		if !output.status.success() {
+			return Err(format!("git {} failed: {}", args.first().unwrap_or(&""), String::from_utf8_lossy(&output.stderr).trim()));
+		}
+		Ok(String::from_utf8_lossy(&output.stdout).to_string())
+	}
+
+	/// The paths after `--`, each checked to be in the workspace
+	fn pathspecs(&self, paths: &[String]) -> Result<Vec<String>, String> {
+		paths.iter().map(|path| self.library.resolve(path).map(|resolved| resolved.display().to_string())).collect()
+	}
+
+	pub fn status(&self) -> Result<String, String> {
+		Ok(parse_status(&self.run(&["status", "--porcelain=v1", "--branch"])?).to_string())
+	}
+
+	pub fn diff(&self, args: GitDiffArgs) -> Result<String, String> {
+		let mut command = vec!["diff".to_string()];
+		if args.staged {
+			command.push("--staged".to_string());
+		}
+		if let Some(revision) = args.revision {
+			// a revision starting with - would be taken as an option
+			if revision.starts_with('-') {
+				return Err(format!("Invalid revision {}", revision));
+			}
+			command.push(revision);
+		}
+		command.push("--".to_string());
+		command.extend(self.pathspecs(&args.paths)?);
+		let (diff, truncated) = truncate(self.run(&command.iter().map(|arg| arg.as_str()).collect::<Vec<&str>>())?);
+		Ok(json!({ "diff": diff, "truncated": truncated }).to_string())
+	}
+
+	pub fn log(&self, args: GitLogArgs) -> Result<String, String> {
+		let count = args.max_count.unwrap_or(DEFAULT_LOG_COUNT).to_string();
+		let mut command = vec!["log".to_string(), "-n".to_string(), count, "--format=%H%x1f%an%x1f%aI%x1f%s".to_string(), "--".to_string()];
+		command.extend(self.pathspecs(&args.paths)?);
+		let output = self.run(&command.iter().map(|arg| arg.as_str()).collect::<Vec<&str>>())?;
+		Ok(json!({ "commits": parse_log(&output) }).to_string())
+	}
+
+	pub fn commit(&self, args: GitCommitArgs) -> Result<String, String> {
+		if args.message.trim().is_empty() {
+			return Err("The commit message is empty".to_string());
+		}
+		if args.all {
+			self.run(&["add", "--all"])?;
+		} else if !args.paths.is_empty() {
+			let mut command = vec!["add".to_string(), "--".to_string()];
+			command.extend(self.pathspecs(&args.paths)?);
+			self.run(&command.iter().map(|arg| arg.as_str()).collect::<Vec<&str>>())?;
+		}
+		let output = self.run(&["commit", "-m", &args.message])?;
+		let commit = self.run(&["rev-parse", "HEAD"])?;
+		Ok(json!({ "commit": commit.trim(), "output": output }).to_string())
+	}
+
+	pub fn preview_commit(&self, args: GitCommitArgs) -> Result<String, String> {
+		let staging = if args.all {
+			"all the changes".to_string()
+		} else if args.paths.is_empty() {
+			"the staged changes".to_string()
+		} else {
+			self.pathspecs(&args.paths)?.join(", ")
+		};
+		Ok(format!("Would commit {} with the message:\n{}", staging, args.message))
+	}
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GitDiffArgs {
+	/// the changes in the index rather than those not staged
+	#[serde(default)]
+	pub staged: bool,
+	/// compare with this commit (or branch) instead
+	pub revision: Option<String>,
+	#[serde(default)]
+	pub paths: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GitLogArgs {
+	pub max_count: Option<usize>,
+	/// only the commits changing these paths
+	#[serde(default)]
+	pub paths: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GitCommitArgs {
+	pub message: String,
+	/// staged before the commit
+	#[serde(default)]
+	pub paths: Vec<String>,
+	/// stage every change (including new files) before the commit
+	#[serde(default)]
+	pub all: bool,
+}
+
+pub struct GitStatusTool {
+	git: Git,
+}
+
+impl GitStatusTool {
+	pub fn new(git: Git) -> Self {
+		GitStatusTool{ git: git }
+	}
+}
+
+impl Tool for GitStatusTool {
+	fn name(&self) -> &str {
+		"git_status"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "git_status",
+			"description": "the branch of the git repository of the workspace and its changed and untracked files (status is the two letter code of git status --porcelain)",
+			"parameters": {
+				"type": "object",
+				"properties": {},
+			},
+		})
+	}
+
+	fn call(&self, _args: &str) -> Result<String, String> {
+		self.git.status()
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
+
+pub struct GitDiffTool {
+	git: Git,
+}
+
+impl GitDiffTool {
+	pub fn new(git: Git) -> Self {
+		GitDiffTool{ git: git }
+	}
+}
+
+impl Tool for GitDiffTool {
+	fn name(&self) -> &str {
+		"git_diff"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "git_diff",
+			"description": "the diff of the changes in the git repository of the workspace that are not staged (or staged, or since a commit)",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"staged": {"type": "boolean", "description": "the staged changes instead"},
+					"revision": {"type": "string", "description": "compare the files with this commit or branch"},
+					"paths": {"type": "array", "items": {"type": "string"}, "description": "only the changes to these files or directories"},
+				},
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: GitDiffArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.git.diff(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
+
+pub struct GitLogTool {
+	git: Git,
+}
+
+impl GitLogTool {
+	pub fn new(git: Git) -> Self {
+		GitLogTool{ git: git }
+	}
+}
+
+impl Tool for GitLogTool {
+	fn name(&self) -> &str {
+		"git_log"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "git_log",
+			"description": "the most recent commits of the git repository of the workspace, with their hash, author, date and subject",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"max_count": {"type": "integer", "description": format!("number of commits (default: {})", DEFAULT_LOG_COUNT)},
+					"paths": {"type": "array", "items": {"type": "string"}, "description": "only the commits changing these files or directories"},
+				},
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: GitLogArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.git.log(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
+
+pub struct GitCommitTool {
+	git: Git,
+}
+
+impl GitCommitTool {
+	pub fn new(git: Git) -> Self {
+		GitCommitTool{ git: git }
+	}
+}
+
+impl Tool for GitCommitTool {
+	fn name(&self) -> &str {
+		"git_commit"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "git_commit",
+			"description": "commit changes to the git repository of the workspace with a message, the staged changes unless paths or all are given",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"message": {"type": "string", "description": "the commit message"},
+					"paths": {"type": "array", "items": {"type": "string"}, "description": "files to stage before the commit"},
+					"all": {"type": "boolean", "description": "stage every change, including new files, before the commit"},
+				},
+				"required": ["message"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: GitCommitArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.git.commit(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: GitCommitArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.git.preview_commit(args)
+	}
+}
@@ -0,0 +1,281 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::helpers;
+use super::Tool;
+use super::executor::{ExecuteArgs, Executor};
+
+/// Bytes from the end of the output files returned by job_status
+pub const OUTPUT_TAIL: u64 = 4096;
+
+// runs the job and records its exit status, the job is "$@"
+const WRAPPER: &str = r#""$@"; echo $? > "$OPENAICLIENT_JOB_EXIT""#;
+
+/// A command started in the background, stored as `<id>.json` in the jobs
+/// directory next to `<id>.out`, `<id>.err` and (once finished) `<id>.exit`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Job {
+	pub id: u64,
+	pub command: String,
+	pub pid: u32,
+	pub started: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JobArgs {
+	pub id: u64,
+}
+
+/// Background jobs, kept in a directory so that they can be queried by later runs
+#[derive(Clone)]
+pub struct JobTable {
+	dir: PathBuf,
+	executor: Executor,
+}
+
+impl JobTable {
+	pub fn new(dir: PathBuf, executor: Executor) -> Self {
+		JobTable{ dir: dir, executor: executor }
+	}
+
+	fn path(&self, id: u64, extension: &str) -> PathBuf {
+		self.dir.join(format!("{}.{}", id, extension))
+	}
+
+	fn next_id(&self) -> Result<u64, String> {
+		let jobs = helpers::list_files(&self.dir, |path| if helpers::has_specific_extension(&path, "json") { Some(path) } else { None }, 0).map_err(|e| e.to_string())?;
+		let last = jobs.iter()
+			.filter_map(|path| path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok()))
+			.max()
+			.unwrap_or(0);
+		Ok(last + 1)
+	}
+
+	pub fn start(&self, args: ExecuteArgs) -> Result<String, String> {
+		if args.stdin.is_some() {
+			return Err("stdin is not supported for background jobs".to_string());
+		}
+		fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+		let id = self.next_id()?;
+		let job_command = self.executor.prepare(&args)?;
+		let mut command = if cfg!(windows) {
+			// the exit status cannot be recorded without a posix shell
+			job_command
+		} else {
+			let mut command = Command::new("sh");
+			command.arg("-c").arg(WRAPPER).arg("sh").arg(job_command.get_program()).args(job_command.get_args());
+			if let Some(cwd) = job_command.get_current_dir() {
+				command.current_dir(cwd);
+			}
+			for (key, value) in job_command.get_envs() {
+				if let Some(value) = value {
+					command.env(key, value);
+				}
+			}
+			command.env("OPENAICLIENT_JOB_EXIT", self.path(id, "exit"));
+			command
+		};
+		#[cfg(unix)]
+		{
+			// own process group so that kill_job stops everything the job started
+			use std::os::unix::process::CommandExt;
+			command.process_group(0);
+		}
+		let stdout = File::create(self.path(id, "out")).map_err(|e| e.to_string())?;
+		let stderr = File::create(self.path(id, "err")).map_err(|e| e.to_string())?;
+		let child = command
+			.stdin(Stdio::null())
+			.stdout(stdout)
+			.stderr(stderr)
+			.spawn()
+			.map_err(|e| format!("Failed to start: {}", e))?;
+		let description = match args.argv.as_ref() {
+			Some(argv) => argv.join(" "),
+			None => args.command.clone(),
+		};
+		let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+		let job = Job{ id: id, command: description, pid: child.id(), started: started };
+		helpers::save_to_json(self.path(id, "json"), &job).map_err(|e| e.to_string())?;
+		Ok(json!({ "id": id, "pid": job.pid }).to_string())
+	}
+
+	fn load(&self, id: u64) -> Result<Job, String> {
+		helpers::read_from_json(self.path(id, "json")).map_err(|_| format!("No job with id {}", id))
+	}
+
+	pub fn status(&self, args: JobArgs) -> Result<String, String> {
+		let job = self.load(args.id)?;
+		let exit_code = fs::read_to_string(self.path(job.id, "exit")).ok().and_then(|s| s.trim().parse::<i32>().ok());
+		let running = exit_code.is_none() && is_running(job.pid);
+		Ok(json!({
+			"id": job.id,
+			"command": job.command,
+			"started": job.started,
+			"running": running,
+			"exit_code": exit_code,
+			"stdout": tail(&self.path(job.id, "out")),
+			"stderr": tail(&self.path(job.id, "err")),
+		}).to_string())
+	}
+
+	pub fn kill(&self, args: JobArgs) -> Result<String, String> {
+		let job = self.load(args.id)?;
+		if !is_running(job.pid) {
+			return Ok(format!("Job {} is not running", job.id));
+		}
+		let status = if cfg!(windows) {
+			Command::new("taskkill").args(&["/PID", &job.pid.to_string(), "/T", "/F"]).status()
+		} else {
+			// negative pid: the process group
+			Command::new("kill").arg("-TERM").arg("--").arg(format!("-{}", job.pid)).status()
+		};
+		match status {
+			Ok(status) if status.success() => Ok(format!("Job {} stopped", job.id)),
+			Ok(status) => Err(format!("Failed to stop job {}: {}", job.id, status)),
+			Err(err) => Err(format!("Failed to stop job {}: {}", job.id, err)),
+		}
+	}
+}
+
+fn is_running(pid: u32) -> bool {
+	if cfg!(windows) {
+		Command::new("tasklist").args(&["/FI", &format!("PID eq {}", pid), "/NH"]).output()
+			.map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+			.unwrap_or(false)
+	} else {
+		Command::new("kill").arg("-0").arg(pid.to_string()).stderr(Stdio::null()).status()
+			.map(|status| status.success())
+			.unwrap_or(false)
+	}
+}
+
+fn tail(path: &Path) -> String {
+	let mut file = match File::open(path) {
+		Ok(file) => file,
+		Err(_) => return String::new(),
+	};
+	let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+	if len > OUTPUT_TAIL {
+		let _ = file.seek(SeekFrom::Start(len - OUTPUT_TAIL));
+	}
+	let mut output = Vec::new();
+	let _ = file.read_to_end(&mut output);
+	String::from_utf8_lossy(&output).to_string()
+}
+
+pub struct ExecuteBackgroundTool {
+	jobs: JobTable,
+}
+
+impl ExecuteBackgroundTool {
+	pub fn new(jobs: JobTable) -> Self {
+		ExecuteBackgroundTool{ jobs: jobs }
+	}
+}
+
+impl Tool for ExecuteBackgroundTool {
+	fn name(&self) -> &str {
+		"execute_background"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		// the same arguments as execute (without the timeout)
+		let mut schema = self.jobs.executor.json_schema();
+		schema["name"] = json!("execute_background");
+		schema["description"] = json!("start a long running command (server, build...) in the background, returns the id of the job for job_status and kill_job");
+		if let Some(properties) = schema["parameters"]["properties"].as_object_mut() {
+			properties.remove("timeout_secs");
+			properties.remove("stdin");
+		}
+		schema
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: ExecuteArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.jobs.start(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: ExecuteArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		Ok(format!("In the background: {}", self.jobs.executor.preview_execute(&args)?))
+	}
+}
+
+pub struct JobStatusTool {
+	jobs: JobTable,
+}
+
+impl JobStatusTool {
+	pub fn new(jobs: JobTable) -> Self {
+		JobStatusTool{ jobs: jobs }
+	}
+}
+
+impl Tool for JobStatusTool {
+	fn name(&self) -> &str {
+		"job_status"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "job_status",
+			"description": "whether a background job is running, its exit code and the end of its output",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"id": {"type": "integer", "description": "the id returned by execute_background"},
+				},
+				"required": ["id"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: JobArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.jobs.status(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
+
+pub struct KillJobTool {
+	jobs: JobTable,
+}
+
+impl KillJobTool {
+	pub fn new(jobs: JobTable) -> Self {
+		KillJobTool{ jobs: jobs }
+	}
+}
+
+impl Tool for KillJobTool {
+	fn name(&self) -> &str {
+		"kill_job"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "kill_job",
+			"description": "stop a background job",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"id": {"type": "integer", "description": "the id returned by execute_background"},
+				},
+				"required": ["id"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: JobArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.jobs.kill(args)
+	}
+}
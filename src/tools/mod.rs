@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::APP_NAME;
+use crate::helpers;
+use crate::openaiapi::{self, Message, ToolCall};
+
+pub mod audit;
+pub mod cargo;
+pub mod executor;
+pub mod files;
+pub mod git;
+pub mod jobs;
+pub mod patch;
+pub mod plugin;
+pub mod search;
+pub mod semantic;
+pub mod sql;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod web;
+
+/// Wait on this thread for a future, for the tools that make requests: they
+/// are called synchronously from the async main. The other tasks are moved
+/// off the thread meanwhile, which panics outside a multi-thread runtime.
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+	tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
+
+/// A function the model may call, see `ToolRegistry::with_builtin` for the
+/// tools that are available without any configuration.
+pub trait Tool {
+	fn name(&self) -> &str;
+	/// The function definition: `{"name": ..., "description": ..., "parameters": {...}}`
+	fn json_schema(&self) -> serde_json::Value;
+	/// Perform the call, `args` is the JSON arguments string sent by the model.
+	fn call(&self, args: &str) -> Result<String, String>;
+	/// Whether the call may change something on the computer (tools that are
+	/// not known to be read only are destructive).
+	fn destructive(&self) -> bool {
+		true
+	}
+	/// Describe what `call` would do without doing it (for `--pretend-tools`),
+	/// e.g. the diff of an edit or the command that would run.
+	fn preview(&self, args: &str) -> Result<String, String> {
+		Ok(format!("Would call {} with {}", self.name(), args))
+	}
+}
+
+/// Which tool calls need to be approved by the user before they are performed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Approve {
+	/// never ask
+	Never,
+	/// ask before tools that may change something (write, execute...)
+	Destructive,
+	/// ask before every tool call
+	Always,
+}
+
+/// Names of the tools the model may call, in config.toml (`[tool_policy]`)
+/// or in the chat (`"tool_policy": {...}`). A call to a tool that is not
+/// allowed is answered with an error instead of being performed.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ToolPolicy {
+	/// when present only these tools may be called
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub allowed: Option<Vec<String>>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub denied: Vec<String>,
+}
+
+impl ToolPolicy {
+	pub fn allows(&self, name: &str) -> bool {
+		let allowed = match self.allowed.as_ref() {
+			Some(allowed) => allowed.iter().any(|n| n == name),
+			None => true,
+		};
+		allowed && !self.denied.iter().any(|n| n == name)
+	}
+}
+
+pub struct ToolRegistry {
+	tools: Vec<Box<dyn Tool>>,
+}
+
+impl ToolRegistry {
+	pub fn new() -> Self {
+		ToolRegistry{ tools: Vec::new() }
+	}
+
+	/// The built-in tools, `workspace_root` (canonical) limits the files that may be used,
+	/// `shell` is used by `execute` when the model does not choose one and the files
+	/// changed by the tools are saved in `backups` so they can be undone
+	pub fn with_builtin(workspace_root: Option<PathBuf>, shell: Option<String>, backups: Option<PathBuf>) -> Self {
+		let mut registry = Self::new();
+		let library = files::FileLibrary::new(workspace_root).with_backups(backups);
+		registry.register(Box::new(files::ReadFileTool::new(library.clone())));
+		registry.register(Box::new(files::WriteFileTool::new(library.clone())));
+		registry.register(Box::new(files::EditFileTool::new(library.clone())));
+		registry.register(Box::new(files::MultiEditTool::new(library.clone())));
+		registry.register(Box::new(files::SearchReplaceTool::new(library.clone())));
+		registry.register(Box::new(patch::ApplyPatchTool::new(library.clone())));
+		registry.register(Box::new(files::UndoLastEditTool::new(library.clone())));
+		registry.register(Box::new(search::GlobTool::new(library.clone())));
+		registry.register(Box::new(search::GrepTool::new(library.clone())));
+		registry.register(Box::new(search::ListFilesTool::new(library.clone())));
+		registry.register(Box::new(web::WebFetchTool::new()));
+		let git = git::Git::new(library.clone());
+		registry.register(Box::new(git::GitStatusTool::new(git.clone())));
+		registry.register(Box::new(git::GitDiffTool::new(git.clone())));
+		registry.register(Box::new(git::GitLogTool::new(git.clone())));
+		registry.register(Box::new(git::GitCommitTool::new(git)));
+		let cargo = cargo::Cargo::new(library.clone());
+		registry.register(Box::new(cargo::CargoCheckTool::new(cargo.clone())));
+		registry.register(Box::new(cargo::CargoTestTool::new(cargo.clone())));
+		registry.register(Box::new(cargo::CargoFmtTool::new(cargo)));
+		let executor = executor::Executor::new(shell);
+		// jobs are kept in ~/.local/share/openaiclient/jobs so later runs can find them
+		let jobs_dir = helpers::data_get_dir(Some(APP_NAME)).unwrap_or(std::env::temp_dir().join(APP_NAME)).join("jobs");
+		let jobs = jobs::JobTable::new(jobs_dir, executor.clone());
+		registry.register(Box::new(executor));
+		registry.register(Box::new(jobs::ExecuteBackgroundTool::new(jobs.clone())));
+		registry.register(Box::new(jobs::JobStatusTool::new(jobs.clone())));
+		registry.register(Box::new(jobs::KillJobTool::new(jobs)));
+		registry
+	}
+
+	/// Add a tool, a tool with the same name is replaced.
+	pub fn register(&mut self, tool: Box<dyn Tool>) {
+		self.tools.retain(|t| t.name() != tool.name());
+		self.tools.push(tool);
+	}
+
+	pub fn get(&self, name: &str) -> Option<&dyn Tool> {
+		self.tools.iter().find(|t| t.name() == name).map(|t| t.as_ref())
+	}
+
+	pub fn names(&self) -> Vec<String> {
+		self.tools.iter().map(|t| t.name().to_string()).collect()
+	}
+
+	/// The `tools` array to send with the request
+	pub fn definitions(&self) -> Result<Vec<openaiapi::Tool>, serde_json::Error> {
+		self.definitions_of(&self.names())
+	}
+
+	pub fn definitions_of(&self, names: &[String]) -> Result<Vec<openaiapi::Tool>, serde_json::Error> {
+		let mut definitions = Vec::new();
+		for tool in self.tools.iter().filter(|t| names.iter().any(|n| n == t.name())) {
+			definitions.push(serde_json::from_value(json!({
+				"type": "function",
+				"function": tool.json_schema(),
+			}))?);
+		}
+		Ok(definitions)
+	}
+}
+
+pub struct Dispatcher {
+	pub registry: ToolRegistry,
+	pub approve: Approve,
+	/// a tool must be allowed by every policy to be called
+	pub policies: Vec<ToolPolicy>,
+	/// destructive tools are not performed, their preview is the result
+	pub pretend: bool,
+	/// every call is recorded here when present
+	pub audit: Option<audit::AuditLog>,
+	/// tools the user chose to always allow
+	always_allowed: RefCell<HashSet<String>>,
+}
+
+impl Dispatcher {
+	pub fn new(registry: ToolRegistry) -> Self {
+		Dispatcher{ registry: registry, approve: Approve::Never, policies: Vec::new(), pretend: false, audit: None, always_allowed: RefCell::new(HashSet::new()) }
+	}
+
+	pub fn allows(&self, name: &str) -> bool {
+		self.policies.iter().all(|policy| policy.allows(name))
+	}
+
+	fn needs_approval(&self, tool: &dyn Tool) -> bool {
+		let required = match self.approve {
+			Approve::Never => false,
+			Approve::Destructive => tool.destructive(),
+			Approve::Always => true,
+		};
+		required && !self.always_allowed.borrow().contains(tool.name())
+	}
+
+	/// Ask the user on the terminal, returns true when the call may go ahead
+	fn ask_approval(&self, name: &str, args: &str) -> bool {
+		// on stderr so the prompt does not end up in the output
+		eprintln!("The assistant wants to call {} with:", name);
+		eprintln!("{}", args);
+		loop {
+			eprint!("Allow? [y]es/[n]o/[a]lways: ");
+			let _ = io::stderr().flush();
+			let mut answer = String::new();
+			match io::stdin().lock().read_line(&mut answer) {
+				Ok(0) | Err(_) => return false,
+				Ok(_) => {},
+			}
+			match answer.trim().to_lowercase().as_str() {
+				"y" | "yes" => return true,
+				"n" | "no" => return false,
+				"a" | "always" => {
+					self.always_allowed.borrow_mut().insert(name.to_string());
+					return true;
+				},
+				_ => continue,
+			}
+		}
+	}
+
+	pub fn call(&self, name: &str, args: &str) -> Result<String, String> {
+		let result = self.perform(name, args);
+		if let Some(audit) = self.audit.as_ref() {
+			if let Err(err) = audit.record(name, args, &result, self.pretend) {
+				eprintln!("Cannot write the audit log: {}", err);
+			}
+		}
+		result
+	}
+
+	fn perform(&self, name: &str, args: &str) -> Result<String, String> {
+		if !self.allows(name) {
+			return Err(format!("The tool {} is not allowed in this chat", name));
+		}
+		let tool = self.registry.get(name).ok_or(format!("Unknown tool: {}", name))?;
+		if self.pretend && tool.destructive() {
+			let preview = tool.preview(args)?;
+			eprintln!("Pretending to call {}:\n{}", name, preview);
+			return Ok(preview);
+		}
+		if self.needs_approval(tool) && !self.ask_approval(name, args) {
+			return Err("The user did not allow this tool call".to_string());
+		}
+		tool.call(args)
+	}
+
+	/// Perform the tool call and make the message containing the result
+	pub fn dispatch(&self, tool_call: &ToolCall) -> Message {
+		let content = match self.call(&tool_call.function.name, &tool_call.function.arguments) {
+			Ok(result) => result,
+			Err(err) => format!("Error: {}", err),
+		};
+		Message::tool_response("tool".to_string(), tool_call.function.name.clone(), tool_call.id.clone(), content)
+	}
+}
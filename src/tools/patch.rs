@@ -0,0 +1,333 @@
+use std::fs;
+use std::path::PathBuf;
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use crate::helpers;
+use super::Tool;
+use super::files::{self, FileLibrary};
+
+#[derive(Deserialize, Debug)]
+pub struct ApplyPatchArgs {
+	/// a unified diff, it may change several files
+	pub patch: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HunkLine {
+	Context(String),
+	Remove(String),
+	Add(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Hunk {
+	/// first line of the hunk in the original file (1 based)
+	pub old_start: usize,
+	pub lines: Vec<HunkLine>,
+	/// the new file ends without a newline after this hunk
+	pub no_newline: bool,
+}
+
+impl Hunk {
+	fn old_lines(&self) -> Vec<&str> {
+		self.lines.iter().filter_map(|line| match line {
+			HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+			HunkLine::Add(_) => None,
+		}).collect()
+	}
+
+	fn new_lines(&self) -> Vec<&str> {
+		self.lines.iter().filter_map(|line| match line {
+			HunkLine::Context(text) | HunkLine::Add(text) => Some(text.as_str()),
+			HunkLine::Remove(_) => None,
+		}).collect()
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+	/// None for a file that is created
+	pub old_path: Option<String>,
+	/// None for a file that is deleted
+	pub new_path: Option<String>,
+	pub hunks: Vec<Hunk>,
+}
+
+/// The path from a `---` or `+++` line without the `a/` or `b/` prefix
+/// added by git, `/dev/null` is None.
+fn diff_path(header: &str) -> Option<String> {
+	// a timestamp may follow the name after a tab
+	let path = header.split('\t').next().unwrap_or("").trim();
+	if path == "/dev/null" {
+		return None;
+	}
+	let path = path.strip_prefix("a/").or(path.strip_prefix("b/")).unwrap_or(path);
+	Some(path.to_string())
+}
+
+/// Parse `@@ -old_start,old_len +new_start,new_len @@`, the lengths are
+/// optional and default to 1.
+fn parse_hunk_header(line: &str) -> Result<(usize, usize, usize), String> {
+	let invalid = || format!("Invalid hunk header: {}", line);
+	let ranges = line.strip_prefix("@@ ").and_then(|rest| rest.split(" @@").next()).ok_or_else(invalid)?;
+	let mut ranges = ranges.split(' ');
+	let parse_range = |range: Option<&str>, sign: char| -> Result<(usize, usize), String> {
+		let range = range.and_then(|r| r.strip_prefix(sign)).ok_or_else(invalid)?;
+		let mut parts = range.splitn(2, ',');
+		let start = parts.next().unwrap_or("").parse::<usize>().map_err(|_| invalid())?;
+		let len = match parts.next() {
+			Some(len) => len.parse::<usize>().map_err(|_| invalid())?,
+			None => 1,
+		};
+		Ok((start, len))
+	};
+	let (old_start, old_len) = parse_range(ranges.next(), '-')?;
+	let (_, new_len) = parse_range(ranges.next(), '+')?;
+	Ok((old_start, old_len, new_len))
+}
+
+/// Parse a unified diff as produced by `diff -u` or `git diff`, lines
+/// outside of the file sections (`diff --git`, `index`, ...) are ignored.
+pub fn parse_patch(patch: &str) -> Result<Vec<FilePatch>, String> {
+	let mut files: Vec<FilePatch> = Vec::new();
+	let lines: Vec<&str> = patch.lines().collect();
+	let mut index = 0;
+	while index < lines.len() {
+		let line = lines[index];
+		if let Some(old) = line.strip_prefix("--- ") {
+			let new = lines.get(index + 1).and_then(|l| l.strip_prefix("+++ ")).ok_or(format!("Expected +++ after {}", line))?;
+			files.push(FilePatch{ old_path: diff_path(old), new_path: diff_path(new), hunks: Vec::new() });
+			index += 2;
+			continue;
+		}
+		if line.starts_with("@@ ") {
+			let header = line;
+			let file = files.last_mut().ok_or(format!("Hunk before any file header: {}", line))?;
+			let (old_start, mut old_len, mut new_len) = parse_hunk_header(line)?;
+			let mut hunk = Hunk{ old_start: old_start, lines: Vec::new(), no_newline: false };
+			index += 1;
+			while index < lines.len() && (old_len > 0 || new_len > 0) {
+				let line = lines[index];
+				match line.chars().next() {
+					Some('+') => {
+						hunk.lines.push(HunkLine::Add(line[1..].to_string()));
+						new_len = new_len.saturating_sub(1);
+					},
+					Some('-') => {
+						hunk.lines.push(HunkLine::Remove(line[1..].to_string()));
+						old_len = old_len.saturating_sub(1);
+					},
+					Some(' ') => {
+						hunk.lines.push(HunkLine::Context(line[1..].to_string()));
+						old_len = old_len.saturating_sub(1);
+						new_len = new_len.saturating_sub(1);
+					},
+					// some editors strip the space from empty context lines
+					None => {
+						hunk.lines.push(HunkLine::Context(String::new()));
+						old_len = old_len.saturating_sub(1);
+						new_len = new_len.saturating_sub(1);
+					},
+					Some('\\') => {},
+					_ => return Err(format!("Unexpected line in hunk: {}", line)),
+				}
+				index += 1;
+			}
+			if old_len > 0 || new_len > 0 {
+				return Err(format!("Hunk is shorter than its header: {}", header));
+			}
+			// "\ No newline at end of file" after the new side of the hunk
+			if lines.get(index).map(|l| l.starts_with('\\')).unwrap_or(false) {
+				if !matches!(hunk.lines.last(), Some(HunkLine::Remove(_))) {
+					hunk.no_newline = true;
+				}
+				index += 1;
+			}
+			file.hunks.push(hunk);
+			continue;
+		}
+		index += 1;
+	}
+	if files.is_empty() {
+		return Err("No files found in the patch".to_string());
+	}
+	Ok(files)
+}
+
+/// Where the old lines of the hunk are found in `lines` at or after `from`,
+/// the position in the header is tried first and then the nearest match.
+fn find_hunk(lines: &[String], hunk: &Hunk, expected: usize, from: usize) -> Option<usize> {
+	let old = hunk.old_lines();
+	let matches_at = |at: usize| at >= from && at + old.len() <= lines.len() && old.iter().zip(&lines[at..]).all(|(a, b)| *a == b.as_str());
+	if matches_at(expected) {
+		return Some(expected);
+	}
+	(1..=lines.len()).find_map(|offset| {
+		if expected >= offset && matches_at(expected - offset) {
+			Some(expected - offset)
+		} else if matches_at(expected + offset) {
+			Some(expected + offset)
+		} else {
+			None
+		}
+	})
+}
+
+/// Apply the hunks to `content`, the result of each hunk is reported. The
+/// new content is only returned when every hunk applied.
+pub fn apply_hunks(content: &str, hunks: &[Hunk]) -> (Option<String>, Vec<serde_json::Value>) {
+	let mut lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+	let mut trailing_newline = content.is_empty() || content.ends_with('\n');
+	let mut report = Vec::new();
+	let mut failed = false;
+	// lines added minus lines removed by the hunks so far
+	let mut shift: isize = 0;
+	let mut from = 0;
+	for (index, hunk) in hunks.iter().enumerate() {
+		// a hunk removing nothing at line 0 inserts at the start
+		let expected = ((hunk.old_start.max(1) - 1) as isize + shift).max(0) as usize;
+		let expected = if hunk.old_lines().is_empty() && hunk.old_start > 0 { expected + 1 } else { expected };
+		match find_hunk(&lines, hunk, expected.min(lines.len()), from) {
+			Some(at) => {
+				let old_len = hunk.old_lines().len();
+				let new_lines: Vec<String> = hunk.new_lines().iter().map(|l| l.to_string()).collect();
+				let new_len = new_lines.len();
+				let at_end = at + old_len == lines.len();
+				lines.splice(at..at + old_len, new_lines);
+				if at_end {
+					trailing_newline = !hunk.no_newline;
+				}
+				shift += new_len as isize - old_len as isize;
+				from = at + new_len;
+				report.push(json!({ "hunk": index, "applied": true, "line": at + 1 }));
+			},
+			None => {
+				failed = true;
+				report.push(json!({ "hunk": index, "applied": false, "error": format!("context not found near line {}", hunk.old_start) }));
+			},
+		}
+	}
+	if failed {
+		return (None, report);
+	}
+	let mut content = lines.join("\n");
+	if trailing_newline && !lines.is_empty() {
+		content.push('\n');
+	}
+	(Some(content), report)
+}
+
+pub struct ApplyPatchTool {
+	library: FileLibrary,
+}
+
+impl ApplyPatchTool {
+	pub fn new(library: FileLibrary) -> Self {
+		ApplyPatchTool{ library: library }
+	}
+
+	/// The changes made by the patch: the path, whether the file is kept
+	/// (not deleted), the current and the new content. Nothing is returned
+	/// unless all the hunks of all the files apply.
+	fn patched(&self, args: &ApplyPatchArgs) -> Result<(Vec<(PathBuf, bool, String, String)>, String), String> {
+		let files = parse_patch(&args.patch)?;
+		let mut changes = Vec::new();
+		let mut report = Vec::new();
+		let mut failed = false;
+		for file in files.iter() {
+			let name = file.new_path.as_ref().or(file.old_path.as_ref()).ok_or("Patch with /dev/null on both sides".to_string())?;
+			let path = self.library.resolve(name)?;
+			// a renamed file is read from its old path, removed once the new one is written
+			let renamed_from = match (file.old_path.as_ref(), file.new_path.as_ref()) {
+				(Some(old_name), Some(new_name)) if old_name != new_name => Some((old_name, self.library.resolve(old_name)?)),
+				_ => None,
+			};
+			let content = match (file.old_path.as_ref(), renamed_from.as_ref()) {
+				(Some(_), Some(_)) if path.exists() => return Err(format!("{}: already exists", name)),
+				(Some(_), Some((old_name, old_path))) => fs::read_to_string(old_path).map_err(|e| format!("{}: {}", old_name, e))?,
+				(Some(_), None) => fs::read_to_string(&path).map_err(|e| format!("{}: {}", name, e))?,
+				(None, _) if path.exists() => return Err(format!("{}: already exists", name)),
+				(None, _) => String::new(),
+			};
+			let (patched, hunks) = apply_hunks(&content, &file.hunks);
+			report.push(json!({ "path": name, "hunks": hunks }));
+			match patched {
+				Some(patched) => {
+					changes.push((path, file.new_path.is_some(), content.clone(), patched));
+					if let Some((_, old_path)) = renamed_from {
+						changes.push((old_path, false, content, String::new()));
+					}
+				},
+				None => failed = true,
+			}
+		}
+		let report = json!({ "applied": !failed, "files": report }).to_string();
+		if failed {
+			return Err(report);
+		}
+		Ok((changes, report))
+	}
+
+	/// Apply every file in the patch, nothing is written unless all the
+	/// hunks of all the files apply.
+	pub fn apply_patch(&self, args: ApplyPatchArgs) -> Result<String, String> {
+		let (changes, report) = self.patched(&args)?;
+		let paths: Vec<_> = changes.iter().map(|(path, _, _, _)| path.clone()).collect();
+		self.library.backup(&paths)?;
+		for (path, keep, _, content) in changes.iter() {
+			let result = if *keep {
+				if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+					fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+				}
+				helpers::write_atomic(path, content)
+			} else {
+				fs::remove_file(path)
+			};
+			result.map_err(|e| format!("{}: {}", path.display(), e))?;
+		}
+		Ok(report)
+	}
+
+	/// The diff of every file the patch would change
+	pub fn preview_patch(&self, args: ApplyPatchArgs) -> Result<String, String> {
+		let (changes, _) = self.patched(&args)?;
+		Ok(changes.iter().map(|(path, keep, old, new)| {
+			if *keep {
+				files::unified_diff(&path.display().to_string(), old, new)
+			} else {
+				format!("Would delete {}\n", path.display())
+			}
+		}).collect())
+	}
+}
+
+impl Tool for ApplyPatchTool {
+	fn name(&self) -> &str {
+		"apply_patch"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "apply_patch",
+			"description": "apply a unified diff (as produced by diff -u or git diff) to one or more files, the files are only changed if every hunk applies and the result of each hunk is reported",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"patch": {"type": "string", "description": "the unified diff, with --- and +++ headers for each file and @@ hunk headers"},
+				},
+				"required": ["patch"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: ApplyPatchArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.apply_patch(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: ApplyPatchArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.preview_patch(args)
+	}
+}
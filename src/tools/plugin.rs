@@ -0,0 +1,100 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::helpers;
+use super::{Tool, ToolRegistry};
+
+/// A tool implemented by an executable in the `tools.d/` directory, the
+/// function definition is read from the file with the same name and a
+/// `.json` extension (e.g. `tools.d/weather` and `tools.d/weather.json`).
+///
+/// The arguments JSON is written to the stdin of the executable and its
+/// stdout is the result, a non-zero exit status is reported as an error.
+pub struct PluginTool {
+	name: String,
+	executable: PathBuf,
+	schema: serde_json::Value,
+}
+
+impl PluginTool {
+	pub fn load(schema_file: &Path) -> Result<Self, helpers::HelperError> {
+		let schema: serde_json::Value = helpers::read_from_json(schema_file)?;
+		let executable = schema_file.with_extension("");
+		let name = match schema.get("name").and_then(|n| n.as_str()) {
+			Some(name) => name.to_string(),
+			None => executable.file_name().unwrap_or_default().to_string_lossy().to_string(),
+		};
+		if !executable.is_file() {
+			return Err(helpers::HelperError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not found", executable.display()))));
+		}
+		Ok(PluginTool{ name: name, executable: executable, schema: schema })
+	}
+}
+
+impl Tool for PluginTool {
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		let mut schema = self.schema.clone();
+		schema["name"] = serde_json::Value::String(self.name.clone());
+		schema
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let mut child = Command::new(&self.executable)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+			.map_err(|e| format!("Failed to start {}: {}", self.executable.display(), e))?;
+		let mut stdin = child.stdin.take().ok_or("Failed to open stdin".to_string())?;
+		let input = args.to_string();
+		// written from another thread in case the plugin writes a lot before reading
+		let writer = thread::spawn(move || {
+			let _ = stdin.write_all(input.as_bytes());
+		});
+		let output = child.wait_with_output().map_err(|e| e.to_string())?;
+		let _ = writer.join();
+		let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+		if output.status.success() {
+			Ok(stdout)
+		} else {
+			let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+			Err(format!("{} exited with {}: {}{}", self.name, output.status, stdout, stderr))
+		}
+	}
+}
+
+/// Register every plugin found in `dir`, plugins that cannot be loaded are
+/// reported and skipped. The names of the registered tools are returned.
+pub fn register_plugins(registry: &mut ToolRegistry, dir: &Path) -> Vec<String> {
+	let mut names = Vec::new();
+	if !dir.is_dir() {
+		return names;
+	}
+	let schema_files = match helpers::list_files(dir, |path| if helpers::has_specific_extension(&path, "json") { Some(path) } else { None }, 0) {
+		Ok(schema_files) => schema_files,
+		Err(err) => {
+			eprintln!("Cannot list plugins in {}: {}", dir.display(), err);
+			return names;
+		},
+	};
+	for schema_file in schema_files.iter() {
+		// the definition of a WebAssembly tool, see wasm.rs
+		if schema_file.with_extension("wasm").is_file() {
+			continue;
+		}
+		match PluginTool::load(schema_file) {
+			Ok(plugin) => {
+				names.push(plugin.name().to_string());
+				registry.register(Box::new(plugin));
+			},
+			Err(err) => eprintln!("Plugin {} not loaded: {}", schema_file.display(), err),
+		}
+	}
+	names
+}
@@ -0,0 +1,337 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use crate::helpers;
+use super::Tool;
+use super::files::FileLibrary;
+
+/// Most paths returned by glob
+pub const MAX_GLOB_RESULTS: usize = 500;
+/// Deepest directory searched below the root
+pub const MAX_DEPTH: usize = 32;
+/// Default number of matching lines returned by grep
+pub const DEFAULT_GREP_RESULTS: usize = 100;
+/// Files larger than this are not searched
+pub const MAX_GREP_FILE_SIZE: u64 = 1024 * 1024;
+/// Default depth listed by list_files
+pub const DEFAULT_LIST_DEPTH: usize = 3;
+/// Most entries returned by list_files
+pub const MAX_LIST_ENTRIES: usize = 1000;
+
+#[derive(Deserialize, Debug)]
+pub struct GlobArgs {
+	pub pattern: String,
+	/// directory to search (default: the workspace root or current directory)
+	pub root: Option<String>,
+}
+
+pub struct GlobTool {
+	library: FileLibrary,
+}
+
+impl GlobTool {
+	pub fn new(library: FileLibrary) -> Self {
+		GlobTool{ library: library }
+	}
+
+	/// Files below the root matching the pattern (relative to the root),
+	/// files ignored by git are skipped, the most recently modified is first.
+	pub fn glob(&self, args: GlobArgs) -> Result<String, String> {
+		let root = self.library.resolve(args.root.as_deref().unwrap_or("."))?;
+		let matcher = globset::Glob::new(&args.pattern).map_err(|e| e.to_string())?.compile_matcher();
+		let accept = helpers::make_gitaccept_matcher(&root);
+		let files = helpers::walk_files(&root, accept, MAX_DEPTH).map_err(|e| format!("{}: {}", root.display(), e))?;
+		let mut matches: Vec<(SystemTime, PathBuf)> = files.into_iter()
+			.filter_map(|path| {
+				let relative = path.strip_prefix(&root).ok()?.to_path_buf();
+				if !matcher.is_match(&relative) {
+					return None;
+				}
+				let modified = fs::metadata(&path).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+				Some((modified, relative))
+			})
+			.collect();
+		matches.sort_by(|a, b| b.0.cmp(&a.0));
+		let truncated = matches.len() > MAX_GLOB_RESULTS;
+		let paths: Vec<String> = matches.iter().take(MAX_GLOB_RESULTS).map(|(_, path)| path.display().to_string()).collect();
+		Ok(json!({ "root": root.display().to_string(), "paths": paths, "truncated": truncated }).to_string())
+	}
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GrepArgs {
+	/// regular expression searched for in each line
+	pub pattern: String,
+	pub root: Option<String>,
+	/// only search files matching one of these globs
+	#[serde(default)]
+	pub include: Vec<String>,
+	/// do not search files matching any of these globs
+	#[serde(default)]
+	pub exclude: Vec<String>,
+	#[serde(default)]
+	pub case_insensitive: bool,
+	pub max_results: Option<usize>,
+	/// lines of context before and after each match
+	#[serde(default)]
+	pub context: usize,
+}
+
+fn make_globset(globs: &[String]) -> Result<Option<globset::GlobSet>, String> {
+	if globs.is_empty() {
+		return Ok(None);
+	}
+	let mut builder = globset::GlobSetBuilder::new();
+	for glob in globs {
+		builder.add(globset::Glob::new(glob).map_err(|e| e.to_string())?);
+	}
+	Ok(Some(builder.build().map_err(|e| e.to_string())?))
+}
+
+pub struct GrepTool {
+	library: FileLibrary,
+}
+
+impl GrepTool {
+	pub fn new(library: FileLibrary) -> Self {
+		GrepTool{ library: library }
+	}
+
+	/// Lines matching the pattern in the files below the root, binary files
+	/// and files ignored by git are skipped.
+	pub fn grep(&self, args: GrepArgs) -> Result<String, String> {
+		let root = self.library.resolve(args.root.as_deref().unwrap_or("."))?;
+		let regex = regex::RegexBuilder::new(&args.pattern)
+			.case_insensitive(args.case_insensitive)
+			.build()
+			.map_err(|e| e.to_string())?;
+		let include = make_globset(&args.include)?;
+		let exclude = make_globset(&args.exclude)?;
+		let max_results = args.max_results.unwrap_or(DEFAULT_GREP_RESULTS);
+		let accept = helpers::make_gitaccept_matcher(&root);
+		let mut files = helpers::walk_files(&root, accept, MAX_DEPTH).map_err(|e| format!("{}: {}", root.display(), e))?;
+		files.sort();
+		let mut results = Vec::new();
+		let mut truncated = false;
+		'files: for path in files.iter() {
+			let relative = path.strip_prefix(&root).unwrap_or(path);
+			if include.as_ref().map(|set| !set.is_match(relative)).unwrap_or(false) {
+				continue;
+			}
+			if exclude.as_ref().map(|set| set.is_match(relative)).unwrap_or(false) {
+				continue;
+			}
+			if fs::metadata(path).map(|m| m.len() > MAX_GREP_FILE_SIZE).unwrap_or(true) {
+				continue;
+			}
+			let content = match fs::read(path) {
+				Ok(content) => content,
+				Err(_) => continue,
+			};
+			if content.iter().take(8192).any(|b| *b == 0) {
+				continue;
+			}
+			let content = String::from_utf8_lossy(&content);
+			let lines: Vec<&str> = content.lines().collect();
+			for (index, line) in lines.iter().enumerate() {
+				if !regex.is_match(line) {
+					continue;
+				}
+				if results.len() >= max_results {
+					truncated = true;
+					break 'files;
+				}
+				let before = &lines[index.saturating_sub(args.context)..index];
+				let after = &lines[(index + 1).min(lines.len())..(index + 1 + args.context).min(lines.len())];
+				let mut result = json!({
+					"path": relative.display().to_string(),
+					"line": index + 1,
+					"text": line,
+				});
+				if args.context > 0 {
+					result["before"] = json!(before);
+					result["after"] = json!(after);
+				}
+				results.push(result);
+			}
+		}
+		Ok(json!({ "root": root.display().to_string(), "matches": results, "truncated": truncated }).to_string())
+	}
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListFilesArgs {
+	pub path: Option<String>,
+	/// levels of directories listed below `path`
+	pub depth: Option<usize>,
+	/// include files and directories starting with `.`
+	#[serde(default)]
+	pub include_hidden: bool,
+}
+
+/// Add the entry at `components` to `tree`, its parent directories are
+/// listed before it by the walk so they are already present.
+fn insert_entry(tree: &mut Vec<serde_json::Value>, components: &[String], is_dir: bool) {
+	let (name, rest) = match components.split_first() {
+		Some(split) => split,
+		None => return,
+	};
+	if rest.is_empty() {
+		let mut entry = json!({ "name": name, "type": if is_dir { "dir" } else { "file" } });
+		if is_dir {
+			entry["children"] = json!([]);
+		}
+		tree.push(entry);
+		return;
+	}
+	let parent = tree.iter_mut().find(|entry| entry["name"] == name.as_str() && entry["type"] == "dir");
+	if let Some(children) = parent.and_then(|parent| parent["children"].as_array_mut()) {
+		insert_entry(children, rest, is_dir);
+	}
+}
+
+pub struct ListFilesTool {
+	library: FileLibrary,
+}
+
+impl ListFilesTool {
+	pub fn new(library: FileLibrary) -> Self {
+		ListFilesTool{ library: library }
+	}
+
+	/// The files and directories below `path` as a tree, the `.gitignore`
+	/// files of every directory are respected.
+	pub fn list_files(&self, args: ListFilesArgs) -> Result<String, String> {
+		let root = self.library.resolve(args.path.as_deref().unwrap_or("."))?;
+		if !root.is_dir() {
+			return Err(format!("{}: not a directory", root.display()));
+		}
+		let walker = ignore::WalkBuilder::new(&root)
+			.max_depth(Some(args.depth.unwrap_or(DEFAULT_LIST_DEPTH)))
+			.hidden(!args.include_hidden)
+			.require_git(false)
+			.filter_entry(|entry| entry.file_name() != ".git")
+			.sort_by_file_name(|a, b| a.cmp(b))
+			.build();
+		let mut tree = Vec::new();
+		let mut count = 0;
+		let mut truncated = false;
+		for entry in walker {
+			let entry = match entry {
+				Ok(entry) => entry,
+				Err(_) => continue,
+			};
+			let relative = match entry.path().strip_prefix(&root) {
+				Ok(relative) if relative.as_os_str().len() > 0 => relative,
+				_ => continue,
+			};
+			if count >= MAX_LIST_ENTRIES {
+				truncated = true;
+				break;
+			}
+			let components: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+			let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+			insert_entry(&mut tree, &components, is_dir);
+			count += 1;
+		}
+		Ok(json!({ "root": root.display().to_string(), "entries": tree, "truncated": truncated }).to_string())
+	}
+}
+
+impl Tool for ListFilesTool {
+	fn name(&self) -> &str {
+		"list_files"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "list_files",
+			"description": "list the files and directories below a directory as a tree, files ignored by git are skipped",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"path": {"type": "string", "description": "directory to list (default: the current directory)"},
+					"depth": {"type": "integer", "description": format!("levels of directories to list (default: {})", DEFAULT_LIST_DEPTH)},
+					"include_hidden": {"type": "boolean", "description": "include files and directories whose names start with a dot"},
+				},
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: ListFilesArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.list_files(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
+
+impl Tool for GrepTool {
+	fn name(&self) -> &str {
+		"grep"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "grep",
+			"description": "search the content of files for a regular expression, files ignored by git and binary files are skipped",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"pattern": {"type": "string", "description": "regular expression (Rust regex syntax) matched against each line"},
+					"root": {"type": "string", "description": "directory to search in (default: the current directory)"},
+					"include": {"type": "array", "items": {"type": "string"}, "description": "only search files matching one of these globs, e.g. **/*.rs"},
+					"exclude": {"type": "array", "items": {"type": "string"}, "description": "skip files matching any of these globs"},
+					"case_insensitive": {"type": "boolean", "description": "ignore case when matching"},
+					"max_results": {"type": "integer", "description": format!("most matching lines to return (default: {})", DEFAULT_GREP_RESULTS)},
+					"context": {"type": "integer", "description": "lines to include before and after each match"},
+				},
+				"required": ["pattern"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: GrepArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.grep(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
+
+impl Tool for GlobTool {
+	fn name(&self) -> &str {
+		"glob"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "glob",
+			"description": "find files by name, e.g. **/*.rs, files ignored by git are skipped and the most recently modified are listed first",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"pattern": {"type": "string", "description": "glob pattern matched against the path relative to root"},
+					"root": {"type": "string", "description": "directory to search in (default: the current directory)"},
+				},
+				"required": ["pattern"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: GlobArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.glob(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use crate::openaiapi::embeddings::EmbeddingsApi;
+use crate::vectorstore::{self, VectorStore};
+use super::{block_on, Tool};
+
+/// Most chunks returned by one search
+pub const MAX_SEARCH_RESULTS: usize = 20;
+
+#[derive(Deserialize, Debug)]
+pub struct SemanticSearchArgs {
+	pub query: String,
+	pub top: Option<usize>,
+}
+
+/// Search the vector store of the project (made with `openaiclient index`)
+/// for the chunks of files most like a query
+pub struct SemanticSearchTool {
+	store: PathBuf,
+	api: EmbeddingsApi,
+}
+
+impl SemanticSearchTool {
+	pub fn new(store: PathBuf, api: EmbeddingsApi) -> Self {
+		SemanticSearchTool{ store: store, api: api }
+	}
+
+	pub fn search(&self, args: SemanticSearchArgs) -> Result<String, String> {
+		let store = VectorStore::open(&self.store).map_err(|e| format!("{}: {}", self.store.display(), e))?;
+		// the query is embedded with the model the files were
+		let model = store.model().map_err(|e| e.to_string())?.ok_or("Nothing has been indexed, run openaiclient index".to_string())?;
+		let top = args.top.unwrap_or(vectorstore::DEFAULT_TOP).min(MAX_SEARCH_RESULTS);
+		let embeddings = block_on(self.api.embed(&model, &[args.query.clone()])).map_err(|e| e.to_string())?;
+		let query = embeddings.first().ok_or("No embedding for the query".to_string())?;
+		let found = store.search(query, &model, top).map_err(|e| e.to_string())?;
+		let results: Vec<serde_json::Value> = found.iter().map(|found| json!({
+			"path": found.chunk.path,
+			"start_line": found.chunk.start_line,
+			"end_line": found.chunk.end_line,
+			"score": found.score,
+			"text": found.chunk.text,
+		})).collect();
+		Ok(json!({ "results": results }).to_string())
+	}
+}
+
+impl Tool for SemanticSearchTool {
+	fn name(&self) -> &str {
+		"semantic_search"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "semantic_search",
+			"description": "find the parts of the files of the project most related to a question or topic (by meaning, not exact text), each with its file and lines",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"query": {"type": "string", "description": "what to look for, e.g. where are the chats encrypted"},
+					"top": {"type": "integer", "description": "number of results (default 5, at most 20)"},
+				},
+				"required": ["query"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: SemanticSearchArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.search(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
@@ -0,0 +1,147 @@
+// Read only queries on the SQLite databases named in the configuration:
+//
+// [sql_databases.music]
+// path = "/home/user/music.sqlite3"
+// statements = ["SELECT", "WITH", "PRAGMA table_info"]
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use rusqlite::{Connection, OpenFlags};
+use rusqlite::types::ValueRef;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::Tool;
+
+/// The statements allowed when a database does not list them
+pub const DEFAULT_STATEMENTS: &[&str] = &["SELECT", "WITH"];
+/// The most rows returned by a query
+pub const MAX_SQL_ROWS: usize = 200;
+
+/// A database the sql_query tool may read, `statements` are the beginnings
+/// (keywords, case insensitive) of the statements allowed
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SqlDatabaseConfig {
+	pub path: PathBuf,
+	pub statements: Option<Vec<String>>,
+}
+
+impl SqlDatabaseConfig {
+	/// Whether the statement starts with one of those allowed, the words
+	/// compared ignoring case and spacing
+	pub fn allows(&self, sql: &str) -> bool {
+		let words = |text: &str| text.split_whitespace().map(|word| word.to_ascii_uppercase()).collect::<Vec<String>>();
+		let statement = words(sql);
+		let allowed: Vec<String> = match self.statements.as_ref() {
+			Some(statements) => statements.clone(),
+			None => DEFAULT_STATEMENTS.iter().map(|statement| statement.to_string()).collect(),
+		};
+		allowed.iter().map(|allowed| words(allowed)).any(|allowed| !allowed.is_empty() && statement.starts_with(&allowed))
+	}
+}
+
+/// A column value as JSON, blobs as their length
+pub fn value_to_json(value: ValueRef) -> serde_json::Value {
+	match value {
+		ValueRef::Null => serde_json::Value::Null,
+		ValueRef::Integer(integer) => json!(integer),
+		ValueRef::Real(real) => json!(real),
+		ValueRef::Text(text) => json!(String::from_utf8_lossy(text)),
+		ValueRef::Blob(blob) => json!(format!("<{} bytes>", blob.len())),
+	}
+}
+
+/// Run one statement on the database opened read only, its columns and (at
+/// most `MAX_SQL_ROWS`) rows
+pub fn query(config: &SqlDatabaseConfig, sql: &str, params: &[serde_json::Value]) -> Result<serde_json::Value, String> {
+	let sql = sql.trim().trim_end_matches(';').trim();
+	if !config.allows(sql) {
+		return Err(format!("Only statements starting with {} are allowed", config.statements.as_ref()
+			.map(|statements| statements.join(", ")).unwrap_or(DEFAULT_STATEMENTS.join(", "))));
+	}
+	let conn = Connection::open_with_flags(&config.path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+		.map_err(|e| format!("{}: {}", config.path.display(), e))?;
+	// prepare only compiles the first statement, the rest would be ignored
+	let mut statement = conn.prepare(sql).map_err(|e| e.to_string())?;
+	if !statement.readonly() {
+		return Err("Only statements that do not change the database are allowed".to_string());
+	}
+	let columns: Vec<String> = statement.column_names().iter().map(|name| name.to_string()).collect();
+	let params: Vec<rusqlite::types::Value> = params.iter().map(|param| match param {
+		serde_json::Value::Null => rusqlite::types::Value::Null,
+		serde_json::Value::Bool(value) => rusqlite::types::Value::Integer(*value as i64),
+		serde_json::Value::Number(number) => match number.as_i64() {
+			Some(integer) => rusqlite::types::Value::Integer(integer),
+			None => rusqlite::types::Value::Real(number.as_f64().unwrap_or(0.0)),
+		},
+		serde_json::Value::String(text) => rusqlite::types::Value::Text(text.clone()),
+		other => rusqlite::types::Value::Text(other.to_string()),
+	}).collect();
+	let mut rows = statement.query(rusqlite::params_from_iter(params)).map_err(|e| e.to_string())?;
+	let mut result = Vec::new();
+	let mut truncated = false;
+	while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+		if result.len() == MAX_SQL_ROWS {
+			truncated = true;
+			break;
+		}
+		let values: Result<Vec<serde_json::Value>, rusqlite::Error> = (0..columns.len()).map(|index| row.get_ref(index).map(value_to_json)).collect();
+		result.push(values.map_err(|e| e.to_string())?);
+	}
+	Ok(json!({ "columns": columns, "rows": result, "truncated": truncated }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SqlQueryArgs {
+	pub database: String,
+	pub sql: String,
+	/// bound to the ?1, ?2... of the statement
+	#[serde(default)]
+	pub params: Vec<serde_json::Value>,
+}
+
+/// Query the databases of `sql_databases` in config.toml
+pub struct SqlQueryTool {
+	databases: HashMap<String, SqlDatabaseConfig>,
+}
+
+impl SqlQueryTool {
+	pub fn new(databases: HashMap<String, SqlDatabaseConfig>) -> Self {
+		SqlQueryTool{ databases: databases }
+	}
+}
+
+impl Tool for SqlQueryTool {
+	fn name(&self) -> &str {
+		"sql_query"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		let mut names: Vec<&String> = self.databases.keys().collect();
+		names.sort();
+		json!({
+			"name": "sql_query",
+			"description": "run a read only SQL statement (SQLite) on one of the databases and return the columns and rows, the tables are listed by SELECT name, sql FROM sqlite_schema",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"database": {"type": "string", "enum": names},
+					"sql": {"type": "string", "description": "one statement, e.g. a SELECT"},
+					"params": {"type": "array", "description": "values of the ?1, ?2... parameters of the statement"},
+				},
+				"required": ["database", "sql"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: SqlQueryArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		let config = self.databases.get(&args.database).ok_or(format!("Unknown database {}", args.database))?;
+		Ok(query(config, &args.sql, &args.params)?.to_string())
+	}
+
+	// opened read only, and the statement must not write
+	fn destructive(&self) -> bool {
+		false
+	}
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+use wasmtime::{Config, Engine, Linker, Module, Store};
+
+use crate::helpers;
+use super::{Tool, ToolRegistry};
+
+/// Instructions a single call may execute before it is stopped
+pub const FUEL_LIMIT: u64 = 1_000_000_000;
+
+/// A tool implemented by a WebAssembly module in the `tools.d/` directory
+/// (`tools.d/NAME.wasm` with the function definition in `tools.d/NAME.json`).
+///
+/// The module is instantiated without any imports so it has no access to
+/// the filesystem or network. It must export:
+///
+/// * `memory`
+/// * `alloc(len: i32) -> i32` returning space for the arguments JSON
+/// * `call(ptr: i32, len: i32) -> i64` returning the result JSON as
+///   `ptr << 32 | len`
+///
+/// A new instance is made for every call.
+pub struct WasmTool {
+	name: String,
+	engine: Engine,
+	module: Module,
+	schema: serde_json::Value,
+}
+
+impl WasmTool {
+	pub fn load(engine: &Engine, wasm_file: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+		let schema: serde_json::Value = helpers::read_from_json(wasm_file.with_extension("json"))?;
+		let module = Module::from_file(engine, wasm_file).map_err(|e| e.to_string())?;
+		let name = match schema.get("name").and_then(|n| n.as_str()) {
+			Some(name) => name.to_string(),
+			None => wasm_file.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+		};
+		Ok(WasmTool{ name: name, engine: engine.clone(), module: module, schema: schema })
+	}
+
+	fn run(&self, args: &str) -> wasmtime::Result<String> {
+		let mut store = Store::new(&self.engine, ());
+		store.set_fuel(FUEL_LIMIT)?;
+		let linker: Linker<()> = Linker::new(&self.engine);
+		let instance = linker.instantiate(&mut store, &self.module)?;
+		let memory = instance.get_memory(&mut store, "memory").ok_or(wasmtime::Error::msg("module does not export memory"))?;
+		let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+		let call = instance.get_typed_func::<(i32, i32), i64>(&mut store, "call")?;
+		let ptr = alloc.call(&mut store, args.len() as i32)?;
+		memory.write(&mut store, ptr as u32 as usize, args.as_bytes())?;
+		let packed = call.call(&mut store, (ptr, args.len() as i32))?;
+		let result_ptr = (packed >> 32) as u32 as usize;
+		let result_len = (packed & 0xffff_ffff) as u32 as usize;
+		let mut result = vec![0u8; result_len];
+		memory.read(&store, result_ptr, &mut result)?;
+		Ok(String::from_utf8(result)?)
+	}
+}
+
+impl Tool for WasmTool {
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		let mut schema = self.schema.clone();
+		schema["name"] = serde_json::Value::String(self.name.clone());
+		schema
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		self.run(args).map_err(|e| format!("{} failed: {}", self.name, e))
+	}
+}
+
+/// Register every `.wasm` module found in `dir`, modules that cannot be
+/// loaded are reported and skipped. The names of the registered tools are returned.
+pub fn register_wasm_plugins(registry: &mut ToolRegistry, dir: &Path) -> Vec<String> {
+	let mut names = Vec::new();
+	if !dir.is_dir() {
+		return names;
+	}
+	let mut config = Config::new();
+	config.consume_fuel(true);
+	let engine = match Engine::new(&config) {
+		Ok(engine) => engine,
+		Err(err) => {
+			eprintln!("Cannot start WebAssembly engine: {}", err);
+			return names;
+		},
+	};
+	let wasm_files = match helpers::list_files(dir, |path| if helpers::has_specific_extension(&path, "wasm") { Some(path) } else { None }, 0) {
+		Ok(wasm_files) => wasm_files,
+		Err(err) => {
+			eprintln!("Cannot list plugins in {}: {}", dir.display(), err);
+			return names;
+		},
+	};
+	for wasm_file in wasm_files.iter() {
+		match WasmTool::load(&engine, wasm_file) {
+			Ok(tool) => {
+				names.push(tool.name().to_string());
+				registry.register(Box::new(tool));
+			},
+			Err(err) => eprintln!("Plugin {} not loaded: {}", wasm_file.display(), err),
+		}
+	}
+	names
+}
@@ -0,0 +1,608 @@
+use std::collections::HashMap;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{block_on, Tool};
+
+/// Most bytes of a page read
+pub const MAX_FETCH_BYTES: usize = 2 * 1024 * 1024;
+/// Default number of characters of content returned by web_fetch
+pub const DEFAULT_FETCH_LENGTH: usize = 20_000;
+/// Default number of results returned by web_search
+pub const DEFAULT_SEARCH_RESULTS: usize = 5;
+/// Most results returned by web_search
+pub const MAX_SEARCH_RESULTS: usize = 20;
+/// Default most bytes of a response body returned by http_request
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 100 * 1024;
+
+/// Elements whose content is not part of the text of a page (scripts,
+/// navigation, forms...)
+const SKIPPED_ELEMENTS: &[&str] = &["script", "style", "noscript", "nav", "header", "footer", "aside", "form", "svg", "iframe", "template", "button"];
+
+/// The text with the HTML character references (`&amp;`, `&#39;`...) replaced
+pub fn decode_entities(text: &str) -> String {
+	let mut out = String::with_capacity(text.len());
+	let mut rest = text;
+	while let Some(start) = rest.find('&') {
+		out.push_str(&rest[..start]);
+		rest = &rest[start..];
+		let decoded = rest[1..].find(';').filter(|end| *end <= 10).and_then(|end| {
+			let entity = &rest[1..1 + end];
+			let c = match entity {
+				"amp" => Some('&'),
+				"lt" => Some('<'),
+				"gt" => Some('>'),
+				"quot" => Some('"'),
+				"apos" => Some('\''),
+				"nbsp" => Some(' '),
+				_ if entity.starts_with("#x") || entity.starts_with("#X") => u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+				_ if entity.starts_with('#') => entity[1..].parse::<u32>().ok().and_then(char::from_u32),
+				_ => None,
+			};
+			c.map(|c| (c, end + 2))
+		});
+		match decoded {
+			Some((c, length)) => {
+				out.push(c);
+				rest = &rest[length..];
+			},
+			None => {
+				out.push('&');
+				rest = &rest[1..];
+			},
+		}
+	}
+	out.push_str(rest);
+	out
+}
+
+/// The value of an attribute of a tag (the text between `<` and `>`)
+fn attribute(tag: &str, name: &str) -> Option<String> {
+	let lower = tag.to_ascii_lowercase();
+	let mut from = 0;
+	while let Some(found) = lower[from..].find(name) {
+		let start = from + found;
+		from = start + name.len();
+		if !lower[..start].ends_with(char::is_whitespace) {
+			continue;
+		}
+		let value_start = from + (lower[from..].len() - lower[from..].trim_start().len());
+		let value = match tag[value_start..].strip_prefix('=') {
+			Some(value) => value.trim_start(),
+			None => continue,
+		};
+		return match value.chars().next() {
+			Some(quote) if quote == '"' || quote == '\'' => value[1..].find(quote).map(|end| decode_entities(&value[1..1 + end])),
+			_ => Some(decode_entities(value.split(|c: char| c.is_whitespace() || c == '>').next().unwrap_or(""))),
+		};
+	}
+	None
+}
+
+/// The `<title>` of a page
+pub fn html_title(html: &str) -> Option<String> {
+	let lower = html.to_ascii_lowercase();
+	let start = lower.find("<title")?;
+	let start = start + lower[start..].find('>')? + 1;
+	let end = start + lower[start..].find("</title")?;
+	let title = decode_entities(&html[start..end]).split_whitespace().collect::<Vec<&str>>().join(" ");
+	if title.is_empty() { None } else { Some(title) }
+}
+
+/// The part of a page with its content: the `<main>` or `<article>`
+/// element when there is one, otherwise the whole page
+fn main_content(html: &str) -> &str {
+	let lower = html.to_ascii_lowercase();
+	for element in ["main", "article"] {
+		let open = format!("<{}", element);
+		let start = lower.match_indices(&open).map(|(index, _)| index)
+			.find(|index| lower[index + open.len()..].starts_with(|c: char| c == '>' || c.is_whitespace()));
+		if let (Some(start), Some(end)) = (start, lower.rfind(&format!("</{}>", element))) {
+			if end > start {
+				return &html[start..end];
+			}
+		}
+	}
+	html
+}
+
+/// End the text with a blank line, for a new block
+fn end_block(out: &mut String) {
+	while out.ends_with(' ') {
+		out.pop();
+	}
+	if !out.is_empty() && !out.ends_with("\n\n") {
+		out.push_str(if out.ends_with('\n') { "\n" } else { "\n\n" });
+	}
+}
+
+fn end_line(out: &mut String) {
+	while out.ends_with(' ') {
+		out.pop();
+	}
+	if !out.is_empty() && !out.ends_with('\n') {
+		out.push('\n');
+	}
+}
+
+/// The text of a page as markdown: headings, paragraphs, lists, links,
+/// emphasis, code and quotes are kept, scripts, styles, navigation and
+/// other boilerplate are left out
+pub fn html_to_markdown(html: &str) -> String {
+	let html = main_content(html);
+	let lower = html.to_ascii_lowercase();
+	let mut out = String::new();
+	let mut pre = 0;
+	let mut links: Vec<Option<String>> = Vec::new();
+	let mut index = 0;
+	while index < html.len() {
+		if html[index..].starts_with("<!--") {
+			index = html[index..].find("-->").map(|end| index + end + 3).unwrap_or(html.len());
+			continue;
+		}
+		if !html[index..].starts_with('<') {
+			let next = html[index..].find('<').map(|next| index + next).unwrap_or(html.len());
+			let text = decode_entities(&html[index..next]);
+			if pre > 0 {
+				out.push_str(&text);
+			} else {
+				for c in text.chars() {
+					if !c.is_whitespace() {
+						out.push(c);
+					} else if !out.is_empty() && !out.ends_with(char::is_whitespace) {
+						out.push(' ');
+					}
+				}
+			}
+			index = next;
+			continue;
+		}
+		let end = match html[index..].find('>') {
+			Some(end) => index + end,
+			None => break,
+		};
+		let tag = &html[index + 1..end];
+		index = end + 1;
+		let closing = tag.starts_with('/');
+		let name = tag.trim_start_matches('/').chars().take_while(|c| c.is_ascii_alphanumeric()).collect::<String>().to_ascii_lowercase();
+		if !closing && SKIPPED_ELEMENTS.contains(&name.as_str()) && !tag.ends_with('/') {
+			// the content is skipped up to the end of the element
+			index = match lower[index..].find(&format!("</{}", name)) {
+				Some(close) => html[index + close..].find('>').map(|end| index + close + end + 1).unwrap_or(html.len()),
+				None => html.len(),
+			};
+			continue;
+		}
+		match (name.as_str(), closing) {
+			("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+				end_block(&mut out);
+				out.push_str(&"#".repeat(name[1..].parse().unwrap_or(1)));
+				out.push(' ');
+			},
+			("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => end_block(&mut out),
+			("p" | "div" | "section" | "article" | "main" | "table" | "ul" | "ol" | "dl" | "figure", _) => end_block(&mut out),
+			("br", _) | ("tr", true) | ("dt" | "dd", false) => end_line(&mut out),
+			("li", false) => {
+				end_line(&mut out);
+				out.push_str("- ");
+			},
+			("td" | "th", false) => out.push_str("| "),
+			("td" | "th", true) => out.push(' '),
+			("pre", false) => {
+				end_block(&mut out);
+				out.push_str("```\n");
+				pre += 1;
+			},
+			("pre", true) if pre > 0 => {
+				end_line(&mut out);
+				out.push_str("```");
+				end_block(&mut out);
+				pre -= 1;
+			},
+			("code", _) if pre == 0 => out.push('`'),
+			("strong" | "b", _) => out.push_str("**"),
+			("em" | "i", _) => out.push('*'),
+			("a", false) => {
+				// links within the page and to scripts are only their text
+				let href = attribute(tag, "href").filter(|href| !href.starts_with('#') && !href.starts_with("javascript:"));
+				if href.is_some() {
+					out.push('[');
+				}
+				links.push(href);
+			},
+			("a", true) => {
+				if let Some(Some(href)) = links.pop() {
+					out.push_str(&format!("]({})", href));
+				}
+			},
+			("blockquote", false) => {
+				end_block(&mut out);
+				out.push_str("> ");
+			},
+			("blockquote", true) => end_block(&mut out),
+			("hr", _) => {
+				end_block(&mut out);
+				out.push_str("---");
+				end_block(&mut out);
+			},
+			_ => {},
+		}
+	}
+	// no more than one blank line between blocks
+	let mut markdown = String::new();
+	let mut blank = true;
+	for line in out.lines().map(|line| line.trim_end()) {
+		if line.is_empty() {
+			if !blank {
+				markdown.push('\n');
+			}
+			blank = true;
+		} else {
+			markdown.push_str(line);
+			markdown.push('\n');
+			blank = false;
+		}
+	}
+	markdown.trim_end().to_string()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebFetchArgs {
+	pub url: String,
+	/// most characters of content returned
+	pub max_length: Option<usize>,
+}
+
+/// Fetch a web page and return its text as markdown
+#[derive(Default)]
+pub struct WebFetchTool {
+	client: reqwest::Client,
+}
+
+impl WebFetchTool {
+	pub fn new() -> Self {
+		WebFetchTool{ client: reqwest::Client::new() }
+	}
+
+	/// The address the page was fetched from (after redirects), its
+	/// content type and up to `MAX_FETCH_BYTES` of its body
+	async fn get(&self, url: url::Url) -> Result<(String, String, String), Box<dyn std::error::Error>> {
+		let mut response = self.client.get(url).send().await?;
+		let status = response.status();
+		if !status.is_success() {
+			return Err(format!("{} from {}", status, response.url()).into());
+		}
+		let content_type = response.headers().get(reqwest::header::CONTENT_TYPE)
+			.and_then(|value| value.to_str().ok()).unwrap_or("").to_string();
+		let final_url = response.url().to_string();
+		let mut body = Vec::new();
+		while let Some(chunk) = response.chunk().await? {
+			body.extend_from_slice(&chunk);
+			if body.len() >= MAX_FETCH_BYTES {
+				body.truncate(MAX_FETCH_BYTES);
+				break;
+			}
+		}
+		Ok((final_url, content_type, String::from_utf8_lossy(&body).to_string()))
+	}
+
+	pub fn fetch(&self, args: WebFetchArgs) -> Result<String, String> {
+		let url = url::Url::parse(&args.url).map_err(|e| format!("{}: {}", args.url, e))?;
+		if url.scheme() != "http" && url.scheme() != "https" {
+			return Err(format!("{}: only http and https pages can be fetched", args.url));
+		}
+		let (final_url, content_type, body) = block_on(self.get(url))
+			.map_err(|e| e.to_string())?;
+		let html = content_type.contains("html") || (content_type.is_empty() && body.trim_start().starts_with('<'));
+		let (title, content) = if html { (html_title(&body), html_to_markdown(&body)) } else { (None, body) };
+		let max_length = args.max_length.unwrap_or(DEFAULT_FETCH_LENGTH);
+		let truncated = content.chars().count() > max_length;
+		let content: String = content.chars().take(max_length).collect();
+		Ok(json!({ "url": final_url, "title": title, "content": content, "truncated": truncated }).to_string())
+	}
+}
+
+impl Tool for WebFetchTool {
+	fn name(&self) -> &str {
+		"web_fetch"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "web_fetch",
+			"description": "fetch a web page (e.g. documentation) and return its title and its text as markdown, without the navigation, scripts and styles",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"url": {"type": "string", "description": "http or https address of the page"},
+					"max_length": {"type": "integer", "description": "most characters of content returned (default 20000)"},
+				},
+				"required": ["url"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: WebFetchArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.fetch(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
+
+/// The search engine of the web_search tool, in config.toml:
+///
+/// ```toml
+/// [web_search]
+/// backend = "searxng"
+/// url = "http://localhost:8888/"
+/// ```
+///
+/// `brave` and `bing` need an `api_key`, their `url` is optional.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebSearchConfig {
+	pub backend: SearchBackend,
+	/// the address of a SearXNG instance, or another for the APIs
+	pub url: Option<String>,
+	pub api_key: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchBackend {
+	Searxng,
+	Brave,
+	Bing,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SearchResult {
+	pub title: String,
+	pub url: String,
+	pub snippet: String,
+}
+
+/// The results in a response of the search engine
+pub fn parse_search_results(backend: SearchBackend, body: &str) -> Result<Vec<SearchResult>, String> {
+	let json: serde_json::Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+	// where the results are and the names of their fields
+	let (results, title, snippet) = match backend {
+		SearchBackend::Searxng => (json.pointer("/results"), "title", "content"),
+		SearchBackend::Brave => (json.pointer("/web/results"), "title", "description"),
+		SearchBackend::Bing => (json.pointer("/webPages/value"), "name", "snippet"),
+	};
+	let field = |result: &serde_json::Value, name: &str| result.get(name).and_then(|value| value.as_str()).unwrap_or("").to_string();
+	// no results may be left out of the response altogether
+	Ok(results.and_then(|results| results.as_array()).map(|results| results.iter().map(|result| SearchResult{
+		// Brave marks the words found with <strong>
+		title: html_to_markdown(&field(result, title)),
+		url: field(result, "url"),
+		snippet: html_to_markdown(&field(result, snippet)),
+	}).filter(|result| !result.url.is_empty()).collect()).unwrap_or_default())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebSearchArgs {
+	pub query: String,
+	pub max_results: Option<usize>,
+}
+
+/// Search the web with the engine in the configuration
+pub struct WebSearchTool {
+	config: WebSearchConfig,
+	client: reqwest::Client,
+}
+
+impl WebSearchTool {
+	pub fn new(config: WebSearchConfig) -> Self {
+		WebSearchTool{ config: config, client: reqwest::Client::new() }
+	}
+
+	fn request(&self, query: &str, count: usize) -> Result<reqwest::RequestBuilder, String> {
+		let api_key = || self.config.api_key.clone().ok_or(format!("web_search needs an api_key for {:?}", self.config.backend));
+		Ok(match self.config.backend {
+			SearchBackend::Searxng => {
+				let base = self.config.url.as_deref().ok_or("web_search needs the url of the SearXNG instance".to_string())?;
+				self.client.get(format!("{}/search", base.trim_end_matches('/'))).query(&[("q", query), ("format", "json")])
+			},
+			SearchBackend::Brave => {
+				let url = self.config.url.as_deref().unwrap_or("https://api.search.brave.com/res/v1/web/search");
+				self.client.get(url).query(&[("q", query), ("count", count.to_string().as_str())])
+					.header("Accept", "application/json").header("X-Subscription-Token", api_key()?)
+			},
+			SearchBackend::Bing => {
+				let url = self.config.url.as_deref().unwrap_or("https://api.bing.microsoft.com/v7.0/search");
+				self.client.get(url).query(&[("q", query), ("count", count.to_string().as_str())])
+					.header("Ocp-Apim-Subscription-Key", api_key()?)
+			},
+		})
+	}
+
+	pub fn search(&self, args: WebSearchArgs) -> Result<String, String> {
+		let count = args.max_results.unwrap_or(DEFAULT_SEARCH_RESULTS).min(MAX_SEARCH_RESULTS);
+		let request = self.request(&args.query, count)?;
+		let (status, body) = block_on(async {
+			let response = request.send().await?;
+			let status = response.status();
+			Ok::<_, reqwest::Error>((status, response.text().await?))
+		}).map_err(|e| e.to_string())?;
+		if !status.is_success() {
+			return Err(format!("{} from the search engine: {}", status, body));
+		}
+		let mut results = parse_search_results(self.config.backend, &body)?;
+		results.truncate(count);
+		Ok(json!({ "query": args.query, "results": results }).to_string())
+	}
+}
+
+impl Tool for WebSearchTool {
+	fn name(&self) -> &str {
+		"web_search"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "web_search",
+			"description": "search the web, returns the title, url and a snippet of each result (read a page with web_fetch)",
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"query": {"type": "string", "description": "the words searched for"},
+					"max_results": {"type": "integer", "description": "number of results (default 5, at most 20)"},
+				},
+				"required": ["query"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: WebSearchArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.search(args)
+	}
+
+	fn destructive(&self) -> bool {
+		false
+	}
+}
+
+/// The addresses the http_request tool may send requests to, in config.toml:
+///
+/// ```toml
+/// [http_request]
+/// allowed = ["http://localhost:8080/api/", "https://tickets.example.com/rest/"]
+/// max_response_bytes = 65536
+/// headers = { Authorization = "Bearer ..." }
+/// ```
+///
+/// A url is allowed when it has the scheme, host and port of an allowed one
+/// and its path starts with that one's. The headers are added to every
+/// request, so the model never sees the credentials.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HttpRequestConfig {
+	pub allowed: Vec<String>,
+	pub max_response_bytes: Option<usize>,
+	#[serde(default)]
+	pub headers: HashMap<String, String>,
+}
+
+impl HttpRequestConfig {
+	pub fn allows(&self, url: &url::Url) -> bool {
+		self.allowed.iter().filter_map(|allowed| url::Url::parse(allowed).ok()).any(|allowed| {
+			allowed.scheme() == url.scheme()
+				&& allowed.host_str() == url.host_str()
+				&& allowed.port_or_known_default() == url.port_or_known_default()
+				&& url.path().starts_with(allowed.path())
+		})
+	}
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HttpRequestArgs {
+	/// GET when not given
+	pub method: Option<String>,
+	pub url: String,
+	#[serde(default)]
+	pub headers: HashMap<String, String>,
+	pub body: Option<String>,
+}
+
+/// Send an HTTP request to one of the addresses allowed in the configuration
+pub struct HttpRequestTool {
+	config: HttpRequestConfig,
+	client: reqwest::Client,
+}
+
+impl HttpRequestTool {
+	pub fn new(config: HttpRequestConfig) -> Self {
+		// a redirect could leave the allowed addresses
+		let client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap_or_default();
+		HttpRequestTool{ config: config, client: client }
+	}
+
+	/// The method and url of the request, an error when it is not allowed
+	fn check(&self, args: &HttpRequestArgs) -> Result<(reqwest::Method, url::Url), String> {
+		let method = args.method.as_deref().unwrap_or("GET").to_ascii_uppercase();
+		let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|_| format!("Invalid method {}", method))?;
+		let url = url::Url::parse(&args.url).map_err(|e| format!("{}: {}", args.url, e))?;
+		if !self.config.allows(&url) {
+			return Err(format!("{} is not one of the addresses allowed for http_request", url));
+		}
+		Ok((method, url))
+	}
+
+	pub fn request(&self, args: HttpRequestArgs) -> Result<String, String> {
+		let (method, url) = self.check(&args)?;
+		let max_bytes = self.config.max_response_bytes.unwrap_or(DEFAULT_MAX_RESPONSE_BYTES);
+		let mut builder = self.client.request(method, url);
+		// the configured headers replace those of the model
+		for (name, value) in args.headers.iter().filter(|(name, _)| !self.config.headers.keys().any(|configured| configured.eq_ignore_ascii_case(name))) {
+			builder = builder.header(name, value);
+		}
+		for (name, value) in self.config.headers.iter() {
+			builder = builder.header(name, value);
+		}
+		if let Some(body) = args.body {
+			builder = builder.body(body);
+		}
+		let (status, headers, body, truncated) = block_on(async {
+			let mut response = builder.send().await?;
+			let status = response.status().as_u16();
+			let headers: HashMap<String, String> = response.headers().iter()
+				.filter_map(|(name, value)| value.to_str().ok().map(|value| (name.to_string(), value.to_string())))
+				.collect();
+			let mut body = Vec::new();
+			let mut truncated = false;
+			while let Some(chunk) = response.chunk().await? {
+				body.extend_from_slice(&chunk);
+				if body.len() > max_bytes {
+					body.truncate(max_bytes);
+					truncated = true;
+					break;
+				}
+			}
+			Ok::<_, reqwest::Error>((status, headers, body, truncated))
+		}).map_err(|e| e.to_string())?;
+		Ok(json!({
+			"status": status,
+			"headers": headers,
+			"body": String::from_utf8_lossy(&body),
+			"truncated": truncated,
+		}).to_string())
+	}
+}
+
+impl Tool for HttpRequestTool {
+	fn name(&self) -> &str {
+		"http_request"
+	}
+
+	fn json_schema(&self) -> serde_json::Value {
+		json!({
+			"name": "http_request",
+			"description": format!("send an HTTP request to a REST API and return the status, headers and body of the response, only these addresses are allowed: {}", self.config.allowed.join(", ")),
+			"parameters": {
+				"type": "object",
+				"properties": {
+					"method": {"type": "string", "description": "GET (default), POST, PUT, PATCH, DELETE..."},
+					"url": {"type": "string"},
+					"headers": {"type": "object", "additionalProperties": {"type": "string"}},
+					"body": {"type": "string", "description": "the body of the request, e.g. JSON"},
+				},
+				"required": ["url"],
+			},
+		})
+	}
+
+	fn call(&self, args: &str) -> Result<String, String> {
+		let args: HttpRequestArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		self.request(args)
+	}
+
+	fn preview(&self, args: &str) -> Result<String, String> {
+		let args: HttpRequestArgs = serde_json::from_str(args).map_err(|e| e.to_string())?;
+		let (method, url) = self.check(&args)?;
+		Ok(format!("Would send {} {}{}", method, url, args.body.map(|body| format!(" with:\n{}", body)).unwrap_or_default()))
+	}
+}
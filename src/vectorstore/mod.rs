@@ -0,0 +1,164 @@
+// The embeddings of the text of files kept in SQLite, searched by cosine
+// similarity. The store is small enough (a project or some notes) for the
+// vectors to be compared one by one.
+
+use std::path::Path;
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// The lines in a chunk of a file
+pub const CHUNK_LINES: usize = 40;
+/// The lines a chunk shares with the one before it
+pub const CHUNK_OVERLAP: usize = 10;
+/// The default number of chunks found by a query
+pub const DEFAULT_TOP: usize = 5;
+
+/// The store of a project, in `.openaiclient` under its root
+pub fn default_path(root: Option<&Path>) -> std::path::PathBuf {
+	root.unwrap_or(Path::new(".")).join(".openaiclient").join("vectors.sqlite3")
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS files (
+	path TEXT PRIMARY KEY,
+	modified INTEGER NOT NULL,
+	model TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS chunks (
+	id INTEGER PRIMARY KEY,
+	path TEXT NOT NULL REFERENCES files(path) ON DELETE CASCADE,
+	start_line INTEGER NOT NULL,
+	end_line INTEGER NOT NULL,
+	text TEXT NOT NULL,
+	embedding BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS chunks_path ON chunks(path);
+";
+
+/// Some lines of a file (numbered from 1, inclusive)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+	pub path: String,
+	pub start_line: usize,
+	pub end_line: usize,
+	pub text: String,
+}
+
+/// A chunk found by a query and its similarity to it (1 is the same direction)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Found {
+	pub chunk: Chunk,
+	pub score: f32,
+}
+
+/// The text of a file in chunks of `CHUNK_LINES` overlapping by
+/// `CHUNK_OVERLAP`, chunks of blank lines are left out
+pub fn chunk_text(path: &str, text: &str) -> Vec<Chunk> {
+	let lines: Vec<&str> = text.lines().collect();
+	let mut chunks = Vec::new();
+	let mut start = 0;
+	while start < lines.len() {
+		let end = (start + CHUNK_LINES).min(lines.len());
+		let chunk = lines[start..end].join("\n");
+		if !chunk.trim().is_empty() {
+			chunks.push(Chunk{ path: path.to_string(), start_line: start + 1, end_line: end, text: chunk });
+		}
+		if end == lines.len() {
+			break;
+		}
+		start = end - CHUNK_OVERLAP;
+	}
+	chunks
+}
+
+/// 1 for vectors in the same direction, 0 for unrelated ones (or vectors of
+/// different lengths)
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+	if a.len() != b.len() {
+		return 0.0;
+	}
+	let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+	let norms = a.iter().map(|a| a * a).sum::<f32>().sqrt() * b.iter().map(|b| b * b).sum::<f32>().sqrt();
+	if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+	vector.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+	blob.chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect()
+}
+
+pub struct VectorStore {
+	conn: Connection,
+}
+
+impl VectorStore {
+	/// Open the store, creating the file and its tables when needed
+	pub fn open(path: &Path) -> Result<Self, rusqlite::Error> {
+		let conn = Connection::open(path)?;
+		conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+		conn.execute_batch(SCHEMA)?;
+		Ok(VectorStore{ conn: conn })
+	}
+
+	/// Whether the file was indexed with `model` since it was last modified
+	pub fn is_current(&self, path: &str, modified: u64, model: &str) -> Result<bool, rusqlite::Error> {
+		let indexed: Option<(i64, String)> = self.conn.query_row("SELECT modified, model FROM files WHERE path = ?1", params![path],
+			|row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+		Ok(indexed.map(|(indexed, indexed_model)| indexed as u64 >= modified && indexed_model == model).unwrap_or(false))
+	}
+
+	/// Replace the chunks of a file, `embeddings` are those of `chunks` in order
+	pub fn replace_file(&mut self, path: &str, modified: u64, model: &str, chunks: &[Chunk], embeddings: &[Vec<f32>]) -> Result<(), rusqlite::Error> {
+		let transaction = self.conn.transaction()?;
+		transaction.execute("DELETE FROM files WHERE path = ?1", params![path])?;
+		transaction.execute("INSERT INTO files (path, modified, model) VALUES (?1, ?2, ?3)", params![path, modified as i64, model])?;
+		{
+			let mut insert = transaction.prepare("INSERT INTO chunks (path, start_line, end_line, text, embedding) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+			for (chunk, embedding) in chunks.iter().zip(embeddings) {
+				insert.execute(params![path, chunk.start_line as i64, chunk.end_line as i64, chunk.text, to_blob(embedding)])?;
+			}
+		}
+		transaction.commit()
+	}
+
+	/// Forget the files indexed that have been deleted since, the paths are
+	/// absolute so this does not depend on the current directory
+	pub fn remove_missing(&self) -> Result<usize, rusqlite::Error> {
+		let mut removed = 0;
+		for path in self.files()? {
+			if !Path::new(&path).exists() {
+				removed += self.conn.execute("DELETE FROM files WHERE path = ?1", params![path])?;
+			}
+		}
+		Ok(removed)
+	}
+
+	/// The model most of the files were indexed with, `None` when the store is empty
+	pub fn model(&self) -> Result<Option<String>, rusqlite::Error> {
+		self.conn.query_row("SELECT model FROM files GROUP BY model ORDER BY COUNT(*) DESC LIMIT 1", [], |row| row.get(0)).optional()
+	}
+
+	/// The paths of the files indexed
+	pub fn files(&self) -> Result<Vec<String>, rusqlite::Error> {
+		let mut statement = self.conn.prepare("SELECT path FROM files ORDER BY path")?;
+		let paths: Result<Vec<String>, rusqlite::Error> = statement.query_map([], |row| row.get(0))?.collect();
+		paths
+	}
+
+	/// The `top` chunks most like the query, of the files indexed with `model`
+	pub fn search(&self, query: &[f32], model: &str, top: usize) -> Result<Vec<Found>, rusqlite::Error> {
+		let mut statement = self.conn.prepare(
+			"SELECT chunks.path, start_line, end_line, text, embedding FROM chunks JOIN files ON files.path = chunks.path WHERE files.model = ?1")?;
+		let rows = statement.query_map(params![model], |row| {
+			let chunk = Chunk{ path: row.get(0)?, start_line: row.get::<_, i64>(1)? as usize, end_line: row.get::<_, i64>(2)? as usize, text: row.get(3)? };
+			let embedding: Vec<u8> = row.get(4)?;
+			Ok(Found{ score: cosine_similarity(query, &from_blob(&embedding)), chunk: chunk })
+		})?;
+		let mut found = rows.collect::<Result<Vec<Found>, rusqlite::Error>>()?;
+		found.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+		found.truncate(top);
+		Ok(found)
+	}
+}